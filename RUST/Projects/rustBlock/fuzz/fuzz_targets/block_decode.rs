@@ -0,0 +1,13 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rustychain;
+
+use rustychain::blockchain::{Block, Decodable, Encodable};
+
+// Anything the wire decoder accepts must encode back to the same bytes.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(block) = Block::from_bytes(data) {
+        assert_eq!(block.to_bytes(), data);
+    }
+});
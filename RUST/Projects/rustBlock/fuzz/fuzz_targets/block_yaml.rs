@@ -0,0 +1,16 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rustychain;
+extern crate serde_yaml;
+
+use std::str;
+
+use rustychain::blockchain::Block;
+
+// A block document from a chain file must parse or fail, never panic.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(document) = str::from_utf8(data) {
+        let _ = serde_yaml::from_str::<Block>(document);
+    }
+});
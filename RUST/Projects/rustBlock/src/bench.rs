@@ -0,0 +1,97 @@
+extern crate rayon;
+
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustychain::blockchain::{Block, BlockChain, InputVerifier, OutPoint, Transaction, TransactionOutput, Validator};
+use rustychain::util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
+
+
+// Hash rounds per input, roughly the cost of a signature check.
+const VERIFY_ROUNDS: usize = 200;
+
+// That of the default chain parameters the chains are validated with.
+const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+// Stands in for script and signature checks, which outputs do not have yet,
+// by hashing the input repeatedly.
+struct HashingVerifier;
+
+impl InputVerifier for HashingVerifier {
+    fn verify(&self, tx: &Transaction, input: usize, spent: &TransactionOutput) -> Result<(), String> {
+        let mut digest = *tx.get_hash(ALGORITHM).as_bytes();
+        for _ in 0..VERIFY_ROUNDS {
+            let mut hash = Hash256::new(ALGORITHM);
+            hash.write_all(&digest).unwrap();
+            hash.write_all(&(input as u32).to_le_bytes()).unwrap();
+            hash.write_all(spent.get_script()).unwrap();
+            hash.finalize(&mut digest);
+        }
+        if digest == [0u8; HASH256_BYTES] {
+            return Err("unlucky digest".to_string());
+        }
+        Ok(())
+    }
+}
+
+// A coinbase block with `txs` outputs followed by `blocks` blocks of `txs`
+// transactions, each spending an output of the block before.
+fn synthetic_chain(blocks: usize, txs: usize) -> Vec<Block> {
+    let mut coinbase = Transaction::new();
+    for i in 0..txs {
+        coinbase.add_output(1000, &(i as u32).to_le_bytes());
+    }
+    let mut genesis = Block::new();
+    genesis.add_transaction(coinbase.clone());
+    genesis.update_merkle_root(ALGORITHM);
+
+    let mut outputs: Vec<OutPoint> = (0..txs).map(|i| OutPoint::new(coinbase.get_hash(ALGORITHM), i as u32)).collect();
+    let mut chain = vec![genesis];
+    for height in 1..=blocks {
+        let mut block = Block::new();
+        block.set_timestamp(height as u64);
+        block.set_previous(chain.last().unwrap().get_hash(ALGORITHM));
+        for out in outputs.iter_mut() {
+            let mut tx = Transaction::new();
+            tx.set_timestamp(height as u64);
+            tx.add_input(*out);
+            tx.add_output(1000 - height as u64, &out.get_index().to_le_bytes());
+            *out = OutPoint::new(tx.get_hash(ALGORITHM), 0);
+            block.add_transaction(tx);
+        }
+        block.update_merkle_root(ALGORITHM);
+        chain.push(block);
+    }
+    chain
+}
+
+fn validate(blocks: &[Block], validator: Validator) -> (Duration, BlockChain) {
+    let mut chain = BlockChain::new();
+    chain.set_validator(validator);
+
+    let start = Instant::now();
+    chain.reorganize(blocks.to_vec()).unwrap();
+    (start.elapsed(), chain)
+}
+
+// Validates the same synthetic chain serially and in parallel and prints
+// both timings.
+pub fn run(blocks: usize, txs: usize) {
+    println!("bench: {} blocks of {} transactions, {} hash rounds per input", blocks, txs, VERIFY_ROUNDS);
+    let chain = synthetic_chain(blocks, txs);
+
+    let verifier: Arc<dyn InputVerifier> = Arc::new(HashingVerifier);
+    let (serial, serial_chain) = validate(&chain, Validator::serial(verifier.clone()));
+    let (parallel, parallel_chain) = validate(&chain, Validator::new(verifier));
+
+    // The pipeline must not change the outcome.
+    assert_eq!(serial_chain.get_best_hash(), parallel_chain.get_best_hash());
+    assert_eq!(serial_chain.get_utxos().len(), parallel_chain.get_utxos().len());
+
+    let inputs = (blocks * txs) as f64;
+    println!("serial:   {:8.1} ms, {:9.0} inputs/s", serial.as_secs_f64() * 1000.0, inputs / serial.as_secs_f64());
+    println!("parallel: {:8.1} ms, {:9.0} inputs/s ({:.2}x on {} threads)",
+             parallel.as_secs_f64() * 1000.0, inputs / parallel.as_secs_f64(),
+             serial.as_secs_f64() / parallel.as_secs_f64(), rayon::current_num_threads());
+}
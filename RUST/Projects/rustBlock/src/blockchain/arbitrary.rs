@@ -0,0 +1,122 @@
+// Proptest strategies shared by the tests of the blockchain modules.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use blockchain::{AssetId, Block, BlockHash, OutPoint, Transaction, TxId, SEQUENCE_FINAL, STATE_ROOT_VERSION};
+use util::hash::HashAlgorithm;
+
+
+pub const COINBASE_AMOUNT: u64 = 5000;
+
+// That of the default chain parameters, which the blocks are hashed with.
+pub const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+fn script() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..8)
+}
+
+// Structurally arbitrary: inputs need not exist and amounts need not add up.
+pub fn transaction() -> impl Strategy<Value = Transaction> {
+    let inputs = vec((any::<[u8; 32]>(), any::<u32>(), prop_oneof![Just(SEQUENCE_FINAL), any::<u32>()]), 0..3);
+    let outputs = vec((any::<u64>(), script(), any::<Option<[u8; 32]>>()), 0..3);
+    let writes = vec((vec(any::<u8>(), 1..8), script()), 0..2);
+    let issuance = proptest::option::of((any::<u64>(), "[a-z]{0,8}"));
+    (any::<u64>(), inputs, outputs, any::<Option<u64>>(), writes, issuance)
+        .prop_map(|(timestamp, inputs, outputs, lock_time, writes, issuance)| {
+            let mut tx = Transaction::new();
+            tx.set_timestamp(timestamp);
+            for (hash, index, sequence) in inputs {
+                tx.add_input_with_sequence(OutPoint::new(TxId::from_bytes(hash), index), sequence);
+            }
+            for (amount, script, asset) in outputs {
+                match asset {
+                    Some(asset) => tx.add_asset_output(AssetId::from_bytes(asset), amount, &script),
+                    None => tx.add_output(amount, &script),
+                }
+            }
+            if let Some(lock_time) = lock_time {
+                tx.set_lock_time(lock_time);
+            }
+            for (key, value) in writes {
+                tx.add_write(&key, &value);
+            }
+            if let Some((amount, name)) = issuance {
+                tx.set_issuance(amount, &name);
+            }
+            tx
+        })
+}
+
+// Arbitrary header fields and transactions, with a matching Merkle root.
+// Blocks that commit to a state root get an arbitrary one.
+pub fn block() -> impl Strategy<Value = Block> {
+    (any::<u64>(), any::<[u8; 32]>(), any::<Option<[u8; 32]>>(), vec(transaction(), 0..4))
+        .prop_map(|(timestamp, previous, state_root, transactions)| {
+            let mut block = Block::new();
+            block.set_timestamp(timestamp);
+            block.set_previous(BlockHash::from_bytes(previous));
+            if let Some(state_root) = state_root {
+                block.set_version(STATE_ROOT_VERSION);
+                block.set_state_root(state_root);
+            }
+            for tx in transactions {
+                block.add_transaction(tx);
+            }
+            block.update_merkle_root(ALGORITHM);
+            block
+        })
+}
+
+// A chain every block of which `BlockChain` must accept. Block `h` has a
+// coinbase and spends, for each `(pick, split)` in its plan, the unspent
+// output at `pick` into `split % 3 + 1` outputs, leaving a fee of 1.
+pub fn valid_chain(max_blocks: usize) -> impl Strategy<Value = Vec<Block>> {
+    vec(vec((any::<usize>(), any::<u8>()), 0..4), 1..max_blocks).prop_map(build_chain)
+}
+
+fn build_chain(plan: Vec<Vec<(usize, u8)>>) -> Vec<Block> {
+    let mut unspent: Vec<(OutPoint, u64)> = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    for (height, spends) in plan.into_iter().enumerate() {
+        let height = height as u64;
+        let mut block = Block::new();
+        block.set_timestamp(height);
+        if let Some(previous) = blocks.last() {
+            block.set_previous(previous.get_hash(ALGORITHM));
+        }
+
+        let mut coinbase = Transaction::new();
+        coinbase.set_timestamp(height);
+        coinbase.add_output(COINBASE_AMOUNT, &height.to_le_bytes());
+        let mut created = vec![(OutPoint::new(coinbase.get_hash(ALGORITHM), 0), COINBASE_AMOUNT)];
+        block.add_transaction(coinbase);
+
+        for (pick, split) in spends {
+            if unspent.is_empty() {
+                break;
+            }
+            let (out, amount) = unspent.swap_remove(pick % unspent.len());
+            if amount < 2 {
+                continue;
+            }
+            let outputs = (split % 3 + 1) as u64;
+            let share = (amount - 1) / outputs;
+
+            let mut tx = Transaction::new();
+            tx.set_timestamp(height);
+            tx.add_input(out);
+            for i in 0..outputs {
+                tx.add_output(share, &[i as u8]);
+            }
+            let hash = tx.get_hash(ALGORITHM);
+            created.extend((0..outputs).map(|i| (OutPoint::new(hash, i as u32), share)));
+            block.add_transaction(tx);
+        }
+
+        unspent.extend(created);
+        block.update_merkle_root(ALGORITHM);
+        blocks.push(block);
+    }
+    blocks
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::fmt;
+
+use blockchain::{AssetId, Transaction, TransactionOutput};
+use util::hash::HashAlgorithm;
+
+
+// Amounts per asset. The native coin has the all zero ID, so it sorts first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Balances {
+    amounts: BTreeMap<AssetId, u64>,
+}
+
+impl Balances {
+    pub fn new() -> Balances {
+        Balances::default()
+    }
+
+    // Fails, leaving the balances as they were, if the sum overflows.
+    pub fn add(&mut self, asset: AssetId, amount: u64) -> Result<(), String> {
+        let sum = self.amounts.entry(asset).or_insert(0);
+        match sum.checked_add(amount) {
+            Some(total) => {
+                *sum = total;
+                Ok(())
+            },
+            None if asset.is_zero() => Err("native amount overflows".to_string()),
+            None => Err(format!("amount of asset {} overflows", asset)),
+        }
+    }
+
+    pub fn add_output(&mut self, output: &TransactionOutput) -> Result<(), String> {
+        self.add(output.get_asset(), output.get_amount())
+    }
+
+    // For totals that are only reported, such as those of the UTXO set,
+    // where stopping at `u64::MAX` is better than failing.
+    pub fn add_saturating(&mut self, asset: AssetId, amount: u64) {
+        let sum = self.amounts.entry(asset).or_insert(0);
+        *sum = sum.saturating_add(amount);
+    }
+
+    pub fn get(&self, asset: &AssetId) -> u64 {
+        self.amounts.get(asset).cloned().unwrap_or(0)
+    }
+
+    pub fn get_native(&self) -> u64 {
+        self.get(&AssetId::default())
+    }
+
+    pub fn len(&self) -> usize {
+        self.amounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.amounts.is_empty()
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, AssetId, u64> {
+        self.amounts.iter()
+    }
+}
+
+impl fmt::Display for Balances {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "  native: 0");
+        }
+        for (asset, amount) in &self.amounts {
+            if asset.is_zero() {
+                writeln!(f, "  native: {}", amount)?;
+            } else {
+                writeln!(f, "  {}: {}", asset, amount)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Checks that `tx`, spending outputs worth `spent`, neither creates nor
+// destroys an issued asset, apart from what it issues, and pays out no more
+// of the native coin than it spends. Returns the fee, the native amount
+// left over.
+pub fn check_conservation(algorithm: HashAlgorithm, tx: &Transaction, spent: &Balances) -> Result<u64, String> {
+    let mut available = spent.clone();
+    if let (Some(issuance), Some(asset)) = (tx.get_issuance(), tx.get_issued_asset(algorithm)) {
+        available.add(asset, issuance.get_amount())?;
+    }
+    let mut paid = Balances::new();
+    for output in tx.get_outputs() {
+        paid.add_output(output).map_err(|e| format!("outputs: {}", e))?;
+    }
+
+    let (native_in, native_out) = (available.get_native(), paid.get_native());
+    if native_out > native_in {
+        return Err(format!("outputs {} exceed inputs {}", native_out, native_in));
+    }
+    for asset in available.iter().chain(paid.iter()).map(|(asset, _)| asset) {
+        let (input, output) = (available.get(asset), paid.get(asset));
+        if !asset.is_zero() && input != output {
+            return Err(format!("asset {}: outputs {} differ from inputs {}", asset, output, input));
+        }
+    }
+    Ok(native_in - native_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{OutPoint, TxId};
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    #[test]
+    fn issued_assets_are_conserved() {
+        let mut issue = Transaction::new();
+        issue.add_input(OutPoint::new(TxId::default(), 0));
+        issue.set_issuance(1000, "gold");
+        let gold = issue.get_issued_asset(ALGORITHM).unwrap();
+        issue.add_asset_output(gold, 600, b"alice");
+        issue.add_asset_output(gold, 400, b"bob");
+        issue.add_output(45, b"alice");
+
+        let mut spent = Balances::new();
+        spent.add(AssetId::default(), 50).unwrap();
+        assert_eq!(check_conservation(ALGORITHM, &issue, &spent), Ok(5));
+
+        // Spending more gold than was issued, or burning some, is rejected.
+        issue.add_asset_output(gold, 1, b"carol");
+        assert!(check_conservation(ALGORITHM, &issue, &spent).unwrap_err().contains("differ from inputs"));
+        let mut burn = Transaction::new();
+        burn.add_input(OutPoint::new(TxId::default(), 1));
+        burn.add_asset_output(gold, 5, b"alice");
+        spent.add(gold, 6).unwrap();
+        assert!(check_conservation(ALGORITHM, &burn, &spent).is_err());
+
+        assert_eq!(spent.to_string(), format!("  native: 50\n  {}: 6\n", gold));
+    }
+
+    #[test]
+    fn overflowing_sums_are_rejected() {
+        let gold = AssetId::from_bytes([7; 32]);
+        let mut spent = Balances::new();
+        spent.add(gold, u64::MAX).unwrap();
+        assert!(spent.add(gold, 1).is_err());
+        assert_eq!(spent.get(&gold), u64::MAX);
+
+        // Two outputs of everything spent would sum to `u64::MAX` if the sum
+        // saturated.
+        let mut tx = Transaction::new();
+        tx.add_input(OutPoint::new(TxId::default(), 0));
+        tx.add_asset_output(gold, u64::MAX, b"alice");
+        tx.add_asset_output(gold, u64::MAX, b"bob");
+        assert!(check_conservation(ALGORITHM, &tx, &spent).unwrap_err().contains("overflows"));
+    }
+}
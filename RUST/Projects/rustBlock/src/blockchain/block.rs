@@ -2,23 +2,123 @@ extern crate byteorder;
 extern crate serde;
 
 use std::fmt;
-use std::io::Write;
+use std::io;
+use std::io::{Read, Write};
 
-use self::byteorder::{LittleEndian, WriteBytesExt};
-//use self::serde::ser::{Serialize, Serializer};
-use self::serde::de::{Visitor, Deserialize, Deserializer, MapAccess, SeqAccess};
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use self::serde::ser::{Serialize, SerializeStruct, Serializer};
+use self::serde::de::{Visitor, Deserialize, Deserializer, MapAccess};
 
-use blockchain::Transaction;
-use util::hash::{Hash256, HASH256_BYTES};
+use blockchain::{BlockHash, Transaction, TxId};
+use blockchain::encode::{Decodable, Encodable, read_vec, write_vec};
+use blockchain::merkle::{merkle_root, MerkleBranch};
+use util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
 use util::hex::{FromHex, ToHex};
 
 
+// Blocks from this version on commit to the chain state after them in
+// `state_root`; earlier versions hash and encode as before and may not
+// change the state.
+pub const STATE_ROOT_VERSION: u32 = 2;
+
+// The part of a block that is hashed; all a light client keeps.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    version: u32,
+    timestamp: u64,
+    previous: BlockHash,
+    merkle_root: [u8; HASH256_BYTES],
+    state_root: [u8; HASH256_BYTES],
+}
+
+impl BlockHeader {
+    pub fn new(version: u32, timestamp: u64, previous: BlockHash, merkle_root: [u8; HASH256_BYTES],
+               state_root: [u8; HASH256_BYTES]) -> BlockHeader {
+        BlockHeader {
+            version,
+            timestamp,
+            previous,
+            merkle_root,
+            state_root,
+        }
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn get_previous(&self) -> BlockHash {
+        self.previous
+    }
+
+    pub fn get_merkle_root(&self) -> &[u8] {
+        &self.merkle_root
+    }
+
+    // All zero before `STATE_ROOT_VERSION`.
+    pub fn get_state_root(&self) -> &[u8] {
+        &self.state_root
+    }
+
+    pub fn get_hash(&self, algorithm: HashAlgorithm) -> BlockHash {
+        let mut hash = Hash256::new(algorithm);
+
+        hash.write_u32::<LittleEndian>(self.version).unwrap();
+        hash.write_u64::<LittleEndian>(self.timestamp).unwrap();
+        self.previous.encode(&mut hash).unwrap();
+        hash.write_all(&self.merkle_root).unwrap();
+        if self.version >= STATE_ROOT_VERSION {
+            hash.write_all(&self.state_root).unwrap();
+        }
+
+        let mut buf = [0u8; HASH256_BYTES];
+        hash.finalize(&mut buf);
+        BlockHash::from_bytes(buf)
+    }
+}
+
+impl Encodable for BlockHeader {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u32::<LittleEndian>(self.version)?;
+        w.write_u64::<LittleEndian>(self.timestamp)?;
+        self.previous.encode(w)?;
+        w.write_all(&self.merkle_root)?;
+        if self.version >= STATE_ROOT_VERSION {
+            w.write_all(&self.state_root)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decodable for BlockHeader {
+    fn decode<R: Read>(r: &mut R) -> io::Result<BlockHeader> {
+        let mut header = BlockHeader {
+            version: r.read_u32::<LittleEndian>()?,
+            timestamp: r.read_u64::<LittleEndian>()?,
+            previous: BlockHash::decode(r)?,
+            merkle_root: [0u8; HASH256_BYTES],
+            state_root: [0u8; HASH256_BYTES],
+        };
+        r.read_exact(&mut header.merkle_root)?;
+        if header.version >= STATE_ROOT_VERSION {
+            r.read_exact(&mut header.state_root)?;
+        }
+        Ok(header)
+    }
+}
+
 //#[derive(Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Block {
     version: u32,
     timestamp: u64,
-    previous: [u8; HASH256_BYTES],
+    previous: BlockHash,
     merkle_root: [u8; HASH256_BYTES],
+    state_root: [u8; HASH256_BYTES],
     transactions: Vec<Transaction>,
 }
 
@@ -27,55 +127,153 @@ impl Block {
         Block {
             version: 1,
             timestamp: 0,
-            previous: [0u8; HASH256_BYTES],
+            previous: BlockHash::default(),
             merkle_root: [0u8; HASH256_BYTES],
+            state_root: [0u8; HASH256_BYTES],
             transactions: Vec::new(),
         }
     }
 
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+
     pub fn set_timestamp(&mut self, ts: u64) {
         self.timestamp = ts;
     }
 
-    pub fn set_previous(&mut self, p: &[u8; HASH256_BYTES]) {
-        self.previous.copy_from_slice(p);
+    pub fn set_previous(&mut self, p: BlockHash) {
+        self.previous = p;
+    }
+
+    pub fn get_previous(&self) -> BlockHash {
+        self.previous
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn get_merkle_root(&self) -> &[u8] {
+        &self.merkle_root
+    }
+
+    // Only hashed from `STATE_ROOT_VERSION` on; `BlockChain::compute_state_root`
+    // gives the root a block must commit to.
+    pub fn set_state_root(&mut self, root: [u8; HASH256_BYTES]) {
+        self.state_root = root;
+    }
+
+    pub fn get_state_root(&self) -> &[u8] {
+        &self.state_root
     }
 
-    pub fn get_previous(&self) -> &[u8] {
-        &self.previous
+    pub fn get_transactions(&self) -> &[Transaction] {
+        &self.transactions
     }
 
     pub fn add_transaction(&mut self, tx: Transaction) {
         self.transactions.push(tx);
     }
 
-    pub fn get_hash(&self, mut buf: &mut [u8]) {
-        let mut hash = Hash256::new();
+    // Drops the transactions but keeps the header, and so the hash.
+    pub fn prune(&mut self) {
+        self.transactions = Vec::new();
+    }
 
-        hash.write_u32::<LittleEndian>(self.version).unwrap();
-        hash.write_u64::<LittleEndian>(self.timestamp).unwrap();
-        hash.write_all(&self.previous).unwrap();
-        hash.write_all(&self.merkle_root).unwrap();
+    pub fn get_header(&self) -> BlockHeader {
+        BlockHeader::new(self.version, self.timestamp, self.previous, self.merkle_root, self.state_root)
+    }
 
-        hash.finalize(&mut buf);
+    pub fn get_tx_hashes(&self, algorithm: HashAlgorithm) -> Vec<TxId> {
+        self.transactions.iter().map(|tx| tx.get_hash(algorithm)).collect()
+    }
+
+    fn get_merkle_leaves(&self, algorithm: HashAlgorithm) -> Vec<[u8; HASH256_BYTES]> {
+        self.transactions.iter().map(|tx| *tx.get_hash(algorithm).as_bytes()).collect()
+    }
+
+    pub fn compute_merkle_root(&self, algorithm: HashAlgorithm) -> [u8; HASH256_BYTES] {
+        merkle_root(algorithm, &self.get_merkle_leaves(algorithm))
+    }
+
+    // Sets `merkle_root` to commit to the current transactions.
+    pub fn update_merkle_root(&mut self, algorithm: HashAlgorithm) {
+        self.merkle_root = self.compute_merkle_root(algorithm);
+    }
+
+    pub fn get_merkle_branch(&self, algorithm: HashAlgorithm, position: usize) -> MerkleBranch {
+        MerkleBranch::build(algorithm, &self.get_merkle_leaves(algorithm), position)
+    }
+
+    pub fn get_hash(&self, algorithm: HashAlgorithm) -> BlockHash {
+        self.get_header().get_hash(algorithm)
+    }
+}
+
+impl Default for Block {
+    fn default() -> Block {
+        Block::new()
+    }
+}
+
+impl Encodable for Block {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.get_header().encode(w)?;
+        write_vec(w, &self.transactions)
+    }
+}
+
+impl Decodable for Block {
+    fn decode<R: Read>(r: &mut R) -> io::Result<Block> {
+        let header = BlockHeader::decode(r)?;
+        Ok(Block {
+            version: header.version,
+            timestamp: header.timestamp,
+            previous: header.previous,
+            merkle_root: header.merkle_root,
+            state_root: header.state_root,
+            transactions: read_vec(r)?,
+        })
     }
 }
 
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut hash = [0u8; HASH256_BYTES];
-        self.get_hash(&mut hash);
-
-        write!(f, "block _hash: {}\n", hash.to_hex()).unwrap();
-        write!(f, "version:     {}\n", self.version).unwrap();
-        write!(f, "timestamp:   {}\n", self.timestamp).unwrap();
-        write!(f, "previous:    {}\n", self.previous.to_hex()).unwrap();
-        write!(f, "merkle_root: {}\n", self.merkle_root.to_hex()).unwrap();
-        write!(f, "transactions:\n").unwrap();
+        writeln!(f, "version:     {}", self.version)?;
+        writeln!(f, "timestamp:   {}", self.timestamp)?;
+        writeln!(f, "previous:    {}", self.previous)?;
+        writeln!(f, "merkle_root: {}", self.merkle_root.to_hex())?;
+        if self.version >= STATE_ROOT_VERSION {
+            writeln!(f, "state_root:  {}", self.state_root.to_hex())?;
+        }
+        writeln!(f, "transactions:")?;
         for tx in &self.transactions {
-            write!(f, "{}", tx).unwrap();
+            write!(f, "{}", tx)?;
+        }
+        writeln!(f)
+    }
+}
+
+// A chain file document.
+impl Serialize for Block {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut s = serializer.serialize_struct("Block", 6)?;
+        s.serialize_field("version", &self.version)?;
+        s.serialize_field("timestamp", &self.timestamp)?;
+        s.serialize_field("previous", &self.previous)?;
+        s.serialize_field("merkle_root", &self.merkle_root.to_hex())?;
+        if self.version >= STATE_ROOT_VERSION {
+            s.serialize_field("state_root", &self.state_root.to_hex())?;
         }
-        write!(f, "\n")
+        s.serialize_field("transactions", &self.transactions)?;
+        s.end()
     }
 }
 
@@ -84,8 +282,8 @@ impl<'de> Deserialize<'de> for Block {
         where D: Deserializer<'de>
     {
         #[derive(Deserialize)]
-        #[serde(field_identifier, rename_all = "lowercase")]
-        enum Field { Version, Timestamp, Previous, Merkle_root };
+        #[serde(field_identifier, rename_all = "snake_case")]
+        enum Field { Version, Timestamp, Previous, MerkleRoot, StateRoot, Transactions }
 
         struct BlockVisitor;
 
@@ -101,8 +299,10 @@ impl<'de> Deserialize<'de> for Block {
             {
                 let mut version: Option<u32> = None;
                 let mut timestamp: Option<u64> = None;
-                let mut previous: Option<String> = None;
+                let mut previous: Option<BlockHash> = None;
                 let mut merkle_root: Option<String> = None;
+                let mut state_root: Option<String> = None;
+                let mut transactions: Option<Vec<Transaction>> = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -124,35 +324,159 @@ impl<'de> Deserialize<'de> for Block {
                             }
                             previous = Some(map.next_value()?);
                         },
-                        Field::Merkle_root => {
+                        Field::MerkleRoot => {
                             if merkle_root.is_some() {
                                 return Err(serde::de::Error::duplicate_field("merkle_root"));
                             }
                             merkle_root = Some(map.next_value()?);
                         },
+                        Field::StateRoot => {
+                            if state_root.is_some() {
+                                return Err(serde::de::Error::duplicate_field("state_root"));
+                            }
+                            state_root = Some(map.next_value()?);
+                        },
+                        Field::Transactions => {
+                            if transactions.is_some() {
+                                return Err(serde::de::Error::duplicate_field("transactions"));
+                            }
+                            transactions = Some(map.next_value()?);
+                        },
                     }
                 }
 
+                let merkle_root = merkle_root.ok_or_else(|| serde::de::Error::missing_field("merkle_root"))?;
                 let mut block = Block {
-                    version: version.unwrap(),
-                    timestamp: timestamp.unwrap(),
-                    previous: [0u8; HASH256_BYTES],
+                    version: version.ok_or_else(|| serde::de::Error::missing_field("version"))?,
+                    timestamp: timestamp.ok_or_else(|| serde::de::Error::missing_field("timestamp"))?,
+                    previous: previous.ok_or_else(|| serde::de::Error::missing_field("previous"))?,
                     merkle_root: [0u8; HASH256_BYTES],
-                    transactions: Vec::new(),
+                    state_root: [0u8; HASH256_BYTES],
+                    transactions: transactions.unwrap_or_default(),
                 };
 
-                let previous_vec = previous.unwrap().from_hex().unwrap();
-                block.previous.copy_from_slice(&previous_vec);
-
-                let merkle_root_vec = merkle_root.unwrap().from_hex().unwrap();
+                let merkle_root_vec = merkle_root.from_hex().map_err(serde::de::Error::custom)?;
+                if merkle_root_vec.len() != HASH256_BYTES {
+                    return Err(serde::de::Error::invalid_length(merkle_root_vec.len(), &"a 32 byte merkle_root"));
+                }
                 block.merkle_root.copy_from_slice(&merkle_root_vec);
 
+                match (state_root, block.version >= STATE_ROOT_VERSION) {
+                    (Some(state_root), true) => {
+                        let state_root_vec = state_root.from_hex().map_err(serde::de::Error::custom)?;
+                        if state_root_vec.len() != HASH256_BYTES {
+                            return Err(serde::de::Error::invalid_length(state_root_vec.len(), &"a 32 byte state_root"));
+                        }
+                        block.state_root.copy_from_slice(&state_root_vec);
+                    },
+                    (None, true) => return Err(serde::de::Error::missing_field("state_root")),
+                    (Some(_), false) => {
+                        return Err(serde::de::Error::custom(format!("state_root needs block version {}", STATE_ROOT_VERSION)));
+                    },
+                    (None, false) => {},
+                }
+
                 Ok(block)
             }
 
         }
 
-        const FIELDS: &'static [&'static str] = &["version", "timestamp", "previous", "merkle_root"];
+        const FIELDS: &[&str] = &["version", "timestamp", "previous", "merkle_root", "state_root", "transactions"];
         deserializer.deserialize_struct("Block", FIELDS, BlockVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_yaml;
+
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use blockchain::arbitrary;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    const CHAIN: &str = include_str!("../../testdata/chain.yaml");
+
+    fn first_block() -> Block {
+        serde_yaml::from_str(CHAIN.split("---").nth(1).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parses_yaml_blocks() {
+        let block = first_block();
+        assert_eq!(block.get_hash(ALGORITHM).to_string(), "9e488d75671f67c71306618b7223f704456c88f0fc91c80c44c14a7364695e48");
+        assert!(block.get_previous().is_zero());
+        assert_eq!(block.get_transactions().len(), 1);
+        assert_eq!(block.compute_merkle_root(ALGORITHM), block.merkle_root);
+    }
+
+    #[test]
+    fn rejects_malformed_yaml() {
+        let document = CHAIN.split("---").nth(1).unwrap();
+        let bad = [
+            document.replace("version: 1\ntimestamp", "timestamp"),
+            document.replace("merkle_root: 0cb3", "merkle_root: 0c"),
+            document.replace("merkle_root: 0cb3", "merkle_root: zzb3"),
+            document.replace("previous: 00", "previous: 0"),
+            "[1, 2]".to_string(),
+        ];
+        for document in &bad {
+            assert!(serde_yaml::from_str::<Block>(document).is_err(), "{}", document);
+        }
+    }
+
+    #[test]
+    fn decodes_encoded_blocks() {
+        let block = first_block();
+        let bytes = block.to_bytes();
+        assert_eq!(Block::from_bytes(&bytes).unwrap(), block);
+        assert!(Block::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Block::from_bytes(&[bytes.clone(), vec![0]].concat()).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn encoding_round_trips(block in arbitrary::block()) {
+            let bytes = block.to_bytes();
+            let decoded = Block::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(decoded.get_hash(ALGORITHM), block.get_hash(ALGORITHM));
+            prop_assert_eq!(decoded, block);
+        }
+
+        #[test]
+        fn decode_never_panics(bytes in vec(any::<u8>(), 0..512)) {
+            let _ = Block::from_bytes(&bytes);
+        }
+
+        #[test]
+        fn decode_of_damaged_block_never_panics(block in arbitrary::block(), at in any::<usize>(), byte in any::<u8>()) {
+            let mut bytes = block.to_bytes();
+            let at = at % bytes.len();
+            bytes[at] ^= byte;
+            if let Ok(decoded) = Block::from_bytes(&bytes) {
+                prop_assert_eq!(decoded.to_bytes(), bytes);
+            }
+        }
+
+        #[test]
+        fn yaml_round_trip(block in arbitrary::block()) {
+            let document = serde_yaml::to_string(&block).unwrap();
+            prop_assert_eq!(serde_yaml::from_str::<Block>(&document).unwrap(), block);
+        }
+
+        #[test]
+        fn yaml_never_panics(document in ".{0,200}") {
+            let _ = serde_yaml::from_str::<Block>(&document);
+        }
+
+        #[test]
+        fn yaml_with_damaged_fields_never_panics(at in any::<usize>(), ch in any::<char>()) {
+            let mut document: Vec<char> = CHAIN.split("---").nth(1).unwrap().chars().collect();
+            let at = at % document.len();
+            document[at] = ch;
+            let _ = serde_yaml::from_str::<Block>(&document.into_iter().collect::<String>());
+        }
+    }
+}
@@ -1,56 +1,534 @@
 extern crate serde_yaml;
 
-use std::collections::LinkedList;
+use std::collections::HashMap;
 use std::fmt;
-use std::fs;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
 use std::io::BufReader;
-use std::io::prelude::*;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use blockchain::Block;
-use util::hash::{Hash256, HASH256_BYTES};
-use util::hex::ToHex;
+use blockchain::{check_block, check_block_limits, AcceptAll, AddressIndex, Block, BlockHash, BlockHeader, BlockUndo,
+                 ChainError, ChainEvent, ChainParams, EventBus, FeeEstimator, FilterIndex, MerkleBranch, SnapshotHash,
+                 StateProof, StateStore, Transaction, TxId, TxIndex, UtxoSet, UtxoSnapshot, Validator, STATE_ROOT_VERSION};
+use util::hash::{HashAlgorithm, HASH256_BYTES};
 
 
+// The snapshot a chain was started from, while the blocks up to it are not
+// yet attached.
+#[derive(Copy, Clone)]
+struct SnapshotBase {
+    height: usize,
+    block_hash: BlockHash,
+    hash: SnapshotHash,
+}
+
 pub struct BlockChain {
-    //block_map: HashMap<BlockHash, Block>,
-    chain: LinkedList<Box<Block>>,
+    params: ChainParams,
+    block_map: HashMap<BlockHash, usize>,
+    // Blocks from height `get_history_start()` on.
+    chain: Vec<Block>,
+    undo: Vec<BlockUndo>,
+    base: Option<SnapshotBase>,
+    utxos: UtxoSet,
+    state: StateStore,
+    validator: Validator,
+    fees: FeeEstimator,
+    snapshots: Option<(usize, PathBuf)>,
+    prune_depth: Option<usize>,
+    // Blocks below this height are pruned down to their headers in memory;
+    // the chain file keeps them whole.
+    pruned: usize,
+    tx_index: Option<TxIndex>,
+    address_index: Option<AddressIndex>,
+    filter_index: Option<FilterIndex>,
+    events: Arc<EventBus>,
+}
+
+pub fn read_documents(file: &Path) -> Result<Vec<String>, String> {
+    let mut contents = String::new();
+    File::open(file)
+        .and_then(|f| BufReader::new(f).read_to_string(&mut contents))
+        .map_err(|e| format!("{}: {}", file.display(), e))?;
+    Ok(contents.split("---").map(|s| s.to_string()).collect())
+}
+
+// Appends `block` to the chain file `file` as a new document.
+pub fn append_document(file: &Path, block: &Block) -> Result<(), String> {
+    let document = serde_yaml::to_string(block).map_err(|e| e.to_string())?;
+    let mut contents = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    contents.push_str(&document);
+    contents.push('\n');
+    fs::write(file, contents).map_err(|e| format!("{}: {}", file.display(), e))
 }
 
 impl BlockChain {
     pub fn new() -> BlockChain {
+        BlockChain::with_params(ChainParams::new())
+    }
+
+    pub fn with_params(params: ChainParams) -> BlockChain {
         BlockChain {
-            //block_map: HashMap::new(),
-            chain: LinkedList::new(),
+            params,
+            block_map: HashMap::new(),
+            chain: Vec::new(),
+            undo: Vec::new(),
+            base: None,
+            utxos: UtxoSet::new(),
+            state: StateStore::new(),
+            validator: Validator::new(Arc::new(AcceptAll)),
+            fees: FeeEstimator::new(),
+            snapshots: None,
+            prune_depth: None,
+            pruned: 0,
+            tx_index: None,
+            address_index: None,
+            filter_index: None,
+            events: Arc::new(EventBus::new()),
         }
     }
 
-    fn append(&mut self, block: Box<Block>) -> Result<(), String> {
-        match self.chain.back() {
-            Some(tail) => {
-                let mut previous_hash = [0u8; HASH256_BYTES];
-                tail.get_hash(&mut previous_hash);
-                if previous_hash != block.get_previous() {
-                    return Err(format!("append expected previous '{}'; actual '{}'",
-                                       previous_hash.to_hex(), block.get_previous().to_hex()));
+    // Blocks connected to or disconnected from the tip are published here,
+    // including those a failed reorganization connects and then takes back.
+    pub fn get_events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    fn append(&mut self, block: Block) -> Result<(), ChainError> {
+        let expected = self.get_best_hash().unwrap_or_default();
+        if block.get_previous() != expected {
+            return Err(ChainError::NotOnTip { expected, actual: block.get_previous() });
+        }
+
+        // Context-free checks, then the UTXO rules, which find the output
+        // each input spends, then verification of every input against it,
+        // then the state root. Nothing is applied until all of them pass.
+        let height = self.len();
+        let algorithm = self.get_hash_algorithm();
+        let invalid = |message| ChainError::InvalidBlock { height, message };
+        check_block(algorithm, &block).map_err(invalid)?;
+        check_block_limits(&block, &self.params).map_err(|reason| ChainError::Limits { height, reason })?;
+        let undo = self.utxos.check_block(algorithm, &block, height).map_err(invalid)?;
+        self.validator.verify_block(algorithm, &block, &undo).map_err(invalid)?;
+        self.state.check_block(algorithm, &block).map_err(invalid)?;
+        self.utxos.connect_block(algorithm, &block, height);
+        self.state.connect_block(&block);
+        self.fees.connect_block(&block, &undo);
+
+        // Indexes cover the whole chain, so they wait for its history.
+        if self.base.is_none() {
+            if let Some(ref mut index) = self.tx_index {
+                index.connect_block(&block, height);
+            }
+            if let Some(ref mut index) = self.address_index {
+                index.connect_block(&block, height, &undo);
+            }
+            if let Some(ref mut index) = self.filter_index {
+                index.connect_block(&block);
+            }
+        }
+
+        let hash = block.get_hash(algorithm);
+        self.block_map.insert(hash, height);
+        self.events.publish(ChainEvent::BlockConnected { height, hash });
+        self.chain.push(block);
+        self.undo.push(undo);
+
+        if let Some((interval, ref dir)) = self.snapshots {
+            if height > 0 && height.is_multiple_of(interval) {
+                let path = dir.join(format!("utxo-{:08}.snapshot", height));
+                if let Err(e) = self.get_snapshot().unwrap().write_to(&path) {
+                    writeln!(&mut io::stderr(), "append: writing {}: {}", path.display(), e).unwrap();
                 }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn disconnect_tip(&mut self) -> Option<Block> {
+        if self.len() <= self.pruned {
+            return None;
+        }
+        let block = self.chain.pop()?;
+        let undo = self.undo.pop().unwrap();
+        let height = self.len();
+
+        self.utxos.undo_block(self.get_hash_algorithm(), &block, &undo);
+        self.state.disconnect_block();
+        self.fees.disconnect_block();
+        if self.base.is_none() {
+            if let Some(ref mut index) = self.tx_index {
+                index.disconnect_block(&block);
+            }
+            if let Some(ref mut index) = self.address_index {
+                index.disconnect_block(&block, height, &undo);
+            }
+            if let Some(ref mut index) = self.filter_index {
+                index.disconnect_block();
+            }
+        }
+
+        let hash = block.get_hash(self.get_hash_algorithm());
+        self.block_map.remove(&hash);
+        self.events.publish(ChainEvent::BlockDisconnected { height, hash });
+        Some(block)
+    }
+
+    // Switches to the branch formed by `blocks`, which must fork off the
+    // active chain and end up longer than it. Returns the blocks that were
+    // disconnected; on failure the active chain is restored.
+    pub fn reorganize(&mut self, blocks: Vec<Block>) -> Result<Vec<Block>, ChainError> {
+        let fork_height = match blocks.first() {
+            Some(first) if first.get_previous().is_zero() => 0,
+            Some(first) if self.base.is_some_and(|b| b.block_hash == first.get_previous()) => {
+                self.get_history_start()
             },
-            None => {
-                let expected = [0u8; HASH256_BYTES];
-                if block.get_previous() != expected {
-                    return Err(format!("append expected previous '{}'; actual '{}'",
-                                       expected.to_hex(), block.get_previous().to_hex()));
+            Some(first) => match self.get_height(&first.get_previous()) {
+                Some(height) => height + 1,
+                None => return Err(ChainError::BadBranch(format!("reorganize: unknown fork point '{}'", first.get_previous()))),
+            },
+            None => return Ok(Vec::new()),
+        };
+        if fork_height < self.get_history_start() {
+            return Err(ChainError::BadBranch(format!("reorganize: fork at height {} is below the snapshot at height {}",
+                                                     fork_height, self.get_history_start() - 1)));
+        }
+        if fork_height < self.pruned {
+            return Err(ChainError::BadBranch(format!("reorganize: fork at height {} is below the pruned height {}",
+                                                     fork_height, self.pruned)));
+        }
+        if fork_height + blocks.len() <= self.len() {
+            return Err(ChainError::BadBranch(format!("reorganize: branch of {} blocks at height {} is not longer than the active chain",
+                                                     blocks.len(), fork_height)));
+        }
+
+        let mut disconnected = Vec::new();
+        while self.len() > fork_height {
+            disconnected.push(self.disconnect_tip().unwrap());
+        }
+        disconnected.reverse();
+
+        for block in blocks {
+            if let Err(e) = self.append(block) {
+                while self.len() > fork_height {
+                    self.disconnect_tip();
+                }
+                for block in disconnected {
+                    self.append(block).unwrap();
                 }
+                return Err(e);
+            }
+        }
+        self.prune();
+        Ok(disconnected)
+    }
+
+    // Keeps the transactions of only the last `depth` blocks, which bounds
+    // how deep a reorganization can go. The transaction and address indexes
+    // cannot be kept up to date from headers alone, so they must be off.
+    pub fn set_prune_depth(&mut self, depth: usize) -> Result<(), ChainError> {
+        if depth == 0 {
+            return Err(ChainError::InvalidState("set_prune_depth: depth must be at least 1".to_string()));
+        }
+        if self.tx_index.is_some() || self.address_index.is_some() {
+            return Err(ChainError::InvalidState("set_prune_depth: not supported with the transaction or address index".to_string()));
+        }
+        self.prune_depth = Some(depth);
+        self.prune();
+        Ok(())
+    }
+
+    // The height of the first block that still has its transactions.
+    pub fn get_prune_height(&self) -> usize {
+        self.pruned
+    }
+
+    // Blocks before the snapshot a chain started from are pruned once they
+    // are attached.
+    fn prune(&mut self) {
+        let depth = match self.prune_depth {
+            Some(depth) if self.base.is_none() => depth,
+            _ => return,
+        };
+        while self.pruned + depth < self.len() {
+            self.chain[self.pruned].prune();
+            self.undo[self.pruned] = BlockUndo::default();
+            self.pruned += 1;
+        }
+    }
+
+    pub fn get_validator(&self) -> &Validator {
+        &self.validator
+    }
+
+    pub fn set_validator(&mut self, validator: Validator) {
+        self.validator = validator;
+    }
+
+    // Writes a snapshot into `dir` whenever a block at a multiple of
+    // `interval` is appended.
+    pub fn set_snapshot_interval(&mut self, interval: usize, dir: &Path) {
+        assert!(interval > 0);
+        self.snapshots = Some((interval, dir.to_path_buf()));
+    }
+
+    // The UTXO set as of the tip.
+    pub fn get_snapshot(&self) -> Option<UtxoSnapshot> {
+        let tip = self.len().checked_sub(1)?;
+        Some(UtxoSnapshot::new(self.get_hash_algorithm(), tip, self.get_best_hash().unwrap(), self.utxos.clone(),
+                               self.state.get_entries().clone()))
+    }
+
+    // Starts an empty chain from `snapshot`, which the chain parameters must
+    // commit to. Blocks after it can then be appended, while those up to it
+    // are verified and attached separately.
+    pub fn load_snapshot(&mut self, snapshot: UtxoSnapshot) -> Result<(), ChainError> {
+        if !self.is_empty() {
+            return Err(ChainError::InvalidState("load_snapshot: the chain is not empty".to_string()));
+        }
+        if snapshot.get_algorithm() != self.get_hash_algorithm() {
+            return Err(ChainError::SnapshotMismatch(format!("load_snapshot: snapshot hashed with {}; the chain uses {}",
+                                                            snapshot.get_algorithm(), self.get_hash_algorithm())));
+        }
+
+        let hash = snapshot.get_hash();
+        match self.params.get_utxo_checkpoint(snapshot.get_height()) {
+            Some(expected) if expected == hash => {},
+            Some(expected) => {
+                return Err(ChainError::SnapshotMismatch(format!("load_snapshot: expected snapshot '{}' at height {}; actual '{}'",
+                                                                expected, snapshot.get_height(), hash)));
             },
+            None => {
+                return Err(ChainError::SnapshotMismatch(format!("load_snapshot: no checkpoint for height {}",
+                                                                snapshot.get_height())));
+            },
+        }
+
+        self.base = Some(SnapshotBase {
+            height: snapshot.get_height(),
+            block_hash: snapshot.get_block_hash(),
+            hash,
+        });
+        let height = snapshot.get_height();
+        let (utxos, state) = snapshot.into_parts();
+        self.utxos = utxos;
+        self.state = StateStore::with_entries(height, state);
+        Ok(())
+    }
+
+    // The height of the first stored block: zero unless the chain was
+    // started from a snapshot whose history is not yet attached.
+    pub fn get_history_start(&self) -> usize {
+        self.base.map_or(0, |b| b.height + 1)
+    }
+
+    // Prepends `history`, a chain of the blocks up to the snapshot this
+    // chain was loaded from, after checking it ends in the same UTXO set.
+    pub fn attach_history(&mut self, history: BlockChain) -> Result<(), ChainError> {
+        let base = match self.base {
+            Some(base) => base,
+            None => return Err(ChainError::InvalidState("attach_history: the chain has its history".to_string())),
+        };
+        if history.get_best_hash() != Some(base.block_hash) || history.len() != base.height + 1 {
+            return Err(ChainError::SnapshotMismatch(format!("attach_history: expected block '{}' at height {}",
+                                                            base.block_hash, base.height)));
+        }
+        let hash = history.get_snapshot().unwrap().get_hash();
+        if hash != base.hash {
+            return Err(ChainError::SnapshotMismatch(format!("attach_history: expected snapshot '{}'; actual '{}'",
+                                                            base.hash, hash)));
         }
 
-        self.chain.push_back(block);
+        let mut chain = history.chain;
+        chain.append(&mut self.chain);
+        self.chain = chain;
+        let mut undo = history.undo;
+        undo.append(&mut self.undo);
+        self.undo = undo;
+        self.block_map.extend(history.block_map);
+        self.state.attach_history(history.state);
+        self.base = None;
+        self.reindex();
+        self.prune();
         Ok(())
     }
 
+    pub fn enable_tx_index(&mut self) -> Result<(), ChainError> {
+        if self.prune_depth.is_some() {
+            return Err(ChainError::InvalidState("enable_tx_index: the transaction index needs unpruned blocks".to_string()));
+        }
+        if self.tx_index.is_none() {
+            self.tx_index = Some(TxIndex::new(self.get_hash_algorithm()));
+            self.reindex();
+        }
+        Ok(())
+    }
+
+    pub fn enable_address_index(&mut self) -> Result<(), ChainError> {
+        if self.prune_depth.is_some() {
+            return Err(ChainError::InvalidState("enable_address_index: the address index needs unpruned blocks".to_string()));
+        }
+        if self.address_index.is_none() {
+            self.address_index = Some(AddressIndex::new(self.get_hash_algorithm()));
+            self.reindex();
+        }
+        Ok(())
+    }
+
+    pub fn enable_filter_index(&mut self) {
+        if self.filter_index.is_none() {
+            self.filter_index = Some(FilterIndex::new(self.get_hash_algorithm()));
+            self.reindex();
+        }
+    }
+
+    pub fn get_tx_index(&self) -> Option<&TxIndex> {
+        self.tx_index.as_ref()
+    }
+
+    pub fn get_address_index(&self) -> Option<&AddressIndex> {
+        self.address_index.as_ref()
+    }
+
+    pub fn get_filter_index(&self) -> Option<&FilterIndex> {
+        self.filter_index.as_ref()
+    }
+
+    // Regenerates the enabled indexes from the stored blocks, once the
+    // chain has its history and as long as none are pruned.
+    pub fn reindex(&mut self) {
+        if self.base.is_some() || self.pruned > 0 {
+            return;
+        }
+        if self.tx_index.is_some() {
+            let mut index = TxIndex::new(self.get_hash_algorithm());
+            for (height, block) in self.chain.iter().enumerate() {
+                index.connect_block(block, height);
+            }
+            self.tx_index = Some(index);
+        }
+        if self.address_index.is_some() {
+            let mut index = AddressIndex::new(self.get_hash_algorithm());
+            for (height, (block, undo)) in self.chain.iter().zip(&self.undo).enumerate() {
+                index.connect_block(block, height, undo);
+            }
+            self.address_index = Some(index);
+        }
+        if self.filter_index.is_some() {
+            let mut index = FilterIndex::new(self.get_hash_algorithm());
+            for block in &self.chain {
+                index.connect_block(block);
+            }
+            self.filter_index = Some(index);
+        }
+    }
+
+    // The height of the tip plus one.
+    pub fn len(&self) -> usize {
+        self.get_history_start() + self.chain.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // `None` for pruned blocks; their headers remain available.
+    pub fn get_block(&self, height: usize) -> Option<&Block> {
+        if height < self.pruned {
+            return None;
+        }
+        height.checked_sub(self.get_history_start()).and_then(|i| self.chain.get(i))
+    }
+
+    // The coins the block at `height` spent, wherever `get_block` has it.
+    pub fn get_undo(&self, height: usize) -> Option<&BlockUndo> {
+        if height < self.pruned {
+            return None;
+        }
+        height.checked_sub(self.get_history_start()).and_then(|i| self.undo.get(i))
+    }
+
+    pub fn get_header(&self, height: usize) -> Option<BlockHeader> {
+        height.checked_sub(self.get_history_start())
+            .and_then(|i| self.chain.get(i))
+            .map(|block| block.get_header())
+    }
+
+    pub fn get_height(&self, hash: &BlockHash) -> Option<usize> {
+        self.block_map.get(hash).cloned()
+    }
+
+    pub fn get_block_by_hash(&self, hash: &BlockHash) -> Option<&Block> {
+        self.get_height(hash).and_then(|h| self.get_block(h))
+    }
+
+    pub fn get_best_hash(&self) -> Option<BlockHash> {
+        match self.chain.last() {
+            Some(tip) => Some(tip.get_hash(self.get_hash_algorithm())),
+            None => self.base.map(|b| b.block_hash),
+        }
+    }
+
+    // The branch proving transaction `hash` is in the block at the returned
+    // height.
+    pub fn get_merkle_proof(&self, hash: &TxId) -> Option<(usize, MerkleBranch)> {
+        let (height, _) = self.find_transaction(hash)?;
+        let block = self.get_block(height)?;
+        let algorithm = self.get_hash_algorithm();
+        let position = block.get_tx_hashes(algorithm).iter().position(|h| h == hash)?;
+        Some((height, block.get_merkle_branch(algorithm, position)))
+    }
+
+    pub fn get_fee_estimator(&self) -> &FeeEstimator {
+        &self.fees
+    }
+
+    pub fn get_utxos(&self) -> &UtxoSet {
+        &self.utxos
+    }
+
+    // The key/value state as of the tip.
+    pub fn get_state(&self) -> &StateStore {
+        &self.state
+    }
+
+    // The state root `block` must commit to if appended next.
+    pub fn compute_state_root(&self, block: &Block) -> Result<[u8; HASH256_BYTES], String> {
+        self.state.compute_root(self.get_hash_algorithm(), block)
+    }
+
+    // Proves the value of `key` after the block at `height`, which must
+    // commit to a state root. Proofs reach back to the snapshot a chain was
+    // loaded from until its history is attached.
+    pub fn get_state_proof(&self, key: &[u8], height: usize) -> Option<StateProof> {
+        if self.get_header(height)?.get_version() < STATE_ROOT_VERSION {
+            return None;
+        }
+        self.state.get_proof(self.get_hash_algorithm(), key, height)
+    }
+
+    // Returns the height `hash` was confirmed at, scanning every block unless
+    // the transaction index is enabled and built.
+    pub fn find_transaction(&self, hash: &TxId) -> Option<(usize, &Transaction)> {
+        if let (Some(index), None) = (&self.tx_index, self.base) {
+            return index.get(hash).map(|l| (l.height, &self.chain[l.height].get_transactions()[l.position]));
+        }
+
+        let start = self.get_history_start();
+        for (i, block) in self.chain.iter().enumerate() {
+            for tx in block.get_transactions() {
+                if tx.get_hash(self.get_hash_algorithm()) == *hash {
+                    return Some((start + i, tx));
+                }
+            }
+        }
+        None
+    }
+
     /*
     pub fn write_chain(&self, dir: &Path) {
         let block_file = dir.join("blocks.yaml");
@@ -67,35 +545,337 @@ impl BlockChain {
     }
     */
 
+    pub fn get_params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    // The hash function of this chain's blocks, transactions, Merkle trees,
+    // filters and snapshots.
+    pub fn get_hash_algorithm(&self) -> HashAlgorithm {
+        self.params.get_hash_algorithm()
+    }
+
+    // Parameters can only change while the chain is empty; hashes of blocks
+    // already connected depend on them.
+    pub fn set_params(&mut self, params: ChainParams) -> Result<(), ChainError> {
+        if params == self.params {
+            return Ok(());
+        }
+        if !self.is_empty() {
+            return Err(ChainError::InvalidState("set_params: the chain is not empty".to_string()));
+        }
+        self.params = params;
+        Ok(())
+    }
+
+    // Sets the chain parameters from the document before the first block of
+    // `file`, if there is one.
+    pub fn read_params(&mut self, file: &Path) -> Result<(), ChainError> {
+        let documents = read_documents(file).map_err(ChainError::Io)?;
+        if documents[0].trim().is_empty() {
+            return Ok(());
+        }
+        let params = serde_yaml::from_str(&documents[0])
+            .map_err(|e| ChainError::Parse(format!("chain params: {}", e)))?;
+        self.set_params(params)
+    }
+
+    // Appends the blocks of `file` after the current tip, stopping before
+    // height `end` if given.
+    pub fn read_blocks(&mut self, file: &Path, end: Option<usize>) -> Result<(), ChainError> {
+        let documents = read_documents(file).map_err(ChainError::Io)?;
+        let start = self.len();
+        let end = end.unwrap_or(usize::MAX);
+        for (height, document) in documents[1..].iter().enumerate().take(end).skip(start) {
+            let block: Block = serde_yaml::from_str(document)
+                .map_err(|e| ChainError::Parse(format!("block {}: {}", height, e)))?;
+            self.append(block)?;
+            self.prune();
+        }
+        Ok(())
+    }
+
+    // Reads the parameters and the blocks after the current tip from
+    // `file`, reporting errors on stderr.
     pub fn read_chain(&mut self, file: &Path) {
-        let f = File::open(file).unwrap();
-        let mut reader = BufReader::new(f);
-        let mut contents = String::new();
-        reader.read_to_string(&mut contents).unwrap();
-
-        let yaml_blocks: Vec<&str> = contents.split("---").collect();
-        for yaml_block in &yaml_blocks[1..] {
-            let block: Box<Block> = Box::new(serde_yaml::from_str(&yaml_block).unwrap());
-            let result = self.append(block);
-            match result {
-                Ok(_) => {},
-                Err(e) => {
-                    writeln!(&mut io::stderr(), "read_chain: {}", e);
-                    return;
-                }
-            }
+        if let Err(e) = self.read_params(file).and_then(|_| self.read_blocks(file, None)) {
+            writeln!(&mut io::stderr(), "read_chain: {}", e).unwrap();
         }
     }
 }
 
+impl Default for BlockChain {
+    fn default() -> BlockChain {
+        BlockChain::new()
+    }
+}
+
 impl fmt::Display for BlockChain {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (i, ref block) in self.chain.iter().enumerate() {
-            let mut hash = [0u8; HASH256_BYTES];
-            block.get_hash(&mut hash);
-            write!(f, "{:08}: {}\n", i, hash.to_hex())?;
+        let start = self.get_history_start();
+        for (i, block) in self.chain.iter().enumerate() {
+            writeln!(f, "{:08}: {}", start + i, block.get_hash(self.get_hash_algorithm()))?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::arbitrary;
+    use blockchain::{Encodable, HistoryKind, OutPoint, RejectReason};
+    use proptest::prelude::*;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    // `count` blocks on top of `previous`, each with a coinbase stamped
+    // with `seed` so that branches differ.
+    fn blocks(previous: BlockHash, count: usize, seed: u64) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for i in 0..count {
+            let mut coinbase = Transaction::new();
+            coinbase.set_timestamp(seed * 1000 + i as u64);
+            coinbase.add_output(50, b"s");
+            let mut block = Block::new();
+            block.set_previous(blocks.last().map_or(previous, |b| b.get_hash(ALGORITHM)));
+            block.add_transaction(coinbase);
+            block.update_merkle_root(ALGORITHM);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn pruning_keeps_headers_and_shallow_reorgs() {
+        let mut chain = BlockChain::new();
+        chain.set_prune_depth(2).unwrap();
+        chain.reorganize(blocks(BlockHash::default(), 6, 0)).unwrap();
+        assert_eq!(chain.get_prune_height(), 4);
+        assert!(chain.get_block(3).is_none());
+        assert_eq!(chain.get_header(3).unwrap().get_hash(ALGORITHM), chain.get_block(4).unwrap().get_previous());
+
+        // Replacing the last two blocks is within the depth, replacing three
+        // is not.
+        let fork = chain.get_header(3).unwrap().get_hash(ALGORITHM);
+        assert_eq!(chain.reorganize(blocks(fork, 3, 1)).unwrap().len(), 2);
+        assert_eq!(chain.len(), 7);
+        let fork = chain.get_header(3).unwrap().get_hash(ALGORITHM);
+        assert!(chain.reorganize(blocks(fork, 5, 2)).is_err());
+        assert_eq!(chain.len(), 7);
+
+        // Index entries would point into pruned blocks.
+        assert!(chain.enable_tx_index().is_err());
+        assert!(chain.enable_address_index().is_err());
+        assert!(chain.get_tx_index().is_none());
+    }
+
+    // A block on the tip writing `key`, committing to the resulting state.
+    fn write(chain: &BlockChain, key: &[u8], value: &[u8]) -> Block {
+        let mut coinbase = Transaction::new();
+        coinbase.set_timestamp(chain.len() as u64);
+        coinbase.add_output(50, b"s");
+        coinbase.add_write(key, value);
+        let mut block = Block::new();
+        block.set_version(STATE_ROOT_VERSION);
+        block.set_previous(chain.get_best_hash().unwrap_or_default());
+        block.add_transaction(coinbase);
+        block.update_merkle_root(ALGORITHM);
+        block.set_state_root(chain.compute_state_root(&block).unwrap());
+        block
+    }
+
+    #[test]
+    fn state_root() {
+        let mut chain = BlockChain::new();
+        chain.reorganize(blocks(BlockHash::default(), 1, 0)).unwrap();
+        let block = write(&chain, b"name", b"rustychain");
+        chain.reorganize(vec![block]).unwrap();
+        assert_eq!(chain.get_state().get(b"name"), Some(&b"rustychain"[..]));
+        let proof = chain.get_state_proof(b"name", 1).unwrap();
+        assert!(proof.verify(ALGORITHM, &chain.get_header(1).unwrap()));
+        // Version 1 blocks commit to no state.
+        assert!(chain.get_state_proof(b"name", 0).is_none());
+
+        let mut stale = write(&chain, b"name", b"other");
+        stale.set_state_root(chain.get_state().get_root(ALGORITHM));
+        assert!(chain.reorganize(vec![stale]).unwrap_err().to_string().contains("expected state_root"));
+
+        chain.disconnect_tip();
+        assert!(chain.get_state().is_empty());
+    }
+
+    // A second block spending `amount` of the first block's coinbase.
+    fn spend(chain: &BlockChain, amount: u64, inputs: usize) -> Block {
+        let algorithm = chain.get_hash_algorithm();
+        let first = chain.get_block(0).unwrap();
+        let coinbase = first.get_transactions()[0].get_hash(algorithm);
+        let mut tx = Transaction::new();
+        for _ in 0..inputs {
+            tx.add_input(OutPoint::new(coinbase, 0));
+        }
+        tx.add_output(amount, b"t");
+        let mut block = Block::new();
+        block.set_previous(chain.get_best_hash().unwrap());
+        block.add_transaction(tx);
+        block.update_merkle_root(algorithm);
+        block
+    }
+
+    #[test]
+    fn chains_hash_independently() {
+        let mut params = ChainParams::new();
+        params.set_hash_algorithm(HashAlgorithm::Sha256d);
+        let mut sha256d = BlockChain::with_params(params);
+        let mut blake2s = BlockChain::new();
+
+        // The same coinbase block, committed to with each chain's algorithm.
+        let genesis = |algorithm| {
+            let mut block = blocks(BlockHash::default(), 1, 0).remove(0);
+            block.update_merkle_root(algorithm);
+            block
+        };
+        blake2s.reorganize(vec![genesis(HashAlgorithm::Blake2s)]).unwrap();
+        sha256d.reorganize(vec![genesis(HashAlgorithm::Sha256d)]).unwrap();
+        assert!(BlockChain::new().reorganize(vec![genesis(HashAlgorithm::Sha256d)]).is_err());
+
+        // Spends link to the coinbase by each chain's txid.
+        let block = spend(&blake2s, 50, 1);
+        blake2s.reorganize(vec![block]).unwrap();
+        let block = spend(&sha256d, 50, 1);
+        sha256d.reorganize(vec![block.clone()]).unwrap();
+        assert_eq!(sha256d.get_best_hash(), Some(block.get_hash(HashAlgorithm::Sha256d)));
+        assert_ne!(blake2s.get_best_hash(), sha256d.get_best_hash());
+        let txid = block.get_transactions()[0].get_hash(HashAlgorithm::Sha256d);
+        assert!(sha256d.get_utxos().get(&OutPoint::new(txid, 0)).is_some());
+        assert!(blake2s.get_utxos().get(&OutPoint::new(txid, 0)).is_none());
+
+        let snapshot = sha256d.get_snapshot().unwrap();
+        assert!(BlockChain::new().load_snapshot(snapshot).unwrap_err().to_string().contains("hashed with sha256d"));
+    }
+
+    #[test]
+    fn indexes_follow_reorganizations() {
+        let mut chain = BlockChain::new();
+        chain.enable_tx_index().unwrap();
+        chain.enable_address_index().unwrap();
+        let genesis = blocks(BlockHash::default(), 1, 0);
+        chain.reorganize(genesis.clone()).unwrap();
+        let block = spend(&chain, 50, 1);
+        let tx = block.get_transactions()[0].get_hash(ALGORITHM);
+        chain.reorganize(vec![block]).unwrap();
+
+        assert_eq!(chain.get_tx_index().unwrap().get(&tx).map(|l| l.height), Some(1));
+        let index = chain.get_address_index().unwrap();
+        assert!(matches!(index.get_history(b"s")[1].kind, HistoryKind::Spend { tx: spender, input: 0 } if spender == tx));
+        assert_eq!(index.get_history(b"t").len(), 1);
+
+        // A longer branch without the spend takes both entries back.
+        let branch = blocks(genesis[0].get_hash(ALGORITHM), 2, 1);
+        assert_eq!(chain.reorganize(branch.clone()).unwrap().len(), 1);
+        let tx_index = chain.get_tx_index().unwrap();
+        assert!(tx_index.get(&tx).is_none());
+        assert_eq!(tx_index.get(&branch[1].get_transactions()[0].get_hash(ALGORITHM)).map(|l| l.height), Some(2));
+        assert_eq!(tx_index.len(), 3);
+        let index = chain.get_address_index().unwrap();
+        assert!(index.get_history(b"t").is_empty());
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get_entry_count(), 3);
+        assert!(index.get_history(b"s").iter().all(|e| matches!(e.kind, HistoryKind::Output)));
+    }
+
+    #[test]
+    fn append_rules() {
+        let mut chain = BlockChain::new();
+        chain.reorganize(blocks(BlockHash::default(), 1, 0)).unwrap();
+        let tip = chain.get_best_hash();
+
+        // Not on top of the tip.
+        let mut orphan = spend(&chain, 50, 1);
+        orphan.set_previous(BlockHash::default());
+        orphan.update_merkle_root(ALGORITHM);
+        match chain.append(orphan) {
+            Err(ChainError::NotOnTip { expected, .. }) => assert_eq!(Some(expected), tip),
+            result => panic!("expected NotOnTip; actual {:?}", result),
+        }
+
+        // Transactions that do not match the Merkle root.
+        let mut tampered = spend(&chain, 50, 1);
+        tampered.add_transaction(Transaction::new());
+        assert!(chain.append(tampered).unwrap_err().to_string().contains("expected merkle_root"));
+
+        // Spending more than the output, or the same output twice.
+        assert!(chain.append(spend(&chain, 51, 1)).unwrap_err().to_string().contains("exceed inputs"));
+        assert!(chain.append(spend(&chain, 50, 2)).unwrap_err().to_string().contains("spent twice"));
+
+        // Rejected blocks leave no trace.
+        assert_eq!(chain.len(), 1);
+        assert_eq!(chain.get_best_hash(), tip);
+        assert_eq!(chain.get_utxos().len(), 1);
+
+        chain.append(spend(&chain, 50, 1)).unwrap();
+        assert!(chain.append(spend(&chain, 50, 1)).unwrap_err().to_string().contains("missing or spent"));
+    }
+
+    #[test]
+    fn append_limits() {
+        let genesis = blocks(BlockHash::default(), 1, 0);
+        let limited = |set: &dyn Fn(&mut ChainParams)| {
+            let mut params = ChainParams::new();
+            set(&mut params);
+            let mut chain = BlockChain::new();
+            chain.set_params(params).unwrap();
+            chain.reorganize(genesis.clone()).unwrap();
+            chain
+        };
+
+        let mut chain = limited(&|p| p.set_dust_threshold(20));
+        match chain.append(spend(&chain, 19, 1)) {
+            Err(ChainError::Limits { height: 1, reason: RejectReason::Dust { amount: 19, min: 20, .. } }) => {},
+            result => panic!("expected Dust; actual {:?}", result),
+        }
+        chain.append(spend(&chain, 20, 1)).unwrap();
+
+        let size = genesis[0].to_bytes().len();
+        let mut chain = limited(&|p| p.set_max_block_size(size));
+        assert!(chain.append(spend(&chain, 50, 1)).unwrap_err().to_string().contains(&format!("exceeds {}", size)));
+
+        let mut chain = limited(&|p| p.set_max_tx_inputs(1));
+        assert!(chain.append(spend(&chain, 50, 2)).unwrap_err().to_string().contains("at most 1 allowed"));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn accepts_valid_chains(blocks in arbitrary::valid_chain(12)) {
+            let mut chain = BlockChain::new();
+            chain.reorganize(blocks.clone()).unwrap();
+            prop_assert_eq!(chain.len(), blocks.len());
+            prop_assert_eq!(chain.get_best_hash(), blocks.last().map(|b| b.get_hash(ALGORITHM)));
+
+            // Disconnecting everything restores the empty set.
+            while chain.disconnect_tip().is_some() {}
+            prop_assert!(chain.get_utxos().is_empty());
+        }
+
+        #[test]
+        fn rejects_chains_with_a_double_spend(blocks in arbitrary::valid_chain(12), at in any::<usize>()) {
+            // Repeating a transaction that spends spends its inputs twice.
+            let mut blocks = blocks;
+            let spending: Vec<Transaction> = blocks.iter()
+                .flat_map(|b| b.get_transactions().iter().skip(1).cloned())
+                .collect();
+            prop_assume!(!spending.is_empty());
+            let tx = spending[at % spending.len()].clone();
+            let last = blocks.len() - 1;
+            blocks[last].add_transaction(tx);
+            blocks[last].update_merkle_root(ALGORITHM);
+
+            let mut chain = BlockChain::new();
+            prop_assert!(chain.reorganize(blocks).is_err());
+            prop_assert_eq!(chain.len(), 0);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+extern crate byteorder;
+
+use std::io;
+use std::io::{Read, Write};
+
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+
+// Upper bound on elements pre-allocated from an untrusted length prefix.
+pub const MAX_PREALLOC: usize = 1024;
+
+// Upper bound on the length of a single byte string.
+pub const MAX_VAR_BYTES: usize = 1 << 20;
+
+pub trait Encodable {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf).unwrap();
+        buf
+    }
+}
+
+pub trait Decodable: Sized {
+    fn decode<R: Read>(r: &mut R) -> io::Result<Self>;
+
+    fn from_bytes(buf: &[u8]) -> io::Result<Self> {
+        let mut reader = buf;
+        let value = Self::decode(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(invalid_data(format!("{} trailing bytes", reader.len())));
+        }
+        Ok(value)
+    }
+}
+
+pub fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub fn write_var_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    w.write_all(bytes)
+}
+
+pub fn read_var_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let len = r.read_u32::<LittleEndian>()? as usize;
+    if len > MAX_VAR_BYTES {
+        return Err(invalid_data(format!("byte string of {} bytes exceeds {}", len, MAX_VAR_BYTES)));
+    }
+
+    let mut buf = Vec::new();
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated byte string"));
+    }
+    Ok(buf)
+}
+
+pub fn write_vec<W: Write, T: Encodable>(w: &mut W, items: &[T]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(items.len() as u32)?;
+    for item in items {
+        item.encode(w)?;
+    }
+    Ok(())
+}
+
+pub fn read_vec<R: Read, T: Decodable>(r: &mut R) -> io::Result<Vec<T>> {
+    let len = r.read_u32::<LittleEndian>()? as usize;
+    let mut items = Vec::with_capacity(len.min(MAX_PREALLOC));
+    for _ in 0..len {
+        items.push(T::decode(r)?);
+    }
+    Ok(items)
+}
@@ -0,0 +1,108 @@
+use std::error;
+use std::fmt;
+
+use blockchain::{BlockHash, OutPoint, RejectReason, RelativeLock};
+
+
+// Why a `TransactionBuilder` or `TransactionInputBuilder` refused to build.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildError {
+    // Only versions 1 to 4 are defined. Version 2 adds lock times,
+    // version 3 state writes and version 4 issued assets.
+    UnsupportedVersion(u32),
+    // A lock time or a sequence other than `SEQUENCE_FINAL` was given to a
+    // transaction explicitly set to version 1.
+    LockTimeNeedsVersion2,
+    // State writes were given to a transaction explicitly set to version 1
+    // or 2.
+    WritesNeedVersion3,
+    // An issuance or an output carrying an issued asset was given to a
+    // transaction explicitly set to a version below 4.
+    AssetsNeedVersion4,
+    // An issuance was given to a transaction without inputs, whose first
+    // input the asset ID is derived from.
+    IssuanceNeedsInput,
+    // Two inputs spend the same output, which no block may contain.
+    DuplicateInput(OutPoint),
+    // The lock is longer than a sequence can express: `SEQUENCE_MASK`
+    // blocks, or that many units of 512 seconds.
+    RelativeLockTooLong(RelativeLock),
+    // The fee estimator has seen fewer blocks than the confirmation target.
+    NoFeeEstimate(usize),
+    // The inputs are worth less of the native coin than the outputs and the
+    // fee need.
+    InsufficientFunds { needed: u64, available: u64 },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::UnsupportedVersion(version) => write!(f, "unsupported transaction version {}", version),
+            BuildError::LockTimeNeedsVersion2 => f.write_str("lock times need version 2"),
+            BuildError::WritesNeedVersion3 => f.write_str("state writes need version 3"),
+            BuildError::AssetsNeedVersion4 => f.write_str("issued assets need version 4"),
+            BuildError::IssuanceNeedsInput => f.write_str("an issuance needs an input"),
+            BuildError::DuplicateInput(ref out) => write!(f, "output {} is spent twice", out),
+            BuildError::RelativeLockTooLong(lock) => write!(f, "relative lock {:?} is too long", lock),
+            BuildError::NoFeeEstimate(target) => write!(f, "no fee estimate for {} blocks yet", target),
+            BuildError::InsufficientFunds { needed, available } =>
+                write!(f, "inputs worth {} cannot pay {}", available, needed),
+        }
+    }
+}
+
+impl error::Error for BuildError {}
+
+// Why a `BlockChain` operation failed.
+// Messages name the operation and the values involved. Functions elsewhere
+// in the crate that return `String` errors accept a `ChainError` through
+// `?`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainError {
+    // A chain file could not be read.
+    Io(String),
+    // A chain file document is not valid YAML for chain parameters or a
+    // block.
+    Parse(String),
+    // The block's `previous` is not the hash of the tip.
+    NotOnTip { expected: BlockHash, actual: BlockHash },
+    // The block breaks a consensus limit of the chain parameters.
+    Limits { height: usize, reason: RejectReason },
+    // The block breaks another consensus rule: its Merkle root does not
+    // match, or the UTXO set or the input verifier rejects a spend.
+    InvalidBlock { height: usize, message: String },
+    // The branch given to `reorganize` cannot replace the active chain:
+    // its fork point is unknown, pruned or below the snapshot, or it is
+    // not longer.
+    BadBranch(String),
+    // The operation does not fit the chain's state or settings, such as
+    // changing the parameters of a non-empty chain.
+    InvalidState(String),
+    // A snapshot, or the history attached to one, does not match the
+    // chain parameters' checkpoint or the chain.
+    SnapshotMismatch(String),
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChainError::NotOnTip { expected, actual } =>
+                write!(f, "expected previous '{}'; actual '{}'", expected, actual),
+            ChainError::Limits { height, ref reason } => write!(f, "block {}: {}", height, reason),
+            ChainError::InvalidBlock { height, ref message } => write!(f, "block {}: {}", height, message),
+            ChainError::Io(ref message) |
+            ChainError::Parse(ref message) |
+            ChainError::BadBranch(ref message) |
+            ChainError::InvalidState(ref message) |
+            ChainError::SnapshotMismatch(ref message) => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for ChainError {}
+
+impl From<ChainError> for String {
+    fn from(e: ChainError) -> String {
+        e.to_string()
+    }
+}
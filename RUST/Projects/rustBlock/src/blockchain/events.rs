@@ -0,0 +1,115 @@
+use std::sync::Mutex;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use blockchain::{BlockHash, TxId};
+
+
+// How many events a subscriber may fall behind by before it is dropped.
+pub const SUBSCRIBER_BUFFER: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictReason {
+    // Included in a connected block.
+    Confirmed,
+    // Spends an output a connected block spent first.
+    Conflict,
+    // Spends an output that no longer exists, after a block that created it
+    // was disconnected.
+    MissingInputs,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainEvent {
+    BlockConnected { height: usize, hash: BlockHash },
+    BlockDisconnected { height: usize, hash: BlockHash },
+    TxAccepted { txid: TxId },
+    TxEvicted { txid: TxId, reason: EvictReason },
+}
+
+impl ChainEvent {
+    // The name of the variant, as serialized in `type`.
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            ChainEvent::BlockConnected { .. } => "block_connected",
+            ChainEvent::BlockDisconnected { .. } => "block_disconnected",
+            ChainEvent::TxAccepted { .. } => "tx_accepted",
+            ChainEvent::TxEvicted { .. } => "tx_evicted",
+        }
+    }
+}
+
+// Fans events out to every subscriber's channel, in the order they were
+// published. Dropping a receiver unsubscribes it. Publishing never blocks: a
+// subscriber whose channel is full is dropped, and its receiver disconnects
+// once it has drained the events before.
+pub struct EventBus {
+    subscribers: Mutex<Vec<SyncSender<ChainEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = sync_channel(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn get_subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    pub fn publish(&self, event: ChainEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        subscribers.retain(|s| s.try_send(event.clone()).is_ok());
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        EventBus::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_receivers_unsubscribe() {
+        let bus = EventBus::new();
+        let kept = bus.subscribe();
+        drop(bus.subscribe());
+        let event = ChainEvent::TxAccepted { txid: TxId::default() };
+        bus.publish(event.clone());
+        assert_eq!(bus.get_subscriber_count(), 1);
+        assert_eq!(kept.try_recv(), Ok(event));
+
+        let json = ::serde_json::to_value(ChainEvent::TxEvicted { txid: TxId::default(), reason: EvictReason::Conflict }).unwrap();
+        assert_eq!(json["type"], "tx_evicted");
+        assert_eq!(json["reason"], "conflict");
+    }
+
+    #[test]
+    fn slow_subscribers_are_dropped() {
+        let bus = EventBus::new();
+        let slow = bus.subscribe();
+        let event = ChainEvent::TxAccepted { txid: TxId::default() };
+        for _ in 0..SUBSCRIBER_BUFFER {
+            bus.publish(event.clone());
+        }
+        assert_eq!(bus.get_subscriber_count(), 1);
+        bus.publish(event);
+        assert_eq!(bus.get_subscriber_count(), 0);
+        assert_eq!(slow.iter().count(), SUBSCRIBER_BUFFER);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+
+use blockchain::{Block, BlockUndo, Encodable};
+
+
+// How many recent blocks the estimator remembers, and so the longest
+// confirmation target it answers for.
+pub const MAX_FEE_BLOCKS: usize = 1008;
+
+// The share of past windows of `target` blocks an estimate must have been
+// enough to get into.
+const SUCCESS_PERCENT: usize = 85;
+
+// Fee rates are amounts per 1000 bytes of encoded transaction.
+pub fn fee_rate(fee: u64, size: usize) -> u64 {
+    fee.saturating_mul(1000) / size.max(1) as u64
+}
+
+// Fee rates paid by the transactions of one block, coinbase excluded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlockFees {
+    pub count: usize,
+    pub min_rate: u64,
+    pub median_rate: u64,
+}
+
+impl BlockFees {
+    // `undo` lists the coin each input of `block` spends, as
+    // `UtxoSet::check_block` returns it.
+    pub fn new(block: &Block, undo: &BlockUndo) -> BlockFees {
+        let mut spent = undo.get_spent().iter();
+        let mut rates = Vec::new();
+        for tx in block.get_transactions() {
+            let input_total = tx.get_inputs().iter()
+                .map(|_| spent.next().unwrap().1.get_output().get_native_amount())
+                .fold(0u64, |sum, a| sum.saturating_add(a));
+            if !tx.is_coinbase() {
+                let fee = tx.get_output_total().map_or(0, |total| input_total.saturating_sub(total));
+                rates.push(fee_rate(fee, tx.to_bytes().len()));
+            }
+        }
+        rates.sort_unstable();
+
+        BlockFees {
+            count: rates.len(),
+            min_rate: rates.first().cloned().unwrap_or(0),
+            median_rate: rates.get(rates.len() / 2).cloned().unwrap_or(0),
+        }
+    }
+}
+
+// Estimates the fee rate needed to confirm within a number of blocks from
+// the lowest rate each recent block accepted.
+pub struct FeeEstimator {
+    blocks: VecDeque<BlockFees>,
+}
+
+impl FeeEstimator {
+    pub fn new() -> FeeEstimator {
+        FeeEstimator {
+            blocks: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn connect_block(&mut self, block: &Block, undo: &BlockUndo) {
+        if self.blocks.len() == MAX_FEE_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(BlockFees::new(block, undo));
+    }
+
+    pub fn disconnect_block(&mut self) {
+        self.blocks.pop_back();
+    }
+
+    // The most recent block first.
+    pub fn get_recent(&self) -> impl Iterator<Item = &BlockFees> {
+        self.blocks.iter().rev()
+    }
+
+    // A transaction confirms within `target` blocks if it pays at least the
+    // lowest rate of one of them. Over every run of `target` consecutive
+    // recent blocks, that is the lowest of their minimum rates; the estimate
+    // is a rate that would have been enough for `SUCCESS_PERCENT` of the
+    // runs. `None` until `target` blocks have been seen.
+    pub fn estimate(&self, target: usize) -> Option<u64> {
+        let target = target.clamp(1, MAX_FEE_BLOCKS);
+        if self.blocks.len() < target {
+            return None;
+        }
+
+        let mins: Vec<u64> = self.blocks.iter().map(|b| b.min_rate).collect();
+        let mut needed: Vec<u64> = mins.windows(target)
+            .map(|run| *run.iter().min().unwrap())
+            .collect();
+        needed.sort_unstable();
+        let index = (needed.len() * SUCCESS_PERCENT).div_ceil(100) - 1;
+        Some(needed[index])
+    }
+}
+
+impl Default for FeeEstimator {
+    fn default() -> FeeEstimator {
+        FeeEstimator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::hash::HashAlgorithm;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn fees(min_rate: u64) -> BlockFees {
+        BlockFees {
+            count: 1,
+            min_rate,
+            median_rate: min_rate,
+        }
+    }
+
+    fn estimator(mins: &[u64]) -> FeeEstimator {
+        FeeEstimator {
+            blocks: mins.iter().map(|&m| fees(m)).collect(),
+        }
+    }
+
+    #[test]
+    fn longer_targets_need_lower_rates() {
+        let estimator = estimator(&[50, 10, 40, 30, 20, 60, 10, 45, 35, 25]);
+        assert_eq!(estimator.estimate(1), Some(50));
+        assert_eq!(estimator.estimate(2), Some(30));
+        assert_eq!(estimator.estimate(5), Some(10));
+        assert!(estimator.estimate(3) >= estimator.estimate(4));
+        assert_eq!(estimator.estimate(11), None);
+    }
+
+    #[test]
+    fn forgets_old_blocks() {
+        let mut estimator = estimator(&vec![100; MAX_FEE_BLOCKS]);
+        let mut block = Block::new();
+        block.update_merkle_root(ALGORITHM);
+        estimator.connect_block(&block, &BlockUndo::default());
+        assert_eq!(estimator.len(), MAX_FEE_BLOCKS);
+        assert_eq!(estimator.get_recent().next(), Some(&BlockFees { count: 0, min_rate: 0, median_rate: 0 }));
+        estimator.disconnect_block();
+        assert_eq!(estimator.estimate(1), Some(100));
+    }
+}
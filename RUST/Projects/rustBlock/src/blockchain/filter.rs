@@ -0,0 +1,345 @@
+extern crate byteorder;
+
+use std::io;
+use std::io::{Read, Write};
+
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use blockchain::{Block, BlockHash, Encodable};
+use blockchain::encode::{Decodable, read_var_bytes, write_var_bytes};
+use util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
+use util::siphash::siphash24;
+
+
+// Golomb-Rice parameters of the BIP158 basic filter.
+pub const FILTER_P: u8 = 19;
+pub const FILTER_M: u64 = 784931;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u8,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            used: 8,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.used == 8 {
+            self.bytes.push(0);
+            self.used = 0;
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+        }
+        self.used += 1;
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn write_golomb_rice(&mut self, value: u64) {
+        for _ in 0..(value >> FILTER_P) {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+        self.write_bits(value, FILTER_P);
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            bytes,
+            position: 0,
+        }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Option<u64> {
+        let mut value = 0u64;
+        for _ in 0..count {
+            value = value << 1 | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    fn read_golomb_rice(&mut self) -> Option<u64> {
+        let mut quotient = 0u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        if quotient >> (64 - FILTER_P) != 0 {
+            return None;
+        }
+        Some(quotient << FILTER_P | self.read_bits(FILTER_P)?)
+    }
+}
+
+// A Golomb-coded set over the output scripts of a block and the outpoints its
+// inputs spend, keyed by the block hash. Matches are probabilistic with a
+// false positive rate of 1/FILTER_M.
+#[derive(Clone)]
+pub struct BlockFilter {
+    n: u32,
+    data: Vec<u8>,
+}
+
+fn filter_key(block_hash: &BlockHash) -> (u64, u64) {
+    let mut k0 = [0u8; 8];
+    let mut k1 = [0u8; 8];
+    k0.copy_from_slice(&block_hash.as_bytes()[0..8]);
+    k1.copy_from_slice(&block_hash.as_bytes()[8..16]);
+    (u64::from_le_bytes(k0), u64::from_le_bytes(k1))
+}
+
+// Hashes `items` into [0, n * FILTER_M), sorted.
+fn hashed_set(block_hash: &BlockHash, n: u32, items: &[Vec<u8>]) -> Vec<u64> {
+    let (k0, k1) = filter_key(block_hash);
+    let range = n as u64 * FILTER_M;
+    let mut values: Vec<u64> = items.iter()
+        .map(|item| ((siphash24(k0, k1, item) as u128 * range as u128) >> 64) as u64)
+        .collect();
+    values.sort_unstable();
+    values
+}
+
+impl BlockFilter {
+    pub fn build(algorithm: HashAlgorithm, block: &Block) -> BlockFilter {
+        let mut items: Vec<Vec<u8>> = Vec::new();
+        for tx in block.get_transactions() {
+            for input in tx.get_inputs() {
+                items.push(input.get_previous_out().to_bytes());
+            }
+            for output in tx.get_outputs() {
+                if !output.get_script().is_empty() {
+                    items.push(output.get_script().to_vec());
+                }
+            }
+        }
+        items.sort();
+        items.dedup();
+
+        let block_hash = block.get_hash(algorithm);
+        let n = items.len() as u32;
+
+        let mut writer = BitWriter::new();
+        let mut last = 0u64;
+        for value in hashed_set(&block_hash, n, &items) {
+            writer.write_golomb_rice(value - last);
+            last = value;
+        }
+
+        BlockFilter {
+            n,
+            data: writer.bytes,
+        }
+    }
+
+    pub fn len(&self) -> u32 {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_hash(&self, algorithm: HashAlgorithm) -> [u8; HASH256_BYTES] {
+        let mut hash = Hash256::new(algorithm);
+        self.encode(&mut hash).unwrap();
+
+        let mut buf = [0u8; HASH256_BYTES];
+        hash.finalize(&mut buf);
+        buf
+    }
+
+    // Whether any of `items` (output scripts or encoded outpoints) may be in
+    // the block with hash `block_hash`.
+    pub fn match_any(&self, block_hash: &BlockHash, items: &[Vec<u8>]) -> bool {
+        if self.n == 0 || items.is_empty() {
+            return false;
+        }
+
+        let queries = hashed_set(block_hash, self.n, items);
+        let mut queries = queries.iter().peekable();
+        let mut reader = BitReader::new(&self.data);
+        let mut value = 0u64;
+        for _ in 0..self.n {
+            value = match reader.read_golomb_rice().and_then(|delta| value.checked_add(delta)) {
+                Some(value) => value,
+                None => return false,
+            };
+            while let Some(&&query) = queries.peek() {
+                if query == value {
+                    return true;
+                }
+                if query > value {
+                    break;
+                }
+                queries.next();
+            }
+            if queries.peek().is_none() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+impl Encodable for BlockFilter {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u32::<LittleEndian>(self.n)?;
+        write_var_bytes(w, &self.data)
+    }
+}
+
+impl Decodable for BlockFilter {
+    fn decode<R: Read>(r: &mut R) -> io::Result<BlockFilter> {
+        Ok(BlockFilter {
+            n: r.read_u32::<LittleEndian>()?,
+            data: read_var_bytes(r)?,
+        })
+    }
+}
+
+// Commits to a filter and, through `previous`, to every filter before it.
+pub fn filter_header(algorithm: HashAlgorithm, filter: &BlockFilter, previous: &[u8; HASH256_BYTES]) -> [u8; HASH256_BYTES] {
+    let filter_hash = filter.get_hash(algorithm);
+
+    let mut hash = Hash256::new(algorithm);
+    hash.write_all(&filter_hash).unwrap();
+    hash.write_all(previous).unwrap();
+
+    let mut header = [0u8; HASH256_BYTES];
+    hash.finalize(&mut header);
+    header
+}
+
+// The filter and filter header of every block in the chain, hashed with the
+// chain's algorithm.
+pub struct FilterIndex {
+    algorithm: HashAlgorithm,
+    filters: Vec<BlockFilter>,
+    headers: Vec<[u8; HASH256_BYTES]>,
+}
+
+impl FilterIndex {
+    pub fn new(algorithm: HashAlgorithm) -> FilterIndex {
+        FilterIndex {
+            algorithm,
+            filters: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, height: usize) -> Option<(&BlockFilter, &[u8; HASH256_BYTES])> {
+        Some((self.filters.get(height)?, &self.headers[height]))
+    }
+
+    pub fn connect_block(&mut self, block: &Block) {
+        let filter = BlockFilter::build(self.algorithm, block);
+        let previous = self.headers.last().cloned().unwrap_or([0u8; HASH256_BYTES]);
+        self.headers.push(filter_header(self.algorithm, &filter, &previous));
+        self.filters.push(filter);
+    }
+
+    pub fn disconnect_block(&mut self) {
+        self.filters.pop();
+        self.headers.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::Decodable;
+    use blockchain::sample::blocks;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    #[test]
+    fn matches_what_it_was_built_from() {
+        let block = blocks().pop().unwrap();
+        let hash = block.get_hash(ALGORITHM);
+        let filter = BlockFilter::build(ALGORITHM, &block);
+        // Three outputs share two scripts, and one outpoint is spent.
+        assert_eq!(filter.len(), 3);
+
+        let spend = &block.get_transactions()[1];
+        let outpoint = spend.get_inputs()[0].get_previous_out().to_bytes();
+        let decoded = BlockFilter::from_bytes(&filter.to_bytes()).unwrap();
+        for filter in &[&filter, &decoded] {
+            assert!(filter.match_any(&hash, &[vec![0xa1, 0x1c, 0xe0]]));
+            assert!(filter.match_any(&hash, &[b"unrelated".to_vec(), outpoint.clone()]));
+            for output in spend.get_outputs() {
+                assert!(filter.match_any(&hash, &[output.get_script().to_vec()]));
+            }
+            assert!(!filter.match_any(&hash, &[b"unrelated".to_vec(), b"another".to_vec()]));
+            assert!(!filter.match_any(&hash, &[]));
+        }
+        // The items are keyed by the block hash.
+        let other = blocks()[0].get_hash(ALGORITHM);
+        assert!(!filter.match_any(&other, &[outpoint]));
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let block = blocks().pop().unwrap();
+        let mut filter = BlockFilter::build(ALGORITHM, &block);
+        // Runs of ones long enough to overflow the decoded values.
+        filter.data = vec![0xff; 64];
+        assert!(!filter.match_any(&block.get_hash(ALGORITHM), &[vec![0xa1, 0x1c, 0xe0]]));
+        filter.data.clear();
+        assert!(!filter.match_any(&block.get_hash(ALGORITHM), &[vec![0xa1, 0x1c, 0xe0]]));
+    }
+
+    #[test]
+    fn headers_chain_filters() {
+        let blocks = blocks();
+        let mut index = FilterIndex::new(ALGORITHM);
+        for block in &blocks {
+            index.connect_block(block);
+        }
+        assert_eq!(index.len(), 3);
+
+        let mut previous = [0u8; HASH256_BYTES];
+        for (height, block) in blocks.iter().enumerate() {
+            let (filter, header) = index.get(height).unwrap();
+            assert_eq!(filter.get_hash(ALGORITHM), BlockFilter::build(ALGORITHM, block).get_hash(ALGORITHM));
+            assert_eq!(*header, filter_header(ALGORITHM, filter, &previous));
+            assert_ne!(*header, filter_header(ALGORITHM, filter, header));
+            previous = *header;
+        }
+
+        index.disconnect_block();
+        assert!(index.get(2).is_none());
+        index.connect_block(&blocks[2]);
+        assert_eq!(*index.get(2).unwrap().1, previous);
+    }
+}
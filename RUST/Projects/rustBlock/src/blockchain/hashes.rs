@@ -0,0 +1,178 @@
+extern crate serde;
+
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use self::serde::de::{Deserialize, Deserializer, Error};
+use self::serde::ser::{Serialize, Serializer};
+
+use blockchain::encode::{Decodable, Encodable};
+use util::hash::HASH256_BYTES;
+use util::hex::{FromHex, ToHex};
+
+
+// A 256 bit hash of a particular kind of object. Formats as hex in byte
+// order; the alternate flag (`{:#}`) reverses the bytes, as bitcoin displays
+// hashes.
+macro_rules! hash_newtype {
+    ($name:ident) => {
+        #[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name([u8; HASH256_BYTES]);
+
+        impl $name {
+            pub fn from_bytes(bytes: [u8; HASH256_BYTES]) -> $name {
+                $name(bytes)
+            }
+
+            pub fn from_slice(bytes: &[u8]) -> Option<$name> {
+                if bytes.len() != HASH256_BYTES {
+                    return None;
+                }
+                let mut hash = [0u8; HASH256_BYTES];
+                hash.copy_from_slice(bytes);
+                Some($name(hash))
+            }
+
+            pub fn as_bytes(&self) -> &[u8; HASH256_BYTES] {
+                &self.0
+            }
+
+            pub fn is_zero(&self) -> bool {
+                self.0 == [0u8; HASH256_BYTES]
+            }
+
+            pub fn reversed(&self) -> $name {
+                let mut hash = self.0;
+                hash.reverse();
+                $name(hash)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                if f.alternate() {
+                    f.write_str(&self.reversed().0.to_hex())
+                } else {
+                    f.write_str(&self.0.to_hex())
+                }
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, concat!(stringify!($name), "({})"), self)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<$name, String> {
+                let bytes = s.from_hex().map_err(|e| e.to_string())?;
+                $name::from_slice(&bytes)
+                    .ok_or_else(|| format!("expected {} hex digits; got {}", HASH256_BYTES * 2, s.len()))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where S: Serializer
+            {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+                where D: Deserializer<'de>
+            {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map_err(D::Error::custom)
+            }
+        }
+
+        impl Encodable for $name {
+            fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                w.write_all(&self.0)
+            }
+        }
+
+        impl Decodable for $name {
+            fn decode<R: Read>(r: &mut R) -> io::Result<$name> {
+                let mut hash = [0u8; HASH256_BYTES];
+                r.read_exact(&mut hash)?;
+                Ok($name(hash))
+            }
+        }
+    }
+}
+
+hash_newtype!(BlockHash);
+hash_newtype!(TxId);
+hash_newtype!(SnapshotHash);
+hash_newtype!(AssetId);
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+
+    const HEX: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        let hash: TxId = HEX.parse().unwrap();
+        assert_eq!(hash.as_bytes()[1], 1);
+        assert_eq!(hash.to_string(), HEX);
+        assert_eq!(format!("{:?}", hash), format!("TxId({})", HEX));
+    }
+
+    #[test]
+    fn alternate_display_reverses_bytes() {
+        let hash: BlockHash = HEX.parse().unwrap();
+        let reversed = format!("{:#}", hash);
+        assert!(reversed.starts_with("1f1e1d"));
+        assert_eq!(reversed.parse::<BlockHash>().unwrap().reversed(), hash);
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        assert!("00".parse::<TxId>().is_err());
+        assert!(HEX.replace("0f", "zz").parse::<TxId>().is_err());
+        assert!(format!("{}00", HEX).parse::<TxId>().is_err());
+    }
+
+    #[test]
+    fn ordering_follows_bytes() {
+        let low = BlockHash::default();
+        let high: BlockHash = HEX.parse().unwrap();
+        assert!(low < high);
+        assert!(low.is_zero() && !high.is_zero());
+    }
+
+    #[test]
+    fn serde_uses_hex_strings() {
+        let hash: TxId = HEX.parse().unwrap();
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", HEX));
+        assert_eq!(serde_json::from_str::<TxId>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<TxId>("\"00\"").is_err());
+    }
+
+    #[test]
+    fn encoding_is_raw_bytes() {
+        let hash: BlockHash = HEX.parse().unwrap();
+        assert_eq!(&hash.to_bytes()[..], hash.as_bytes());
+        assert_eq!(BlockHash::from_bytes(*hash.as_bytes()), hash);
+        assert_eq!(BlockHash::from_slice(&hash.to_bytes()), Some(hash));
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use blockchain::{AssetId, Block, BlockUndo, OutPoint, TxId};
+use util::hash::HashAlgorithm;
+
+
+// Where a confirmed transaction lives: block height and position in the
+// block's transaction list.
+#[derive(Copy, Clone)]
+pub struct TxLocation {
+    pub height: usize,
+    pub position: usize,
+}
+
+pub struct TxIndex {
+    algorithm: HashAlgorithm,
+    locations: HashMap<TxId, TxLocation>,
+}
+
+impl TxIndex {
+    pub fn new(algorithm: HashAlgorithm) -> TxIndex {
+        TxIndex {
+            algorithm,
+            locations: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, hash: &TxId) -> Option<TxLocation> {
+        self.locations.get(hash).cloned()
+    }
+
+    pub fn connect_block(&mut self, block: &Block, height: usize) {
+        for (position, tx) in block.get_transactions().iter().enumerate() {
+            let hash = tx.get_hash(self.algorithm);
+            self.locations.insert(hash, TxLocation { height, position });
+        }
+    }
+
+    pub fn disconnect_block(&mut self, block: &Block) {
+        for tx in block.get_transactions() {
+            self.locations.remove(&tx.get_hash(self.algorithm));
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum HistoryKind {
+    // The script received `amount` in output `out`.
+    Output,
+    // Output `out` paying the script was spent by input `input` of `tx`.
+    Spend { tx: TxId, input: usize },
+}
+
+#[derive(Copy, Clone)]
+pub struct HistoryEntry {
+    pub height: usize,
+    pub out: OutPoint,
+    pub amount: u64,
+    pub asset: AssetId,
+    pub kind: HistoryKind,
+}
+
+// Output script to every output paying it and every spend of those outputs,
+// in chain order.
+pub struct AddressIndex {
+    algorithm: HashAlgorithm,
+    history: HashMap<Vec<u8>, Vec<HistoryEntry>>,
+    entries: usize,
+}
+
+impl AddressIndex {
+    pub fn new(algorithm: HashAlgorithm) -> AddressIndex {
+        AddressIndex {
+            algorithm,
+            history: HashMap::new(),
+            entries: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_entry_count(&self) -> usize {
+        self.entries
+    }
+
+    pub fn get_history(&self, script: &[u8]) -> &[HistoryEntry] {
+        self.history.get(script).map(|h| &h[..]).unwrap_or(&[])
+    }
+
+    fn push(&mut self, script: &[u8], entry: HistoryEntry) {
+        self.history.entry(script.to_vec()).or_default().push(entry);
+        self.entries += 1;
+    }
+
+    pub fn connect_block(&mut self, block: &Block, height: usize, undo: &BlockUndo) {
+        let mut spent = undo.get_spent().iter();
+        for tx in block.get_transactions() {
+            let hash = tx.get_hash(self.algorithm);
+
+            for input in 0..tx.get_inputs().len() {
+                let (out, coin) = spent.next().unwrap();
+                let output = coin.get_output();
+                self.push(output.get_script(), HistoryEntry {
+                    height,
+                    out: *out,
+                    amount: output.get_amount(),
+                    asset: output.get_asset(),
+                    kind: HistoryKind::Spend { tx: hash, input },
+                });
+            }
+
+            for (index, output) in tx.get_outputs().iter().enumerate() {
+                self.push(output.get_script(), HistoryEntry {
+                    height,
+                    out: OutPoint::new(hash, index as u32),
+                    amount: output.get_amount(),
+                    asset: output.get_asset(),
+                    kind: HistoryKind::Output,
+                });
+            }
+        }
+    }
+
+    // Entries are appended in chain order, so the tip's are at the end.
+    pub fn disconnect_block(&mut self, block: &Block, height: usize, undo: &BlockUndo) {
+        let scripts = block.get_transactions().iter()
+            .flat_map(|tx| tx.get_outputs().iter().map(|o| o.get_script()))
+            .chain(undo.get_spent().iter().map(|(_, c)| c.get_output().get_script()));
+
+        for script in scripts {
+            let emptied = match self.history.get_mut(script) {
+                Some(history) => {
+                    while history.last().is_some_and(|e| e.height == height) {
+                        history.pop();
+                        self.entries -= 1;
+                    }
+                    history.is_empty()
+                },
+                None => false,
+            };
+            if emptied {
+                self.history.remove(script);
+            }
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+
+use blockchain::{check_transaction_limits, Block, BlockChain, ChainEvent, EventBus, EvictReason, OutPoint, Policy, RejectReason,
+                 Transaction, TxId};
+use blockchain::encode::Encodable;
+use util::hash::HashAlgorithm;
+use util::time::{Clock, SystemClock};
+
+
+pub struct MempoolEntry {
+    tx: Transaction,
+    fee: u64,
+    size: usize,
+    time: u64,
+}
+
+impl MempoolEntry {
+    pub fn get_transaction(&self) -> &Transaction {
+        &self.tx
+    }
+
+    pub fn get_fee(&self) -> u64 {
+        self.fee
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_time(&self) -> u64 {
+        self.time
+    }
+}
+
+// Transactions waiting to be included in a block. Only spends of confirmed
+// outputs are accepted, and no two entries may spend the same output.
+pub struct Mempool {
+    entries: HashMap<TxId, MempoolEntry>,
+    spent: HashMap<OutPoint, TxId>,
+    policy: Policy,
+    events: Arc<EventBus>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool::with_policy(Policy::new())
+    }
+
+    pub fn with_policy(policy: Policy) -> Mempool {
+        Mempool {
+            entries: HashMap::new(),
+            spent: HashMap::new(),
+            policy,
+            events: Arc::new(EventBus::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    // Entries are stamped with the time of this clock, the system's unless
+    // set otherwise.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // Accepted and evicted transactions are published here. Sharing the
+    // chain's bus puts them in one stream with its blocks.
+    pub fn set_events(&mut self, events: Arc<EventBus>) {
+        self.events = events;
+    }
+
+    pub fn get_events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    pub fn get_policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, hash: &TxId) -> Option<&MempoolEntry> {
+        self.entries.get(hash)
+    }
+
+    // In no particular order.
+    pub fn iter(&self) -> hash_map::Values<'_, TxId, MempoolEntry> {
+        self.entries.values()
+    }
+
+    pub fn get_size_bytes(&self) -> usize {
+        self.entries.values().map(|e| e.size).sum()
+    }
+
+    pub fn get_total_fee(&self) -> u64 {
+        self.entries.values().fold(0u64, |sum, e| sum.saturating_add(e.fee))
+    }
+
+    pub fn accept(&mut self, tx: Transaction, chain: &BlockChain) -> Result<TxId, RejectReason> {
+        let algorithm = chain.get_params().get_hash_algorithm();
+        let hash = tx.get_hash(algorithm);
+
+        if self.entries.contains_key(&hash) {
+            return Err(RejectReason::AlreadyInMempool(hash));
+        }
+        if chain.find_transaction(&hash).is_some() {
+            return Err(RejectReason::AlreadyInChain(hash));
+        }
+        if tx.is_coinbase() {
+            return Err(RejectReason::Coinbase(hash));
+        }
+        check_transaction_limits(&tx, chain.get_params())?;
+        self.policy.check_transaction(algorithm, &tx)?;
+
+        for input in tx.get_inputs() {
+            if let Some(&other) = self.spent.get(input.get_previous_out()) {
+                return Err(RejectReason::Conflict { out: *input.get_previous_out(), other });
+            }
+        }
+        // Lock times must allow the transaction into the next block, which
+        // is assumed to be no older than the tip.
+        let tip_time = chain.len().checked_sub(1)
+            .and_then(|tip| chain.get_header(tip))
+            .map_or(0, |header| header.get_timestamp());
+        let fee = chain.get_utxos().check_transaction(algorithm, &tx, chain.len(), tip_time)?;
+        let spent: Vec<_> = tx.get_inputs().iter()
+            .map(|i| chain.get_utxos().get(i.get_previous_out()).unwrap())
+            .collect();
+        chain.get_validator().verify_transaction(algorithm, &tx, &spent)?;
+        self.policy.check_fee(algorithm, &tx, fee)?;
+
+        for input in tx.get_inputs() {
+            self.spent.insert(*input.get_previous_out(), hash);
+        }
+        let entry = MempoolEntry {
+            size: tx.to_bytes().len(),
+            tx,
+            fee,
+            time: self.clock.now(),
+        };
+        self.entries.insert(hash, entry);
+        self.events.publish(ChainEvent::TxAccepted { txid: hash });
+        Ok(hash)
+    }
+
+    fn evict(&mut self, hash: &TxId, reason: EvictReason) {
+        if let Some(entry) = self.entries.remove(hash) {
+            for input in entry.tx.get_inputs() {
+                self.spent.remove(input.get_previous_out());
+            }
+            self.events.publish(ChainEvent::TxEvicted { txid: *hash, reason });
+        }
+    }
+
+    // Evicts the transactions `block` confirms and those spending an output
+    // it spends.
+    pub fn connect_block(&mut self, algorithm: HashAlgorithm, block: &Block) {
+        for tx in block.get_transactions() {
+            self.evict(&tx.get_hash(algorithm), EvictReason::Confirmed);
+            for input in tx.get_inputs() {
+                if let Some(&other) = self.spent.get(input.get_previous_out()) {
+                    self.evict(&other, EvictReason::Conflict);
+                }
+            }
+        }
+    }
+
+    // Evicts the transactions spending outputs that are no longer in the
+    // chain's UTXO set, after blocks were disconnected from it.
+    pub fn disconnect_block(&mut self, chain: &BlockChain) {
+        let missing: Vec<TxId> = self.spent.iter()
+            .filter(|&(out, _)| !chain.get_utxos().contains(out))
+            .map(|(_, &hash)| hash)
+            .collect();
+        for hash in missing {
+            self.evict(&hash, EvictReason::MissingInputs);
+        }
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Mempool {
+        Mempool::new()
+    }
+}
+
+// Keeps `mempool` in step with the blocks connected to and disconnected from
+// `chain`, and publishes its events on the chain's bus. The thread holds
+// neither, and ends once both are dropped and the bus with them.
+pub fn follow_chain(mempool: Arc<Mutex<Mempool>>, chain: Arc<RwLock<BlockChain>>) -> thread::JoinHandle<()> {
+    let mut events = {
+        let chain = chain.read().unwrap();
+        mempool.lock().unwrap().set_events(chain.get_events().clone());
+        chain.get_events().subscribe()
+    };
+    let (mempool, chain) = (Arc::downgrade(&mempool), Arc::downgrade(&chain));
+    thread::spawn(move || loop {
+        let event = events.recv();
+        let (mempool, chain) = match (mempool.upgrade(), chain.upgrade()) {
+            (Some(mempool), Some(chain)) => (mempool, chain),
+            _ => return,
+        };
+        let chain = chain.read().unwrap();
+        let mut mempool = mempool.lock().unwrap();
+        match event {
+            // The block may have been disconnected again since.
+            Ok(ChainEvent::BlockConnected { hash, .. }) => {
+                if let Some(block) = chain.get_block_by_hash(&hash) {
+                    mempool.connect_block(chain.get_hash_algorithm(), block);
+                }
+            },
+            Ok(ChainEvent::BlockDisconnected { .. }) => mempool.disconnect_block(&chain),
+            Ok(_) => {},
+            // The bus dropped the thread for falling behind. Evicting what
+            // no longer spends unspent outputs catches up with the blocks
+            // missed, confirmed transactions included.
+            Err(_) => {
+                events = chain.get_events().subscribe();
+                mempool.disconnect_block(&chain);
+            },
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+    use blockchain::{BlockHash, SUBSCRIBER_BUFFER};
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn block(previous: BlockHash, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block::new();
+        block.set_previous(previous);
+        for tx in transactions {
+            block.add_transaction(tx);
+        }
+        block.update_merkle_root(ALGORITHM);
+        block
+    }
+
+    fn coinbase(tag: u64) -> Transaction {
+        let mut tx = Transaction::new();
+        tx.set_timestamp(tag);
+        tx.add_output(50, b"s");
+        tx
+    }
+
+    fn spend(out: OutPoint, amount: u64) -> Transaction {
+        let mut tx = Transaction::new();
+        tx.add_input(out);
+        tx.add_output(amount, b"t");
+        tx
+    }
+
+    fn next(events: &Receiver<ChainEvent>) -> ChainEvent {
+        events.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn follows_connected_and_disconnected_blocks() {
+        let genesis = block(BlockHash::default(), vec![coinbase(0)]);
+        let funding = block(genesis.get_hash(ALGORITHM), vec![coinbase(1)]);
+        let coin = OutPoint::new(funding.get_transactions()[0].get_hash(ALGORITHM), 0);
+        let chain = Arc::new(RwLock::new(BlockChain::new()));
+        chain.write().unwrap().reorganize(vec![genesis.clone(), funding.clone()]).unwrap();
+
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        follow_chain(mempool.clone(), chain.clone());
+        let events = chain.read().unwrap().get_events().subscribe();
+
+        let tx = spend(coin, 40);
+        let hash = mempool.lock().unwrap().accept(tx.clone(), &chain.read().unwrap()).unwrap();
+        assert_eq!(next(&events), ChainEvent::TxAccepted { txid: hash });
+        let conflict = mempool.lock().unwrap().accept(spend(coin, 45), &chain.read().unwrap()).unwrap_err();
+        assert_eq!(conflict, RejectReason::Conflict { out: coin, other: hash });
+
+        // A block spending the same coin differently evicts it.
+        let double = block(funding.get_hash(ALGORITHM), vec![coinbase(2), spend(coin, 30)]);
+        chain.write().unwrap().reorganize(vec![double.clone()]).unwrap();
+        assert_eq!(next(&events), ChainEvent::BlockConnected { height: 2, hash: double.get_hash(ALGORITHM) });
+        assert_eq!(next(&events), ChainEvent::TxEvicted { txid: hash, reason: EvictReason::Conflict });
+
+        // Once the funding block is reorganized away, a spend of its coinbase
+        // has nothing to spend.
+        chain.write().unwrap().disconnect_tip();
+        assert_eq!(next(&events), ChainEvent::BlockDisconnected { height: 2, hash: double.get_hash(ALGORITHM) });
+        let hash = mempool.lock().unwrap().accept(tx, &chain.read().unwrap()).unwrap();
+        assert_eq!(next(&events), ChainEvent::TxAccepted { txid: hash });
+        let first = block(genesis.get_hash(ALGORITHM), vec![coinbase(3)]);
+        let second = block(first.get_hash(ALGORITHM), vec![coinbase(4)]);
+        chain.write().unwrap().reorganize(vec![first, second]).unwrap();
+        assert_eq!(next(&events), ChainEvent::BlockDisconnected { height: 1, hash: funding.get_hash(ALGORITHM) });
+        assert!(matches!(next(&events), ChainEvent::BlockConnected { height: 1, .. }));
+        assert!(matches!(next(&events), ChainEvent::BlockConnected { height: 2, .. }));
+        assert_eq!(next(&events), ChainEvent::TxEvicted { txid: hash, reason: EvictReason::MissingInputs });
+        assert!(mempool.lock().unwrap().is_empty());
+    }
+
+    // Polls `done` for up to five seconds.
+    fn eventually<F: Fn() -> bool>(done: F) -> bool {
+        (0..500).any(|_| done() || {
+            thread::sleep(Duration::from_millis(10));
+            false
+        })
+    }
+
+    #[test]
+    fn follower_catches_up_and_ends() {
+        let genesis = block(BlockHash::default(), vec![coinbase(0)]);
+        let funding = block(genesis.get_hash(ALGORITHM), vec![coinbase(1)]);
+        let coin = OutPoint::new(funding.get_transactions()[0].get_hash(ALGORITHM), 0);
+        let chain = Arc::new(RwLock::new(BlockChain::new()));
+        chain.write().unwrap().reorganize(vec![genesis, funding.clone()]).unwrap();
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let follower = follow_chain(mempool.clone(), chain.clone());
+        mempool.lock().unwrap().accept(spend(coin, 40), &chain.read().unwrap()).unwrap();
+
+        // Blocked on the chain, the follower falls behind and is dropped, and
+        // so misses the block confirming the spend. It may have taken one
+        // event off its channel before blocking.
+        {
+            let mut chain = chain.write().unwrap();
+            for _ in 0..SUBSCRIBER_BUFFER + 2 {
+                chain.get_events().publish(ChainEvent::TxAccepted { txid: TxId::default() });
+            }
+            assert_eq!(chain.get_events().get_subscriber_count(), 0);
+            chain.reorganize(vec![block(funding.get_hash(ALGORITHM), vec![coinbase(2), spend(coin, 40)])]).unwrap();
+        }
+        assert!(eventually(|| mempool.lock().unwrap().is_empty()));
+        assert_eq!(chain.read().unwrap().get_events().get_subscriber_count(), 1);
+
+        drop(chain);
+        drop(mempool);
+        assert!(eventually(|| follower.is_finished()));
+    }
+}
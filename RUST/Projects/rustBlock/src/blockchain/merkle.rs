@@ -0,0 +1,172 @@
+use std::io::Write;
+
+use util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
+
+
+// Leaves and inner nodes are hashed with different prefixes, so no inner
+// node can pass for a leaf, and a level with an odd number of nodes pairs the
+// last one with `PADDING` rather than with itself, so no two lists of leaves
+// share a root (CVE-2012-2459). No node hashes to all zeros.
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const PADDING: [u8; HASH256_BYTES] = [0u8; HASH256_BYTES];
+
+fn hash_leaf(algorithm: HashAlgorithm, leaf: &[u8; HASH256_BYTES]) -> [u8; HASH256_BYTES] {
+    let mut hash = Hash256::new(algorithm);
+    hash.write_all(&[LEAF_PREFIX]).unwrap();
+    hash.write_all(leaf).unwrap();
+
+    let mut node = [0u8; HASH256_BYTES];
+    hash.finalize(&mut node);
+    node
+}
+
+fn hash_pair(algorithm: HashAlgorithm, left: &[u8; HASH256_BYTES], right: &[u8; HASH256_BYTES]) -> [u8; HASH256_BYTES] {
+    let mut hash = Hash256::new(algorithm);
+    hash.write_all(&[NODE_PREFIX]).unwrap();
+    hash.write_all(left).unwrap();
+    hash.write_all(right).unwrap();
+
+    let mut parent = [0u8; HASH256_BYTES];
+    hash.finalize(&mut parent);
+    parent
+}
+
+fn leaf_level(algorithm: HashAlgorithm, leaves: &[[u8; HASH256_BYTES]]) -> Vec<[u8; HASH256_BYTES]> {
+    leaves.iter().map(|leaf| hash_leaf(algorithm, leaf)).collect()
+}
+
+// Hashes one level of the tree.
+fn parent_level(algorithm: HashAlgorithm, level: &[[u8; HASH256_BYTES]]) -> Vec<[u8; HASH256_BYTES]> {
+    level.chunks(2)
+        .map(|pair| hash_pair(algorithm, &pair[0], pair.get(1).unwrap_or(&PADDING)))
+        .collect()
+}
+
+// The root of the tree over `leaves`. An empty tree has an all zero root.
+pub fn merkle_root(algorithm: HashAlgorithm, leaves: &[[u8; HASH256_BYTES]]) -> [u8; HASH256_BYTES] {
+    if leaves.is_empty() {
+        return [0u8; HASH256_BYTES];
+    }
+
+    let mut level = leaf_level(algorithm, leaves);
+    while level.len() > 1 {
+        level = parent_level(algorithm, &level);
+    }
+    level[0]
+}
+
+// The siblings on the path from leaf `index` to the root.
+#[derive(Clone)]
+pub struct MerkleBranch {
+    index: u32,
+    hashes: Vec<[u8; HASH256_BYTES]>,
+}
+
+impl MerkleBranch {
+    pub fn new(index: u32, hashes: Vec<[u8; HASH256_BYTES]>) -> MerkleBranch {
+        MerkleBranch {
+            index,
+            hashes,
+        }
+    }
+
+    pub fn build(algorithm: HashAlgorithm, leaves: &[[u8; HASH256_BYTES]], index: usize) -> MerkleBranch {
+        assert!(index < leaves.len());
+
+        let mut hashes = Vec::new();
+        let mut level = leaf_level(algorithm, leaves);
+        let mut position = index;
+        while level.len() > 1 {
+            let sibling = if position & 1 == 0 {
+                level.get(position + 1).unwrap_or(&PADDING)
+            } else {
+                &level[position - 1]
+            };
+            hashes.push(*sibling);
+            level = parent_level(algorithm, &level);
+            position /= 2;
+        }
+        MerkleBranch::new(index as u32, hashes)
+    }
+
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn get_hashes(&self) -> &[[u8; HASH256_BYTES]] {
+        &self.hashes
+    }
+
+    // Whether the branch proves `leaf` is in the tree with root `root`.
+    pub fn verify(&self, algorithm: HashAlgorithm, leaf: &[u8; HASH256_BYTES], root: &[u8]) -> bool {
+        // Index bits above the branch depth would be silently ignored.
+        if self.hashes.len() < 32 && self.index >> self.hashes.len() != 0 {
+            return false;
+        }
+        self.compute_root(algorithm, leaf) == root
+    }
+
+    pub fn compute_root(&self, algorithm: HashAlgorithm, leaf: &[u8; HASH256_BYTES]) -> [u8; HASH256_BYTES] {
+        let mut node = hash_leaf(algorithm, leaf);
+        let mut position = self.index;
+        for sibling in &self.hashes {
+            node = if position & 1 == 0 {
+                hash_pair(algorithm, &node, sibling)
+            } else {
+                hash_pair(algorithm, sibling, &node)
+            };
+            position /= 2;
+        }
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn leaves(count: u8) -> Vec<[u8; HASH256_BYTES]> {
+        (1..=count).map(|i| [i; HASH256_BYTES]).collect()
+    }
+
+    #[test]
+    fn branches_verify_for_every_leaf() {
+        for count in 1..=9 {
+            let leaves = leaves(count);
+            let root = merkle_root(ALGORITHM, &leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let branch = MerkleBranch::build(ALGORITHM, &leaves, index);
+                assert!(branch.verify(ALGORITHM, leaf, &root), "leaf {} of {}", index, count);
+                assert!(!branch.verify(ALGORITHM, &[0xff; HASH256_BYTES], &root));
+                let moved = MerkleBranch::new(branch.get_index() ^ 1, branch.get_hashes().to_vec());
+                assert!(count == 1 || !moved.verify(ALGORITHM, leaf, &root));
+            }
+        }
+    }
+
+    #[test]
+    fn roots_are_unambiguous() {
+        // A single leaf is not its own root, and no inner node is a leaf.
+        let three = leaves(3);
+        assert_ne!(merkle_root(ALGORITHM, &three[..1]), three[0]);
+        let inner = hash_pair(ALGORITHM, &hash_leaf(ALGORITHM, &three[0]), &hash_leaf(ALGORITHM, &three[1]));
+        assert_ne!(merkle_root(ALGORITHM, &three[..2]), merkle_root(ALGORITHM, &[inner]));
+
+        // Repeating the last leaf of an odd level changes the root.
+        let mut four = three.clone();
+        four.push(three[2]);
+        assert_ne!(merkle_root(ALGORITHM, &three), merkle_root(ALGORITHM, &four));
+        let mut six = leaves(5);
+        six.push(six[4]);
+        assert_ne!(merkle_root(ALGORITHM, &leaves(5)), merkle_root(ALGORITHM, &six));
+
+        // A leaf past the end cannot be proved with the padding.
+        let branch = MerkleBranch::build(ALGORITHM, &three, 2);
+        let past = MerkleBranch::new(3, vec![hash_leaf(ALGORITHM, &three[2]), branch.get_hashes()[1]]);
+        assert!(!past.verify(ALGORITHM, &PADDING, &merkle_root(ALGORITHM, &three)));
+        assert_eq!(merkle_root(ALGORITHM, &[]), [0u8; HASH256_BYTES]);
+    }
+}
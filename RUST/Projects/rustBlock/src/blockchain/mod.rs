@@ -1,11 +1,53 @@
 
+#[cfg(test)]
+pub mod arbitrary;
+mod asset;
 mod block;
 mod chain;
+mod encode;
+mod error;
+mod events;
+mod fees;
+mod filter;
+mod hashes;
+mod index;
+mod mempool;
+mod merkle;
+mod params;
+mod policy;
+#[cfg(test)]
+pub mod sample;
+mod snapshot;
+mod state;
+mod stats;
 mod transaction;
+mod utxo;
+mod validation;
+mod verify;
 
-pub use self::block::Block;
-pub use self::chain::BlockChain;
-pub use self::transaction::OutPoint;
-pub use self::transaction::TransactionInput;
-pub use self::transaction::TransactionOutput;
-pub use self::transaction::Transaction;
+pub use self::asset::{check_conservation, Balances};
+pub use self::block::{Block, BlockHeader, STATE_ROOT_VERSION};
+pub use self::chain::{append_document, BlockChain};
+pub use self::encode::{Decodable, Encodable};
+pub use self::error::{BuildError, ChainError};
+pub use self::events::{ChainEvent, EventBus, EvictReason, SUBSCRIBER_BUFFER};
+pub use self::fees::{fee_rate, BlockFees, FeeEstimator, MAX_FEE_BLOCKS};
+pub use self::filter::{filter_header, BlockFilter, FilterIndex};
+pub use self::hashes::{AssetId, BlockHash, SnapshotHash, TxId};
+pub use self::index::{AddressIndex, HistoryEntry, HistoryKind, TxIndex, TxLocation};
+pub use self::mempool::{follow_chain, Mempool};
+pub use self::merkle::{merkle_root, MerkleBranch};
+pub use self::params::ChainParams;
+pub use self::policy::{check_block_limits, check_transaction_limits, Policy, RejectReason};
+pub use self::snapshot::{verify_history, UtxoSnapshot};
+pub use self::state::{check_writes, StateProof, StateStore, MAX_STATE_KEY_SIZE, MAX_STATE_VALUE_SIZE};
+pub use self::stats::ChainStats;
+pub use self::transaction::{LOCK_TIME_THRESHOLD, SEQUENCE_DISABLE_FLAG, SEQUENCE_FINAL, SEQUENCE_MASK, SEQUENCE_TYPE_FLAG, STATE_VERSION};
+pub use self::transaction::{ANCHOR_PREFIX, ASSET_VERSION, MAX_ASSET_NAME_SIZE};
+pub use self::transaction::{OutPoint, OutPointBuilder, RelativeLock};
+pub use self::transaction::{TransactionInput, TransactionInputBuilder};
+pub use self::transaction::{Issuance, StateWrite, TransactionOutput};
+pub use self::transaction::{Transaction, TransactionBuilder};
+pub use self::utxo::{BlockUndo, Coin, UtxoSet};
+pub use self::validation::{check_block, AcceptAll, InputVerifier, Validator};
+pub use self::verify::{verify_chain, Problem, ProblemKind, VerifyReport};
@@ -0,0 +1,111 @@
+use blockchain::SnapshotHash;
+use util::hash::HashAlgorithm;
+
+
+// The hash of the UTXO snapshot taken at `height`, trusted to start from.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UtxoCheckpoint {
+    height: usize,
+    hash: SnapshotHash,
+}
+
+// Consensus parameters that differ between chains. A chain file may set them
+// in a YAML document before its first block.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainParams {
+    #[serde(default)]
+    hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    utxo_checkpoints: Vec<UtxoCheckpoint>,
+    #[serde(default = "default_max_block_size")]
+    max_block_size: usize,
+    #[serde(default = "default_max_tx_inputs")]
+    max_tx_inputs: usize,
+    #[serde(default = "default_max_tx_outputs")]
+    max_tx_outputs: usize,
+    // Outputs worth less are invalid. Zero allows any amount.
+    #[serde(default)]
+    dust_threshold: u64,
+}
+
+// Sizes are of the binary encoding, in bytes.
+fn default_max_block_size() -> usize {
+    1_000_000
+}
+
+fn default_max_tx_inputs() -> usize {
+    10_000
+}
+
+fn default_max_tx_outputs() -> usize {
+    10_000
+}
+
+impl ChainParams {
+    pub fn new() -> ChainParams {
+        ChainParams {
+            hash_algorithm: HashAlgorithm::default(),
+            utxo_checkpoints: Vec::new(),
+            max_block_size: default_max_block_size(),
+            max_tx_inputs: default_max_tx_inputs(),
+            max_tx_outputs: default_max_tx_outputs(),
+            dust_threshold: 0,
+        }
+    }
+
+    pub fn set_hash_algorithm(&mut self, algorithm: HashAlgorithm) {
+        self.hash_algorithm = algorithm;
+    }
+
+    pub fn get_hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    pub fn add_utxo_checkpoint(&mut self, height: usize, hash: SnapshotHash) {
+        self.utxo_checkpoints.push(UtxoCheckpoint { height, hash });
+    }
+
+    pub fn get_utxo_checkpoint(&self, height: usize) -> Option<SnapshotHash> {
+        self.utxo_checkpoints.iter().find(|c| c.height == height).map(|c| c.hash)
+    }
+
+    pub fn set_max_block_size(&mut self, size: usize) {
+        self.max_block_size = size;
+    }
+
+    pub fn get_max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    pub fn set_max_tx_inputs(&mut self, count: usize) {
+        self.max_tx_inputs = count;
+    }
+
+    pub fn get_max_tx_inputs(&self) -> usize {
+        self.max_tx_inputs
+    }
+
+    pub fn set_max_tx_outputs(&mut self, count: usize) {
+        self.max_tx_outputs = count;
+    }
+
+    pub fn get_max_tx_outputs(&self) -> usize {
+        self.max_tx_outputs
+    }
+
+    pub fn set_dust_threshold(&mut self, amount: u64) {
+        self.dust_threshold = amount;
+    }
+
+    pub fn get_dust_threshold(&self) -> u64 {
+        self.dust_threshold
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> ChainParams {
+        ChainParams::new()
+    }
+}
@@ -0,0 +1,256 @@
+use std::error;
+use std::fmt;
+
+use blockchain::{fee_rate, Block, ChainParams, Encodable, OutPoint, Transaction, TxId, ASSET_VERSION};
+use util::hash::HashAlgorithm;
+
+
+// Why a block or transaction was turned away.
+// The limit variants are consensus rules from `ChainParams`; the policy
+// variants only keep a transaction out of the mempool, and a block may
+// still contain it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RejectReason {
+    // The encoded block is larger than `ChainParams::get_max_block_size`.
+    BlockTooLarge { size: usize, max: usize },
+    // The transaction has more inputs than `ChainParams::get_max_tx_inputs`.
+    TooManyInputs { tx: TxId, count: usize, max: usize },
+    // The transaction has more outputs than `ChainParams::get_max_tx_outputs`.
+    TooManyOutputs { tx: TxId, count: usize, max: usize },
+    // A native output pays less than the dust threshold of the chain parameters
+    // or the policy.
+    Dust { tx: TxId, index: usize, amount: u64, min: u64 },
+
+    // The version is above the policy's `max_version`.
+    NonStandardVersion { tx: TxId, version: u32 },
+    // The encoded transaction is larger than the policy's `max_tx_size`.
+    TxTooLarge { tx: TxId, size: usize, max: usize },
+    // The fee rate is below the policy's `min_relay_fee_rate`.
+    FeeTooLow { tx: TxId, rate: u64, min: u64 },
+
+    // The mempool already holds the transaction.
+    AlreadyInMempool(TxId),
+    // The transaction index finds the transaction in a block.
+    AlreadyInChain(TxId),
+    // A coinbase only belongs in a block.
+    Coinbase(TxId),
+    // The input spends an output a mempool transaction already spends.
+    Conflict { out: OutPoint, other: TxId },
+    // Spends the UTXO set or the input verifier does not allow.
+    Invalid(String),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RejectReason::BlockTooLarge { size, max } =>
+                write!(f, "block of {} bytes exceeds {}", size, max),
+            RejectReason::TooManyInputs { tx, count, max } =>
+                write!(f, "tx {} has {} inputs; at most {} allowed", tx, count, max),
+            RejectReason::TooManyOutputs { tx, count, max } =>
+                write!(f, "tx {} has {} outputs; at most {} allowed", tx, count, max),
+            RejectReason::Dust { tx, index, amount, min } =>
+                write!(f, "tx {} output {} of {} is below the dust threshold {}", tx, index, amount, min),
+            RejectReason::NonStandardVersion { tx, version } =>
+                write!(f, "tx {} has non-standard version {}", tx, version),
+            RejectReason::TxTooLarge { tx, size, max } =>
+                write!(f, "tx {} of {} bytes exceeds {}", tx, size, max),
+            RejectReason::FeeTooLow { tx, rate, min } =>
+                write!(f, "tx {} fee rate {} is below the minimum {}", tx, rate, min),
+            RejectReason::AlreadyInMempool(tx) => write!(f, "tx {} already in mempool", tx),
+            RejectReason::AlreadyInChain(tx) => write!(f, "tx {} already in chain", tx),
+            RejectReason::Coinbase(tx) => write!(f, "tx {} has no inputs", tx),
+            RejectReason::Conflict { ref out, other } =>
+                write!(f, "input {} already spent by mempool tx {}", out, other),
+            RejectReason::Invalid(ref message) => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for RejectReason {}
+
+impl From<String> for RejectReason {
+    fn from(message: String) -> RejectReason {
+        RejectReason::Invalid(message)
+    }
+}
+
+impl From<RejectReason> for String {
+    fn from(reason: RejectReason) -> String {
+        reason.to_string()
+    }
+}
+
+// Issued assets have units of their own, so only native outputs count, and
+// anchors carry no value by design.
+fn check_dust(algorithm: HashAlgorithm, tx: &Transaction, min: u64) -> Result<(), RejectReason> {
+    for (index, output) in tx.get_outputs().iter().enumerate() {
+        if output.is_native() && !output.is_anchor() && output.get_amount() < min {
+            return Err(RejectReason::Dust { tx: tx.get_hash(algorithm), index, amount: output.get_amount(), min });
+        }
+    }
+    Ok(())
+}
+
+// The consensus limits on a single transaction.
+pub fn check_transaction_limits(tx: &Transaction, params: &ChainParams) -> Result<(), RejectReason> {
+    let algorithm = params.get_hash_algorithm();
+    let inputs = tx.get_inputs().len();
+    if inputs > params.get_max_tx_inputs() {
+        return Err(RejectReason::TooManyInputs { tx: tx.get_hash(algorithm), count: inputs, max: params.get_max_tx_inputs() });
+    }
+    let outputs = tx.get_outputs().len();
+    if outputs > params.get_max_tx_outputs() {
+        return Err(RejectReason::TooManyOutputs { tx: tx.get_hash(algorithm), count: outputs, max: params.get_max_tx_outputs() });
+    }
+    check_dust(algorithm, tx, params.get_dust_threshold())
+}
+
+// The consensus limits on a block and each of its transactions.
+pub fn check_block_limits(block: &Block, params: &ChainParams) -> Result<(), RejectReason> {
+    let size = block.to_bytes().len();
+    if size > params.get_max_block_size() {
+        return Err(RejectReason::BlockTooLarge { size, max: params.get_max_block_size() });
+    }
+    for tx in block.get_transactions() {
+        check_transaction_limits(tx, params)?;
+    }
+    Ok(())
+}
+
+// Relay rules the mempool applies on top of the consensus limits. Blocks are
+// not held to them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    max_version: u32,
+    max_tx_size: usize,
+    // Per 1000 bytes, as `fee_rate` computes it.
+    min_relay_fee_rate: u64,
+    dust_threshold: u64,
+}
+
+impl Policy {
+    pub fn new() -> Policy {
+        Policy {
+            max_version: ASSET_VERSION,
+            max_tx_size: 100_000,
+            min_relay_fee_rate: 1,
+            dust_threshold: 1,
+        }
+    }
+
+    pub fn set_max_tx_size(&mut self, size: usize) {
+        self.max_tx_size = size;
+    }
+
+    pub fn get_max_tx_size(&self) -> usize {
+        self.max_tx_size
+    }
+
+    pub fn set_min_relay_fee_rate(&mut self, rate: u64) {
+        self.min_relay_fee_rate = rate;
+    }
+
+    pub fn get_min_relay_fee_rate(&self) -> u64 {
+        self.min_relay_fee_rate
+    }
+
+    pub fn set_dust_threshold(&mut self, amount: u64) {
+        self.dust_threshold = amount;
+    }
+
+    pub fn get_dust_threshold(&self) -> u64 {
+        self.dust_threshold
+    }
+
+    // Checks that need nothing but the transaction itself.
+    pub fn check_transaction(&self, algorithm: HashAlgorithm, tx: &Transaction) -> Result<(), RejectReason> {
+        if tx.get_version() == 0 || tx.get_version() > self.max_version {
+            return Err(RejectReason::NonStandardVersion { tx: tx.get_hash(algorithm), version: tx.get_version() });
+        }
+        let size = tx.to_bytes().len();
+        if size > self.max_tx_size {
+            return Err(RejectReason::TxTooLarge { tx: tx.get_hash(algorithm), size, max: self.max_tx_size });
+        }
+        check_dust(algorithm, tx, self.dust_threshold)
+    }
+
+    pub fn check_fee(&self, algorithm: HashAlgorithm, tx: &Transaction, fee: u64) -> Result<(), RejectReason> {
+        let rate = fee_rate(fee, tx.to_bytes().len());
+        if rate < self.min_relay_fee_rate {
+            return Err(RejectReason::FeeTooLow { tx: tx.get_hash(algorithm), rate, min: self.min_relay_fee_rate });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn spend(outputs: &[u64]) -> Transaction {
+        let mut tx = Transaction::new();
+        tx.add_input(OutPoint::new(TxId::default(), 0));
+        for &amount in outputs {
+            tx.add_output(amount, &[]);
+        }
+        tx
+    }
+
+    #[test]
+    fn consensus_limits() {
+        let mut params = ChainParams::new();
+        params.set_max_tx_outputs(2);
+        params.set_dust_threshold(10);
+        assert_eq!(check_transaction_limits(&spend(&[10, 20]), &params), Ok(()));
+        match check_transaction_limits(&spend(&[10, 20, 30]), &params) {
+            Err(RejectReason::TooManyOutputs { count: 3, max: 2, .. }) => {},
+            other => panic!("{:?}", other),
+        }
+        match check_transaction_limits(&spend(&[10, 9]), &params) {
+            Err(RejectReason::Dust { index: 1, amount: 9, .. }) => {},
+            other => panic!("{:?}", other),
+        }
+        params.set_max_tx_inputs(0);
+        match check_transaction_limits(&spend(&[10]), &params) {
+            Err(RejectReason::TooManyInputs { count: 1, max: 0, .. }) => {},
+            other => panic!("{:?}", other),
+        }
+
+        let mut block = Block::new();
+        block.add_transaction(spend(&[10]));
+        params.set_max_block_size(block.to_bytes().len() - 1);
+        match check_block_limits(&block, &params) {
+            Err(RejectReason::BlockTooLarge { .. }) => {},
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn relay_policy() {
+        let policy = Policy::new();
+        let tx = spend(&[100]);
+        assert_eq!(policy.check_transaction(ALGORITHM, &tx), Ok(()));
+        assert_eq!(policy.check_fee(ALGORITHM, &tx, 0), Err(RejectReason::FeeTooLow { tx: tx.get_hash(ALGORITHM), rate: 0, min: 1 }));
+        assert!(policy.check_fee(ALGORITHM, &tx, 1).is_ok());
+        match policy.check_transaction(ALGORITHM, &spend(&[0])) {
+            Err(RejectReason::Dust { amount: 0, min: 1, .. }) => {},
+            other => panic!("{:?}", other),
+        }
+
+        let mut small = Policy::new();
+        small.set_max_tx_size(10);
+        match small.check_transaction(ALGORITHM, &tx) {
+            Err(RejectReason::TxTooLarge { max: 10, .. }) => {},
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+// The chain of testdata/chain.yaml, for tests that need a chain but not its
+// file.
+
+use blockchain::{Block, BlockChain};
+
+
+pub const CHAIN: &str = include_str!("../../testdata/chain.yaml");
+
+// The blocks of CHAIN, which has no parameters document.
+pub fn blocks() -> Vec<Block> {
+    CHAIN.split("---").skip(1).map(|document| serde_yaml::from_str(document).unwrap()).collect()
+}
+
+pub fn chain() -> BlockChain {
+    let mut chain = BlockChain::new();
+    chain.reorganize(blocks()).unwrap();
+    assert_eq!(chain.len(), 3);
+    chain
+}
@@ -0,0 +1,255 @@
+extern crate byteorder;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use blockchain::{BlockChain, BlockHash, ChainError, Coin, OutPoint, SnapshotHash, UtxoSet};
+use blockchain::encode::{Decodable, Encodable, invalid_data, read_var_bytes, write_var_bytes};
+use util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
+
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"utxo";
+const SNAPSHOT_VERSION: u32 = 5;
+
+// The UTXO set and key/value state as of the block at `height`: enough to
+// validate the blocks after it without replaying the ones before. It is
+// hashed with the algorithm of the chain it was taken from.
+pub struct UtxoSnapshot {
+    algorithm: HashAlgorithm,
+    height: usize,
+    block_hash: BlockHash,
+    utxos: UtxoSet,
+    state: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl UtxoSnapshot {
+    pub fn new(algorithm: HashAlgorithm, height: usize, block_hash: BlockHash, utxos: UtxoSet,
+               state: BTreeMap<Vec<u8>, Vec<u8>>) -> UtxoSnapshot {
+        UtxoSnapshot {
+            algorithm,
+            height,
+            block_hash,
+            utxos,
+            state,
+        }
+    }
+
+    pub fn get_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_block_hash(&self) -> BlockHash {
+        self.block_hash
+    }
+
+    pub fn get_utxos(&self) -> &UtxoSet {
+        &self.utxos
+    }
+
+    pub fn get_state(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.state
+    }
+
+    pub fn into_parts(self) -> (UtxoSet, BTreeMap<Vec<u8>, Vec<u8>>) {
+        (self.utxos, self.state)
+    }
+
+    fn sorted_coins(&self) -> Vec<(&OutPoint, &Coin)> {
+        let mut coins: Vec<_> = self.utxos.iter().collect();
+        coins.sort_unstable_by_key(|&(out, _)| *out);
+        coins
+    }
+
+    fn encode_body<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<LittleEndian>(self.height as u64)?;
+        self.block_hash.encode(w)?;
+        w.write_u64::<LittleEndian>(self.utxos.len() as u64)?;
+        for (out, coin) in self.sorted_coins() {
+            out.encode(w)?;
+            coin.encode(w)?;
+        }
+        w.write_u64::<LittleEndian>(self.state.len() as u64)?;
+        for (key, value) in &self.state {
+            write_var_bytes(w, key)?;
+            write_var_bytes(w, value)?;
+        }
+        Ok(())
+    }
+
+    // Commits to the height, the block, every unspent output and every
+    // state entry, so equal sets hash equally however they were built.
+    pub fn get_hash(&self) -> SnapshotHash {
+        let mut hash = Hash256::new(self.algorithm);
+        self.encode_body(&mut hash).unwrap();
+
+        let mut buf = [0u8; HASH256_BYTES];
+        hash.finalize(&mut buf);
+        SnapshotHash::from_bytes(buf)
+    }
+
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.encode(&mut w)?;
+        w.flush()
+    }
+
+    pub fn read_from(path: &Path) -> io::Result<UtxoSnapshot> {
+        UtxoSnapshot::decode(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+// Magic, version and hash algorithm, the body `get_hash` commits to, and the
+// hash itself so a damaged file is detected on load.
+impl Encodable for UtxoSnapshot {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(SNAPSHOT_MAGIC)?;
+        w.write_u32::<LittleEndian>(SNAPSHOT_VERSION)?;
+        write_var_bytes(w, self.algorithm.name().as_bytes())?;
+        self.encode_body(w)?;
+        self.get_hash().encode(w)
+    }
+}
+
+impl Decodable for UtxoSnapshot {
+    fn decode<R: Read>(r: &mut R) -> io::Result<UtxoSnapshot> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not a UTXO snapshot".to_string()));
+        }
+        let version = r.read_u32::<LittleEndian>()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(invalid_data(format!("unsupported snapshot version {}", version)));
+        }
+        let algorithm = String::from_utf8(read_var_bytes(r)?).ok()
+            .and_then(|name| name.parse().ok())
+            .ok_or_else(|| invalid_data("unknown snapshot hash algorithm".to_string()))?;
+
+        let height = r.read_u64::<LittleEndian>()? as usize;
+        let block_hash = BlockHash::decode(r)?;
+        let count = r.read_u64::<LittleEndian>()?;
+        let mut coins = Vec::new();
+        for _ in 0..count {
+            coins.push((OutPoint::decode(r)?, Coin::decode(r)?));
+        }
+        let count = r.read_u64::<LittleEndian>()?;
+        let mut state = BTreeMap::new();
+        for _ in 0..count {
+            state.insert(read_var_bytes(r)?, read_var_bytes(r)?);
+        }
+        let snapshot = UtxoSnapshot::new(algorithm, height, block_hash, coins.into_iter().collect(), state);
+
+        let expected = SnapshotHash::decode(r)?;
+        if snapshot.get_hash() != expected {
+            return Err(invalid_data(format!("snapshot hash {} does not match its contents", expected)));
+        }
+        Ok(snapshot)
+    }
+}
+
+// Replays the blocks of `file` up to the snapshot `chain` was loaded from on
+// a separate thread, then attaches them to `chain`. Until then `chain` keeps
+// serving from the snapshot.
+pub fn verify_history(chain: Arc<RwLock<BlockChain>>, file: PathBuf) -> thread::JoinHandle<Result<usize, ChainError>> {
+    thread::spawn(move || {
+        let (params, validator, end) = {
+            let chain = chain.read().unwrap();
+            (chain.get_params().clone(), chain.get_validator().clone(), chain.get_history_start())
+        };
+
+        let mut history = BlockChain::with_params(params);
+        history.set_validator(validator);
+        history.read_blocks(&file, Some(end))?;
+
+        chain.write().unwrap().attach_history(history)?;
+        Ok(end)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{Block, ChainParams, Transaction};
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    // `count` blocks, each with a coinbase paying `amount` plus its height.
+    fn blocks(count: usize) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for height in 0..count {
+            let mut coinbase = Transaction::new();
+            coinbase.set_timestamp(height as u64);
+            coinbase.add_output(50 + height as u64, b"s");
+            let mut block = Block::new();
+            if let Some(previous) = blocks.last() {
+                block.set_previous(previous.get_hash(ALGORITHM));
+            }
+            block.add_transaction(coinbase);
+            block.update_merkle_root(ALGORITHM);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn chain(blocks: &[Block], params: ChainParams) -> BlockChain {
+        let mut chain = BlockChain::with_params(params);
+        chain.reorganize(blocks.to_vec()).unwrap();
+        chain
+    }
+
+    #[test]
+    fn encoding_round_trips_and_detects_damage() {
+        let snapshot = chain(&blocks(3), ChainParams::new()).get_snapshot().unwrap();
+        let mut state = BTreeMap::new();
+        state.insert(b"key".to_vec(), b"value".to_vec());
+        let snapshot = UtxoSnapshot::new(ALGORITHM, snapshot.get_height(), snapshot.get_block_hash(), snapshot.into_parts().0, state);
+        let bytes = snapshot.to_bytes();
+        let decoded = UtxoSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.get_height(), 2);
+        assert_eq!(decoded.get_state(), snapshot.get_state());
+        assert_eq!(decoded.get_block_hash(), snapshot.get_block_hash());
+        assert_eq!(decoded.get_hash(), snapshot.get_hash());
+
+        let mut damaged = bytes.clone();
+        damaged[20] ^= 1;
+        assert!(UtxoSnapshot::from_bytes(&damaged).is_err());
+    }
+
+    #[test]
+    fn load_then_attach_history() {
+        let blocks = blocks(5);
+        let snapshot = chain(&blocks[..3], ChainParams::new()).get_snapshot().unwrap();
+
+        let mut unchecked = BlockChain::new();
+        assert!(unchecked.load_snapshot(chain(&blocks[..3], ChainParams::new()).get_snapshot().unwrap()).is_err());
+
+        let mut params = ChainParams::new();
+        params.add_utxo_checkpoint(2, snapshot.get_hash());
+        let mut fast = BlockChain::with_params(params.clone());
+        fast.load_snapshot(snapshot).unwrap();
+        fast.reorganize(blocks[3..].to_vec()).unwrap();
+        assert_eq!(fast.len(), 5);
+        assert_eq!(fast.get_history_start(), 3);
+        assert!(fast.get_block(2).is_none());
+
+        // History that ends elsewhere is refused.
+        assert!(fast.attach_history(chain(&blocks[..2], params.clone())).is_err());
+        fast.attach_history(chain(&blocks[..3], params.clone())).unwrap();
+
+        let full = chain(&blocks, params);
+        assert_eq!(fast.get_history_start(), 0);
+        assert_eq!(fast.to_string(), full.to_string());
+        assert_eq!(fast.get_snapshot().unwrap().get_hash(), full.get_snapshot().unwrap().get_hash());
+    }
+}
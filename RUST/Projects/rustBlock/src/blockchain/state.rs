@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+
+use blockchain::{merkle_root, Block, BlockHeader, MerkleBranch, STATE_ROOT_VERSION, STATE_VERSION};
+use blockchain::encode::write_var_bytes;
+use util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
+use util::hex::ToHex;
+
+
+pub const MAX_STATE_KEY_SIZE: usize = 64;
+pub const MAX_STATE_VALUE_SIZE: usize = 1024;
+
+// The value each key a block wrote had before it, in write order; `None` if
+// the key was not set.
+type StateUndo = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+fn leaf_hash(algorithm: HashAlgorithm, key: &[u8], value: &[u8]) -> [u8; HASH256_BYTES] {
+    let mut hash = Hash256::new(algorithm);
+    write_var_bytes(&mut hash, key).unwrap();
+    write_var_bytes(&mut hash, value).unwrap();
+
+    let mut buf = [0u8; HASH256_BYTES];
+    hash.finalize(&mut buf);
+    buf
+}
+
+fn leaves(algorithm: HashAlgorithm, entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<[u8; HASH256_BYTES]> {
+    entries.iter().map(|(key, value)| leaf_hash(algorithm, key, value)).collect()
+}
+
+fn write(entries: &mut BTreeMap<Vec<u8>, Vec<u8>>, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+    if value.is_empty() {
+        entries.remove(key)
+    } else {
+        entries.insert(key.to_vec(), value.to_vec())
+    }
+}
+
+fn revert(entries: &mut BTreeMap<Vec<u8>, Vec<u8>>, undo: &StateUndo) {
+    for (key, previous) in undo.iter().rev() {
+        match *previous {
+            Some(ref value) => entries.insert(key.clone(), value.clone()),
+            None => entries.remove(key),
+        };
+    }
+}
+
+// Checks that need nothing but the block itself: only version 3
+// transactions write, only into blocks that commit to a state root, and keys
+// and values stay within their limits.
+pub fn check_writes(algorithm: HashAlgorithm, block: &Block) -> Result<(), String> {
+    for tx in block.get_transactions() {
+        if tx.get_writes().is_empty() {
+            continue;
+        }
+        if tx.get_version() < STATE_VERSION {
+            return Err(format!("tx {}: state writes need version {}", tx.get_hash(algorithm), STATE_VERSION));
+        }
+        if block.get_version() < STATE_ROOT_VERSION {
+            return Err(format!("tx {}: state writes need block version {}", tx.get_hash(algorithm), STATE_ROOT_VERSION));
+        }
+        for w in tx.get_writes() {
+            if w.get_key().is_empty() || w.get_key().len() > MAX_STATE_KEY_SIZE {
+                return Err(format!("tx {}: state key of {} bytes; 1 to {} allowed",
+                                   tx.get_hash(algorithm), w.get_key().len(), MAX_STATE_KEY_SIZE));
+            }
+            if w.get_value().len() > MAX_STATE_VALUE_SIZE {
+                return Err(format!("tx {}: state value of {} bytes exceeds {}",
+                                   tx.get_hash(algorithm), w.get_value().len(), MAX_STATE_VALUE_SIZE));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The key/value pairs transactions wrote, applied in block order, with what
+// each block changed so it can be disconnected and older states rebuilt. The
+// state root is the Merkle root over the pairs sorted by key, each hashed as
+// its length prefixed key and value; an empty state has an all zero root.
+#[derive(Clone, Debug, Default)]
+pub struct StateStore {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    // The height of the block `undo` starts with.
+    start: usize,
+    undo: Vec<StateUndo>,
+}
+
+impl StateStore {
+    pub fn new() -> StateStore {
+        StateStore::default()
+    }
+
+    // The state as of the block at `height`, e.g. from a snapshot; older
+    // states cannot be rebuilt.
+    pub fn with_entries(height: usize, entries: BTreeMap<Vec<u8>, Vec<u8>>) -> StateStore {
+        StateStore {
+            entries,
+            start: height + 1,
+            undo: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries.get(key).map(|v| &v[..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, Vec<u8>, Vec<u8>> {
+        self.entries.iter()
+    }
+
+    pub fn get_entries(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.entries
+    }
+
+    pub fn get_root(&self, algorithm: HashAlgorithm) -> [u8; HASH256_BYTES] {
+        merkle_root(algorithm, &leaves(algorithm, &self.entries))
+    }
+
+    // The root `block` must commit to if connected next.
+    pub fn compute_root(&self, algorithm: HashAlgorithm, block: &Block) -> Result<[u8; HASH256_BYTES], String> {
+        check_writes(algorithm, block)?;
+        let mut entries = self.entries.clone();
+        for w in block.get_transactions().iter().flat_map(|tx| tx.get_writes()) {
+            write(&mut entries, w.get_key(), w.get_value());
+        }
+        Ok(merkle_root(algorithm, &leaves(algorithm, &entries)))
+    }
+
+    // Checks the writes of `block` and, if it has one, its state root.
+    pub fn check_block(&self, algorithm: HashAlgorithm, block: &Block) -> Result<(), String> {
+        let root = self.compute_root(algorithm, block)?;
+        if block.get_version() >= STATE_ROOT_VERSION && root[..] != *block.get_state_root() {
+            return Err(format!("append expected state_root '{}'; actual '{}'",
+                               root.to_hex(), block.get_state_root().to_hex()));
+        }
+        Ok(())
+    }
+
+    // Applies the writes of a block that passed `check_block`.
+    pub fn connect_block(&mut self, block: &Block) {
+        let mut undo = StateUndo::new();
+        for w in block.get_transactions().iter().flat_map(|tx| tx.get_writes()) {
+            let previous = write(&mut self.entries, w.get_key(), w.get_value());
+            undo.push((w.get_key().to_vec(), previous));
+        }
+        self.undo.push(undo);
+    }
+
+    pub fn disconnect_block(&mut self) {
+        if let Some(undo) = self.undo.pop() {
+            revert(&mut self.entries, &undo);
+        }
+    }
+
+    // Takes over the changes of `history`, the blocks before `start`.
+    pub fn attach_history(&mut self, mut history: StateStore) {
+        history.undo.append(&mut self.undo);
+        self.undo = history.undo;
+        self.start = history.start;
+    }
+
+    // The state after the block at `height`, if the changes since are kept.
+    fn get_entries_at(&self, height: usize) -> Option<BTreeMap<Vec<u8>, Vec<u8>>> {
+        let end = self.start + self.undo.len();
+        if height + 1 < self.start || height >= end {
+            return None;
+        }
+        let mut entries = self.entries.clone();
+        for undo in self.undo[height + 1 - self.start..].iter().rev() {
+            revert(&mut entries, undo);
+        }
+        Some(entries)
+    }
+
+    // Proves the value `key` had after the block at `height`. `None` if the
+    // key was not set then, or the height is out of range.
+    pub fn get_proof(&self, algorithm: HashAlgorithm, key: &[u8], height: usize) -> Option<StateProof> {
+        let entries = self.get_entries_at(height)?;
+        let index = entries.keys().position(|k| &k[..] == key)?;
+        Some(StateProof {
+            height,
+            key: key.to_vec(),
+            value: entries[key].clone(),
+            branch: MerkleBranch::build(algorithm, &leaves(algorithm, &entries), index),
+        })
+    }
+}
+
+// That `key` had `value` after the block at `height`.
+#[derive(Clone)]
+pub struct StateProof {
+    pub height: usize,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub branch: MerkleBranch,
+}
+
+impl StateProof {
+    // Whether the proof links the pair to the state root of `header`, which
+    // the caller must know is the header at `height`.
+    pub fn verify(&self, algorithm: HashAlgorithm, header: &BlockHeader) -> bool {
+        header.get_version() >= STATE_ROOT_VERSION &&
+            self.branch.verify(algorithm, &leaf_hash(algorithm, &self.key, &self.value), header.get_state_root())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{BlockHash, Transaction};
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn block(previous: BlockHash, state: &StateStore, writes: &[(&[u8], &[u8])]) -> Block {
+        let mut tx = Transaction::new();
+        tx.add_output(50, b"s");
+        for &(key, value) in writes {
+            tx.add_write(key, value);
+        }
+        let mut block = Block::new();
+        block.set_version(STATE_ROOT_VERSION);
+        block.set_previous(previous);
+        block.add_transaction(tx);
+        block.update_merkle_root(ALGORITHM);
+        block.set_state_root(state.compute_root(ALGORITHM, &block).unwrap());
+        block
+    }
+
+    #[test]
+    fn connect_disconnect_and_prove() {
+        let mut state = StateStore::new();
+        let first = block(BlockHash::default(), &state, &[(b"asset/gold", b"alice"), (b"asset/tin", b"bob")]);
+        state.check_block(ALGORITHM, &first).unwrap();
+        state.connect_block(&first);
+        let second = block(first.get_hash(ALGORITHM), &state, &[(b"asset/gold", b"carol"), (b"asset/tin", b"")]);
+        state.check_block(ALGORITHM, &second).unwrap();
+        state.connect_block(&second);
+        assert_eq!(state.get(b"asset/gold"), Some(&b"carol"[..]));
+        assert_eq!(state.len(), 1);
+
+        // Older states are rebuilt from what each block changed.
+        let proof = state.get_proof(ALGORITHM, b"asset/tin", 0).unwrap();
+        assert_eq!(proof.value, b"bob");
+        assert!(proof.verify(ALGORITHM, &first.get_header()));
+        assert!(!proof.verify(ALGORITHM, &second.get_header()));
+        assert!(state.get_proof(ALGORITHM, b"asset/tin", 1).is_none());
+        assert!(state.get_proof(ALGORITHM, b"asset/gold", 1).unwrap().verify(ALGORITHM, &second.get_header()));
+
+        let mut wrong = block(second.get_hash(ALGORITHM), &state, &[(b"asset/gold", b"dave")]);
+        wrong.set_state_root([1u8; HASH256_BYTES]);
+        assert!(state.check_block(ALGORITHM, &wrong).unwrap_err().contains("expected state_root"));
+
+        state.disconnect_block();
+        assert_eq!(state.get(b"asset/tin"), Some(&b"bob"[..]));
+        assert_eq!(state.get_root(ALGORITHM)[..], *first.get_state_root());
+    }
+
+    #[test]
+    fn writes_need_new_versions() {
+        let state = StateStore::new();
+        let mut old = block(BlockHash::default(), &state, &[(b"k", b"v")]);
+        old.set_version(1);
+        assert!(check_writes(ALGORITHM, &old).unwrap_err().contains("need block version 2"));
+
+        let long = [0u8; MAX_STATE_KEY_SIZE + 1];
+        let mut tx = Transaction::new();
+        tx.add_write(&long, b"v");
+        let mut block = Block::new();
+        block.set_version(STATE_ROOT_VERSION);
+        block.add_transaction(tx);
+        assert!(check_writes(ALGORITHM, &block).is_err());
+    }
+}
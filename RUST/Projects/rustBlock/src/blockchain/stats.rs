@@ -0,0 +1,93 @@
+use blockchain::BlockChain;
+
+
+// A summary of the chain, taken at one tip. Blocks that were pruned, or lie
+// before a loaded snapshot, keep only their headers: they count towards
+// `blocks` and the block interval, but not towards the transaction and fee
+// figures, which cover the `blocks_with_data` others.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChainStats {
+    pub blocks: usize,
+    pub blocks_with_data: usize,
+    pub transactions: usize,
+    pub transactions_per_block: f64,
+    // Seconds between block timestamps, over the headers available.
+    pub average_block_interval: f64,
+    // The amount held by unspent outputs.
+    pub total_supply: u64,
+    pub utxo_count: usize,
+    pub total_fees: u64,
+}
+
+impl ChainStats {
+    pub fn new(chain: &BlockChain) -> ChainStats {
+        let mut blocks_with_data = 0;
+        let mut transactions = 0;
+        let mut total_fees = 0u64;
+        for height in chain.get_history_start()..chain.len() {
+            let (block, undo) = match (chain.get_block(height), chain.get_undo(height)) {
+                (Some(block), Some(undo)) => (block, undo),
+                _ => continue,
+            };
+            blocks_with_data += 1;
+            transactions += block.get_transactions().len();
+
+            let spent = undo.get_spent().iter()
+                .fold(0u64, |sum, (_, coin)| sum.saturating_add(coin.get_output().get_native_amount()));
+            let created = block.get_transactions().iter()
+                .filter(|tx| !tx.is_coinbase())
+                .fold(0u64, |sum, tx| sum.saturating_add(tx.get_output_total().unwrap_or(0)));
+            total_fees = total_fees.saturating_add(spent.saturating_sub(created));
+        }
+
+        let first = chain.get_header(chain.get_history_start());
+        let last = chain.len().checked_sub(1).and_then(|tip| chain.get_header(tip));
+        let intervals = chain.len().saturating_sub(chain.get_history_start() + 1);
+        let average_block_interval = match (first, last) {
+            (Some(first), Some(last)) if intervals > 0 =>
+                last.get_timestamp().saturating_sub(first.get_timestamp()) as f64 / intervals as f64,
+            _ => 0.0,
+        };
+
+        let utxos = chain.get_utxos();
+        ChainStats {
+            blocks: chain.len(),
+            blocks_with_data,
+            transactions,
+            transactions_per_block: if blocks_with_data > 0 {
+                transactions as f64 / blocks_with_data as f64
+            } else {
+                0.0
+            },
+            average_block_interval,
+            total_supply: utxos.iter()
+                .fold(0u64, |sum, (_, coin)| sum.saturating_add(coin.get_output().get_native_amount())),
+            utxo_count: utxos.len(),
+            total_fees,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::sample;
+
+    #[test]
+    fn sample_chain() {
+        let chain = sample::chain();
+        let stats = ChainStats::new(&chain);
+        assert_eq!(stats.blocks, 3);
+        assert_eq!(stats.blocks_with_data, 3);
+        assert_eq!(stats.transactions, 4);
+        assert_eq!(stats.utxo_count, chain.get_utxos().len());
+        assert_eq!(stats.total_supply, chain.get_utxos().get_balance(None));
+        let first = chain.get_header(0).unwrap().get_timestamp();
+        let last = chain.get_header(2).unwrap().get_timestamp();
+        assert_eq!(stats.average_block_interval, (last - first) as f64 / 2.0);
+
+        // Every coinbase pays 5000, so the fees are what the supply lacks.
+        assert_eq!(stats.total_fees, 3 * 5000 - stats.total_supply);
+        assert_eq!(ChainStats::new(&BlockChain::new()).average_block_interval, 0.0);
+    }
+}
@@ -1,66 +1,563 @@
 extern crate byteorder;
+extern crate serde;
 
+use std::collections::HashSet;
 use std::fmt;
-use std::io::Write;
+use std::io;
+use std::io::{Read, Write};
 
-use self::byteorder::{LittleEndian, WriteBytesExt};
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use self::serde::de::{Deserialize, Deserializer, Error};
+use self::serde::ser::{Serialize, SerializeStruct, Serializer};
 
-use util::hash::{Hash256, HASH256_BYTES};
+use blockchain::{AssetId, BuildError, FeeEstimator, TxId};
+use blockchain::encode::{Decodable, Encodable, MAX_PREALLOC, invalid_data, read_var_bytes, read_vec, write_var_bytes, write_vec};
+use util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
 use util::hex::{FromHex, ToHex};
 
 
+// Lock times below this are block heights, from it on Unix timestamps.
+pub const LOCK_TIME_THRESHOLD: u64 = 500_000_000;
+
+// Relative locks, as in BIP68: an input sequence with the disable flag set
+// has none; otherwise its low 16 bits count blocks, or units of 512 seconds
+// if the type flag is set, since the spent output was confirmed.
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+pub const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+pub const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_MASK: u32 = 0xffff;
+const SEQUENCE_GRANULARITY: u64 = 512;
+
+// Lock times and sequences are only part of version 2 transactions, so
+// version 1 transactions hash and encode as before.
+pub const LOCK_TIME_VERSION: u32 = 2;
+
+// Key/value writes are only part of version 3 transactions.
+pub const STATE_VERSION: u32 = 3;
+
+// Output assets and issuances are only part of version 4 transactions;
+// outputs of older ones carry the native coin.
+pub const ASSET_VERSION: u32 = 4;
+
+pub const MAX_ASSET_NAME_SIZE: usize = 32;
+
+// The script of an anchor output is this prefix, bitcoin's OP_RETURN and a
+// 32 byte push, followed by the hash it commits to. Anchors can never be
+// spent, so they stay out of the UTXO set.
+pub const ANCHOR_PREFIX: [u8; 2] = [0x6a, 0x20];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RelativeLock {
+    Blocks(u64),
+    Seconds(u64),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct OutPoint {
-    hash: [u8; HASH256_BYTES],
+    hash: TxId,
     index: u32,
 }
 
 impl OutPoint {
+    pub fn new(hash: TxId, index: u32) -> OutPoint {
+        OutPoint {
+            hash,
+            index,
+        }
+    }
+
+    pub fn builder(hash: TxId) -> OutPointBuilder {
+        OutPointBuilder {
+            hash,
+            index: 0,
+        }
+    }
+
+    pub fn get_tx_hash(&self) -> TxId {
+        self.hash
+    }
+
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+
     fn hash(&self, hash: &mut Hash256) {
-        hash.write_all(&self.hash);
+        self.hash.encode(hash).unwrap();
         hash.write_u32::<LittleEndian>(self.index).unwrap();
     }
 }
 
+impl fmt::Display for OutPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.hash, self.index)
+    }
+}
+
+impl Encodable for OutPoint {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.hash.encode(w)?;
+        w.write_u32::<LittleEndian>(self.index)
+    }
+}
+
+impl Decodable for OutPoint {
+    fn decode<R: Read>(r: &mut R) -> io::Result<OutPoint> {
+        let hash = TxId::decode(r)?;
+        let index = r.read_u32::<LittleEndian>()?;
+        Ok(OutPoint::new(hash, index))
+    }
+}
+
+impl<'de> Deserialize<'de> for OutPoint {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        struct RawOutPoint {
+            hash: TxId,
+            index: u32,
+        }
+
+        let raw = RawOutPoint::deserialize(deserializer)?;
+        Ok(OutPoint::new(raw.hash, raw.index))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct TransactionInput {
     previous_out: OutPoint,
+    sequence: u32,
 }
 
 impl TransactionInput {
-    fn hash(&self, hash: &mut Hash256) {
+    // An input without a relative lock.
+    pub fn new(previous_out: OutPoint) -> TransactionInput {
+        TransactionInput {
+            previous_out,
+            sequence: SEQUENCE_FINAL,
+        }
+    }
+
+    pub fn builder(previous_out: OutPoint) -> TransactionInputBuilder {
+        TransactionInputBuilder {
+            input: TransactionInput::new(previous_out),
+            error: None,
+        }
+    }
+
+    pub fn get_previous_out(&self) -> &OutPoint {
+        &self.previous_out
+    }
+
+    pub fn get_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn get_relative_lock(&self) -> Option<RelativeLock> {
+        if self.sequence & SEQUENCE_DISABLE_FLAG != 0 {
+            return None;
+        }
+        let value = (self.sequence & SEQUENCE_MASK) as u64;
+        if self.sequence & SEQUENCE_TYPE_FLAG != 0 {
+            Some(RelativeLock::Seconds(value * SEQUENCE_GRANULARITY))
+        } else {
+            Some(RelativeLock::Blocks(value))
+        }
+    }
+
+    fn hash(&self, hash: &mut Hash256, version: u32) {
         self.previous_out.hash(hash);
+        if version >= LOCK_TIME_VERSION {
+            hash.write_u32::<LittleEndian>(self.sequence).unwrap();
+        }
+    }
+
+    fn encode<W: Write>(&self, w: &mut W, version: u32) -> io::Result<()> {
+        self.previous_out.encode(w)?;
+        if version >= LOCK_TIME_VERSION {
+            w.write_u32::<LittleEndian>(self.sequence)?;
+        }
+        Ok(())
+    }
+
+    fn decode<R: Read>(r: &mut R, version: u32) -> io::Result<TransactionInput> {
+        let previous_out = OutPoint::decode(r)?;
+        let sequence = if version >= LOCK_TIME_VERSION {
+            r.read_u32::<LittleEndian>()?
+        } else {
+            SEQUENCE_FINAL
+        };
+        Ok(TransactionInput { previous_out, sequence })
     }
 }
 
+impl fmt::Display for TransactionInput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    previous_out: {}", self.previous_out)?;
+        if self.sequence != SEQUENCE_FINAL {
+            writeln!(f, "    sequence:     {:#x}", self.sequence)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionInput {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        struct RawInput {
+            hash: TxId,
+            index: u32,
+            #[serde(default = "default_sequence")]
+            sequence: u32,
+        }
+
+        let raw = RawInput::deserialize(deserializer)?;
+        Ok(TransactionInput {
+            previous_out: OutPoint::new(raw.hash, raw.index),
+            sequence: raw.sequence,
+        })
+    }
+}
+
+impl Serialize for TransactionInput {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let locked = self.sequence != SEQUENCE_FINAL;
+        let mut s = serializer.serialize_struct("TransactionInput", if locked { 3 } else { 2 })?;
+        s.serialize_field("hash", &self.previous_out.hash)?;
+        s.serialize_field("index", &self.previous_out.index)?;
+        if locked {
+            s.serialize_field("sequence", &self.sequence)?;
+        }
+        s.end()
+    }
+}
+
+fn default_sequence() -> u32 {
+    SEQUENCE_FINAL
+}
+
+// An amount of an asset, the native coin unless `asset` is set.
+#[derive(Clone, Debug, PartialEq)]
 pub struct TransactionOutput {
     amount: u64,
+    script: Vec<u8>,
+    asset: AssetId,
 }
 
 impl fmt::Display for TransactionOutput {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "    amount: {}\n", self.amount)
+        writeln!(f, "    amount: {}", self.amount)?;
+        if !self.is_native() {
+            writeln!(f, "    asset:  {}", self.asset)?;
+        }
+        writeln!(f, "    script: {}", self.script.to_hex())
     }
 }
 
 impl TransactionOutput {
+    pub fn new(amount: u64, script: &[u8]) -> TransactionOutput {
+        TransactionOutput::with_asset(AssetId::default(), amount, script)
+    }
+
+    // An output of no value committing to `hash`, e.g. of a document.
+    pub fn anchor(hash: &[u8; HASH256_BYTES]) -> TransactionOutput {
+        let mut script = ANCHOR_PREFIX.to_vec();
+        script.extend_from_slice(hash);
+        TransactionOutput::new(0, &script)
+    }
+
+    pub fn with_asset(asset: AssetId, amount: u64, script: &[u8]) -> TransactionOutput {
+        TransactionOutput {
+            amount,
+            script: script.to_vec(),
+            asset,
+        }
+    }
+
+    pub fn get_amount(&self) -> u64 {
+        self.amount
+    }
+
+    pub fn get_script(&self) -> &[u8] {
+        &self.script
+    }
+
+    // The all zero ID for the native coin.
+    pub fn get_asset(&self) -> AssetId {
+        self.asset
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.asset.is_zero()
+    }
+
+    // The hash an anchor output commits to; `None` for other outputs.
+    pub fn get_anchor(&self) -> Option<[u8; HASH256_BYTES]> {
+        if self.script.len() != ANCHOR_PREFIX.len() + HASH256_BYTES || !self.script.starts_with(&ANCHOR_PREFIX) {
+            return None;
+        }
+        let mut hash = [0u8; HASH256_BYTES];
+        hash.copy_from_slice(&self.script[ANCHOR_PREFIX.len()..]);
+        Some(hash)
+    }
+
+    pub fn is_anchor(&self) -> bool {
+        self.get_anchor().is_some()
+    }
+
+    // The amount if this carries the native coin, which fees are paid in.
+    pub fn get_native_amount(&self) -> u64 {
+        if self.is_native() { self.amount } else { 0 }
+    }
+
+    fn hash(&self, hash: &mut Hash256, version: u32) {
+        hash.write_u64::<LittleEndian>(self.amount).unwrap();
+        write_var_bytes(hash, &self.script).unwrap();
+        if version >= ASSET_VERSION {
+            hash.write_all(self.asset.as_bytes()).unwrap();
+        }
+    }
+
+    // Coins are stored at the latest version, so they keep their asset.
+    pub fn encode<W: Write>(&self, w: &mut W, version: u32) -> io::Result<()> {
+        w.write_u64::<LittleEndian>(self.amount)?;
+        write_var_bytes(w, &self.script)?;
+        if version >= ASSET_VERSION {
+            self.asset.encode(w)?;
+        }
+        Ok(())
+    }
+
+    pub fn decode<R: Read>(r: &mut R, version: u32) -> io::Result<TransactionOutput> {
+        let amount = r.read_u64::<LittleEndian>()?;
+        let script = read_var_bytes(r)?;
+        let asset = if version >= ASSET_VERSION {
+            AssetId::decode(r)?
+        } else {
+            AssetId::default()
+        };
+        Ok(TransactionOutput { amount, script, asset })
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionOutput {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        struct RawOutput {
+            amount: u64,
+            #[serde(default)]
+            script: String,
+            #[serde(default)]
+            asset: AssetId,
+        }
+
+        let raw = RawOutput::deserialize(deserializer)?;
+        Ok(TransactionOutput {
+            amount: raw.amount,
+            script: raw.script.from_hex().map_err(D::Error::custom)?,
+            asset: raw.asset,
+        })
+    }
+}
+
+// Sets `key` in the chain state to `value`; an empty value deletes it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateWrite {
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl StateWrite {
+    pub fn new(key: &[u8], value: &[u8]) -> StateWrite {
+        StateWrite {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        }
+    }
+
+    pub fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn get_value(&self) -> &[u8] {
+        &self.value
+    }
+
+    fn hash(&self, hash: &mut Hash256) {
+        write_var_bytes(hash, &self.key).unwrap();
+        write_var_bytes(hash, &self.value).unwrap();
+    }
+}
+
+impl fmt::Display for StateWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    key:   {}", self.key.to_hex())?;
+        writeln!(f, "    value: {}", self.value.to_hex())
+    }
+}
+
+impl Encodable for StateWrite {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_var_bytes(w, &self.key)?;
+        write_var_bytes(w, &self.value)
+    }
+}
+
+impl Decodable for StateWrite {
+    fn decode<R: Read>(r: &mut R) -> io::Result<StateWrite> {
+        Ok(StateWrite {
+            key: read_var_bytes(r)?,
+            value: read_var_bytes(r)?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for StateWrite {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        struct RawWrite {
+            key: String,
+            #[serde(default)]
+            value: String,
+        }
+
+        let raw = RawWrite::deserialize(deserializer)?;
+        Ok(StateWrite {
+            key: raw.key.from_hex().map_err(D::Error::custom)?,
+            value: raw.value.from_hex().map_err(D::Error::custom)?,
+        })
+    }
+}
+
+impl Serialize for TransactionOutput {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let native = self.is_native();
+        let mut s = serializer.serialize_struct("TransactionOutput", if native { 2 } else { 3 })?;
+        s.serialize_field("amount", &self.amount)?;
+        s.serialize_field("script", &self.script.to_hex())?;
+        if !native {
+            s.serialize_field("asset", &self.asset)?;
+        }
+        s.end()
+    }
+}
+
+impl Serialize for StateWrite {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut s = serializer.serialize_struct("StateWrite", 2)?;
+        s.serialize_field("key", &self.key.to_hex())?;
+        s.serialize_field("value", &self.value.to_hex())?;
+        s.end()
+    }
+}
+
+// Creates `amount` of a new asset, whose ID commits to the first input the
+// transaction spends, so no two issuances share one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Issuance {
+    amount: u64,
+    #[serde(default)]
+    name: String,
+}
+
+impl Issuance {
+    pub fn new(amount: u64, name: &str) -> Issuance {
+        Issuance {
+            amount,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn get_amount(&self) -> u64 {
+        self.amount
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
     fn hash(&self, hash: &mut Hash256) {
         hash.write_u64::<LittleEndian>(self.amount).unwrap();
+        write_var_bytes(hash, self.name.as_bytes()).unwrap();
     }
 }
 
+impl fmt::Display for Issuance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    amount: {}", self.amount)?;
+        writeln!(f, "    name:   {}", self.name)
+    }
+}
+
+impl Encodable for Issuance {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<LittleEndian>(self.amount)?;
+        write_var_bytes(w, self.name.as_bytes())
+    }
+}
+
+impl Decodable for Issuance {
+    fn decode<R: Read>(r: &mut R) -> io::Result<Issuance> {
+        let amount = r.read_u64::<LittleEndian>()?;
+        let name = String::from_utf8(read_var_bytes(r)?)
+            .map_err(|e| invalid_data(format!("asset name: {}", e)))?;
+        Ok(Issuance { amount, name })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct Transaction {
     version: u32,
     timestamp: u64,
+    #[serde(default)]
     inputs: Vec<TransactionInput>,
+    #[serde(default)]
     outputs: Vec<TransactionOutput>,
+    #[serde(default)]
+    lock_time: u64,
+    #[serde(default)]
+    writes: Vec<StateWrite>,
+    #[serde(default)]
+    issuance: Option<Issuance>,
 }
 
 impl Transaction {
+    pub fn builder() -> TransactionBuilder {
+        TransactionBuilder {
+            version: None,
+            timestamp: 0,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            lock_time: 0,
+            writes: Vec::new(),
+            issuance: None,
+            fee_rate: 0,
+            change: None,
+            error: None,
+        }
+    }
+
     pub fn new() -> Transaction {
         Transaction {
             version: 1,
             timestamp: 0,
             inputs: Vec::new(),
             outputs: Vec::new(),
+            lock_time: 0,
+            writes: Vec::new(),
+            issuance: None,
         }
     }
 
@@ -68,48 +565,630 @@ impl Transaction {
         self.timestamp = ts;
     }
 
-    pub fn add_output(&mut self, amount: u64) {
-        let output = TransactionOutput {
-            amount: amount,
-        };
+    pub fn add_input(&mut self, previous_out: OutPoint) {
+        self.add_input_with_sequence(previous_out, SEQUENCE_FINAL);
+    }
+
+    // Makes this a version 2 transaction unless `sequence` is final.
+    pub fn add_input_with_sequence(&mut self, previous_out: OutPoint, sequence: u32) {
+        if sequence != SEQUENCE_FINAL {
+            self.version = self.version.max(LOCK_TIME_VERSION);
+        }
+        self.inputs.push(TransactionInput { previous_out, sequence });
+    }
+
+    // The transaction cannot be included in a block before this height, or
+    // block timestamp if at least `LOCK_TIME_THRESHOLD`; zero means no lock.
+    // Makes this a version 2 transaction.
+    pub fn set_lock_time(&mut self, lock_time: u64) {
+        self.version = self.version.max(LOCK_TIME_VERSION);
+        self.lock_time = lock_time;
+    }
+
+    pub fn get_lock_time(&self) -> u64 {
+        self.lock_time
+    }
+
+    pub fn add_output(&mut self, amount: u64, script: &[u8]) {
+        self.outputs.push(TransactionOutput::new(amount, script));
+    }
+
+    pub fn add_anchor(&mut self, hash: &[u8; HASH256_BYTES]) {
+        self.outputs.push(TransactionOutput::anchor(hash));
+    }
+
+    // Makes this a version 4 transaction.
+    pub fn add_asset_output(&mut self, asset: AssetId, amount: u64, script: &[u8]) {
+        self.version = self.version.max(ASSET_VERSION);
+        self.outputs.push(TransactionOutput::with_asset(asset, amount, script));
+    }
+
+    // Issues `amount` of a new asset, `get_issued_asset`, which the outputs
+    // must then carry. Makes this a version 4 transaction.
+    pub fn set_issuance(&mut self, amount: u64, name: &str) {
+        self.version = self.version.max(ASSET_VERSION);
+        self.issuance = Some(Issuance::new(amount, name));
+    }
+
+    pub fn get_issuance(&self) -> Option<&Issuance> {
+        self.issuance.as_ref()
+    }
+
+    // The ID of the asset this transaction issues: a hash of its first input
+    // and the asset name. `None` without an issuance or inputs.
+    pub fn get_issued_asset(&self, algorithm: HashAlgorithm) -> Option<AssetId> {
+        let issuance = self.issuance.as_ref()?;
+        let first = self.inputs.first()?;
+        let mut hash = Hash256::new(algorithm);
+        first.previous_out.hash(&mut hash);
+        write_var_bytes(&mut hash, issuance.name.as_bytes()).unwrap();
+
+        let mut buf = [0u8; HASH256_BYTES];
+        hash.finalize(&mut buf);
+        Some(AssetId::from_bytes(buf))
+    }
+
+    // Makes this a version 3 transaction.
+    pub fn add_write(&mut self, key: &[u8], value: &[u8]) {
+        self.version = self.version.max(STATE_VERSION);
+        self.writes.push(StateWrite::new(key, value));
+    }
+
+    pub fn get_writes(&self) -> &[StateWrite] {
+        &self.writes
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn get_inputs(&self) -> &[TransactionInput] {
+        &self.inputs
+    }
+
+    pub fn get_outputs(&self) -> &[TransactionOutput] {
+        &self.outputs
+    }
+
+    // A transaction without inputs mints its outputs.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    // The native coin paid out; other assets are not counted. `None` if the
+    // sum overflows, which makes the transaction invalid.
+    pub fn get_output_total(&self) -> Option<u64> {
+        self.outputs.iter().try_fold(0u64, |sum, o| sum.checked_add(o.get_native_amount()))
+    }
+
+    // Checks that need nothing but the transaction: only version 4
+    // transactions carry assets, coinbases mint only the native coin and
+    // an issuance spends an input to derive its ID from.
+    pub fn check_assets(&self, algorithm: HashAlgorithm) -> Result<(), String> {
+        let issued = self.outputs.iter().any(|o| !o.is_native());
+        if self.version < ASSET_VERSION {
+            if issued || self.issuance.is_some() {
+                return Err(format!("tx {}: assets need version {}", self.get_hash(algorithm), ASSET_VERSION));
+            }
+            return Ok(());
+        }
+        if self.is_coinbase() && issued {
+            return Err(format!("tx {}: coinbase outputs must be native", self.get_hash(algorithm)));
+        }
+        if let Some(ref issuance) = self.issuance {
+            if self.is_coinbase() {
+                return Err(format!("tx {}: issuance needs an input", self.get_hash(algorithm)));
+            }
+            if issuance.name.len() > MAX_ASSET_NAME_SIZE {
+                return Err(format!("tx {}: asset name of {} bytes exceeds {}",
+                                   self.get_hash(algorithm), issuance.name.len(), MAX_ASSET_NAME_SIZE));
+            }
+        }
+        Ok(())
+    }
+
+    // Checks the absolute lock time against a block at `height` with
+    // timestamp `time`. Relative locks depend on the outputs spent, so
+    // `UtxoSet` checks those.
+    pub fn check_lock_time(&self, algorithm: HashAlgorithm, height: usize, time: u64) -> Result<(), String> {
+        if self.version < LOCK_TIME_VERSION {
+            if self.lock_time != 0 || self.inputs.iter().any(|i| i.sequence != SEQUENCE_FINAL) {
+                return Err(format!("tx {}: lock times need version {}", self.get_hash(algorithm), LOCK_TIME_VERSION));
+            }
+            return Ok(());
+        }
 
-        self.outputs.push(output);
+        let (now, unit) = if self.lock_time < LOCK_TIME_THRESHOLD {
+            (height as u64, "height")
+        } else {
+            (time, "time")
+        };
+        if self.lock_time > now {
+            return Err(format!("tx {}: locked until {} {}; block {} is {}",
+                               self.get_hash(algorithm), unit, self.lock_time, unit, now));
+        }
+        Ok(())
     }
 
-    pub fn get_hash(&self, mut buf: &mut [u8]) {
-        let mut hash = Hash256::new();
+    pub fn get_hash(&self, algorithm: HashAlgorithm) -> TxId {
+        let mut hash = Hash256::new(algorithm);
         hash.write_u32::<LittleEndian>(self.version).unwrap();
         hash.write_u64::<LittleEndian>(self.timestamp).unwrap();
 
         for i in &self.inputs {
-            i.hash(&mut hash);
+            i.hash(&mut hash, self.version);
         }
 
         for o in &self.outputs {
-            o.hash(&mut hash);
+            o.hash(&mut hash, self.version);
+        }
+
+        if self.version >= LOCK_TIME_VERSION {
+            hash.write_u64::<LittleEndian>(self.lock_time).unwrap();
+        }
+
+        if self.version >= STATE_VERSION {
+            hash.write_u32::<LittleEndian>(self.writes.len() as u32).unwrap();
+            for w in &self.writes {
+                w.hash(&mut hash);
+            }
+        }
+
+        if self.version >= ASSET_VERSION {
+            match self.issuance {
+                Some(ref issuance) => {
+                    hash.write_u8(1).unwrap();
+                    issuance.hash(&mut hash);
+                },
+                None => hash.write_u8(0).unwrap(),
+            }
         }
 
+        let mut buf = [0u8; HASH256_BYTES];
         hash.finalize(&mut buf);
+        TxId::from_bytes(buf)
+    }
+}
+
+// Writes the fields a chain file gives, leaving out those that are at their
+// defaults.
+impl Serialize for Transaction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut s = serializer.serialize_struct("Transaction", 7)?;
+        s.serialize_field("version", &self.version)?;
+        s.serialize_field("timestamp", &self.timestamp)?;
+        if !self.inputs.is_empty() {
+            s.serialize_field("inputs", &self.inputs)?;
+        }
+        if !self.outputs.is_empty() {
+            s.serialize_field("outputs", &self.outputs)?;
+        }
+        if self.lock_time != 0 {
+            s.serialize_field("lock_time", &self.lock_time)?;
+        }
+        if !self.writes.is_empty() {
+            s.serialize_field("writes", &self.writes)?;
+        }
+        if let Some(ref issuance) = self.issuance {
+            s.serialize_field("issuance", issuance)?;
+        }
+        s.end()
+    }
+}
+
+impl Default for Transaction {
+    fn default() -> Transaction {
+        Transaction::new()
+    }
+}
+
+impl Encodable for Transaction {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u32::<LittleEndian>(self.version)?;
+        w.write_u64::<LittleEndian>(self.timestamp)?;
+        w.write_u32::<LittleEndian>(self.inputs.len() as u32)?;
+        for input in &self.inputs {
+            input.encode(w, self.version)?;
+        }
+        w.write_u32::<LittleEndian>(self.outputs.len() as u32)?;
+        for output in &self.outputs {
+            output.encode(w, self.version)?;
+        }
+        if self.version >= LOCK_TIME_VERSION {
+            w.write_u64::<LittleEndian>(self.lock_time)?;
+        }
+        if self.version >= STATE_VERSION {
+            write_vec(w, &self.writes)?;
+        }
+        if self.version >= ASSET_VERSION {
+            match self.issuance {
+                Some(ref issuance) => {
+                    w.write_u8(1)?;
+                    issuance.encode(w)?;
+                },
+                None => w.write_u8(0)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Decodable for Transaction {
+    fn decode<R: Read>(r: &mut R) -> io::Result<Transaction> {
+        let version = r.read_u32::<LittleEndian>()?;
+        let timestamp = r.read_u64::<LittleEndian>()?;
+        let count = r.read_u32::<LittleEndian>()? as usize;
+        let mut inputs = Vec::with_capacity(count.min(MAX_PREALLOC));
+        for _ in 0..count {
+            inputs.push(TransactionInput::decode(r, version)?);
+        }
+        let count = r.read_u32::<LittleEndian>()? as usize;
+        let mut outputs = Vec::with_capacity(count.min(MAX_PREALLOC));
+        for _ in 0..count {
+            outputs.push(TransactionOutput::decode(r, version)?);
+        }
+        let lock_time = if version >= LOCK_TIME_VERSION {
+            r.read_u64::<LittleEndian>()?
+        } else {
+            0
+        };
+        let writes = if version >= STATE_VERSION {
+            read_vec(r)?
+        } else {
+            Vec::new()
+        };
+        let issuance = if version >= ASSET_VERSION {
+            match r.read_u8()? {
+                0 => None,
+                1 => Some(Issuance::decode(r)?),
+                flag => return Err(invalid_data(format!("issuance flag {}", flag))),
+            }
+        } else {
+            None
+        };
+        Ok(Transaction {
+            version,
+            timestamp,
+            inputs,
+            outputs,
+            lock_time,
+            writes,
+            issuance,
+        })
     }
 }
 
 impl fmt::Display for Transaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut hash = [0u8; HASH256_BYTES];
-        self.get_hash(&mut hash);
-
-        write!(f, "  tx _hash:    {}\n", hash.to_hex()).unwrap();
-        write!(f, "  version:     {}\n", self.version).unwrap();
-        write!(f, "  timestamp:   {}\n", self.timestamp).unwrap();
-        write!(f, "  inputs:\n").unwrap();
-        //for i in &self.inputs {
-        //    write!(f, "{}", i).unwrap();
-        //}
+        writeln!(f, "  version:     {}", self.version)?;
+        writeln!(f, "  timestamp:   {}", self.timestamp)?;
+        if self.lock_time != 0 {
+            writeln!(f, "  lock_time:   {}", self.lock_time)?;
+        }
+        writeln!(f, "  inputs:")?;
+        for i in &self.inputs {
+            write!(f, "{}", i)?;
+        }
 
-        write!(f, "  outputs:\n").unwrap();
+        writeln!(f, "  outputs:")?;
         for o in &self.outputs {
-            write!(f, "{}", o).unwrap();
+            write!(f, "{}", o)?;
+        }
+
+        if !self.writes.is_empty() {
+            writeln!(f, "  writes:")?;
+            for w in &self.writes {
+                write!(f, "{}", w)?;
+            }
+        }
+
+        if let Some(ref issuance) = self.issuance {
+            writeln!(f, "  issuance:")?;
+            write!(f, "{}", issuance)?;
+        }
+        writeln!(f)
+    }
+}
+
+// Builds an `OutPoint`, the output `index` of the transaction `hash`.
+#[derive(Clone, Debug)]
+pub struct OutPointBuilder {
+    hash: TxId,
+    index: u32,
+}
+
+impl OutPointBuilder {
+    // The position of the output in the transaction; 0 if not set.
+    pub fn index(mut self, index: u32) -> OutPointBuilder {
+        self.index = index;
+        self
+    }
+
+    pub fn build(self) -> OutPoint {
+        OutPoint::new(self.hash, self.index)
+    }
+}
+
+// Builds a `TransactionInput` spending `previous_out`, by default without a
+// relative lock.
+#[derive(Clone, Debug)]
+pub struct TransactionInputBuilder {
+    input: TransactionInput,
+    error: Option<BuildError>,
+}
+
+impl TransactionInputBuilder {
+    // Sets the raw sequence, as in BIP68. `SEQUENCE_FINAL` disables the
+    // relative lock.
+    pub fn sequence(mut self, sequence: u32) -> TransactionInputBuilder {
+        self.input.sequence = sequence;
+        self
+    }
+
+    // Locks the input until the spent output has this many confirmations,
+    // or is this old. Seconds round up to the next multiple of 512.
+    pub fn relative_lock(mut self, lock: RelativeLock) -> TransactionInputBuilder {
+        let (value, flag) = match lock {
+            RelativeLock::Blocks(blocks) => (blocks, 0),
+            RelativeLock::Seconds(seconds) =>
+                (seconds.div_ceil(SEQUENCE_GRANULARITY), SEQUENCE_TYPE_FLAG),
+        };
+        if value > SEQUENCE_MASK as u64 {
+            self.error = Some(BuildError::RelativeLockTooLong(lock));
+        } else {
+            self.input.sequence = flag | value as u32;
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<TransactionInput, BuildError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.input),
+        }
+    }
+}
+
+// Builds a `Transaction`, checking what `Transaction::new` and its setters
+// leave to block validation. Unless `version` is called, the transaction is
+// version 4 if it carries issued assets, version 3 if it writes to the chain
+// state, version 2 if it has a lock time or a relative lock, and version 1
+// otherwise.
+#[derive(Clone, Debug)]
+pub struct TransactionBuilder {
+    version: Option<u32>,
+    timestamp: u64,
+    inputs: Vec<TransactionInput>,
+    outputs: Vec<TransactionOutput>,
+    lock_time: u64,
+    writes: Vec<StateWrite>,
+    issuance: Option<Issuance>,
+    fee_rate: u64,
+    change: Option<(u64, Vec<u8>)>,
+    error: Option<BuildError>,
+}
+
+impl TransactionBuilder {
+    pub fn version(mut self, version: u32) -> TransactionBuilder {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> TransactionBuilder {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn input(mut self, input: TransactionInput) -> TransactionBuilder {
+        self.inputs.push(input);
+        self
+    }
+
+    // Adds an input spending `previous_out` without a relative lock.
+    pub fn spend(self, previous_out: OutPoint) -> TransactionBuilder {
+        self.input(TransactionInput::new(previous_out))
+    }
+
+    pub fn output(mut self, amount: u64, script: &[u8]) -> TransactionBuilder {
+        self.outputs.push(TransactionOutput::new(amount, script));
+        self
+    }
+
+    // Adds an output carrying `amount` of the issued asset `asset`.
+    pub fn asset_output(mut self, asset: AssetId, amount: u64, script: &[u8]) -> TransactionBuilder {
+        self.outputs.push(TransactionOutput::with_asset(asset, amount, script));
+        self
+    }
+
+    // Issues `amount` of a new asset. Its ID depends on the first input;
+    // `Transaction::get_issued_asset` returns it.
+    pub fn issue(mut self, amount: u64, name: &str) -> TransactionBuilder {
+        self.issuance = Some(Issuance::new(amount, name));
+        self
+    }
+
+    // A block height, or a Unix timestamp from `LOCK_TIME_THRESHOLD` on,
+    // before which no block may include the transaction.
+    pub fn lock_time(mut self, lock_time: u64) -> TransactionBuilder {
+        self.lock_time = lock_time;
+        self
+    }
+
+    // Sets `key` in the chain state to `value` once the transaction is
+    // confirmed; an empty value deletes it.
+    pub fn write(mut self, key: &[u8], value: &[u8]) -> TransactionBuilder {
+        self.writes.push(StateWrite::new(key, value));
+        self
+    }
+
+    // Pays at least `rate` per 1000 bytes of the encoded transaction out of
+    // the change; without `change` nothing is left to pay it from.
+    pub fn fee_rate(mut self, rate: u64) -> TransactionBuilder {
+        self.fee_rate = rate;
+        self
+    }
+
+    // Pays the fee rate `estimator` expects to confirm the transaction
+    // within `target` blocks.
+    pub fn estimated_fee_rate(mut self, estimator: &FeeEstimator, target: usize) -> TransactionBuilder {
+        match estimator.estimate(target) {
+            Some(rate) => self.fee_rate = rate,
+            None => self.error = Some(BuildError::NoFeeEstimate(target)),
+        }
+        self
+    }
+
+    // Adds a last output paying `script` what is left of `input_total`, the
+    // native coin the inputs spend, after the other outputs and the fee.
+    // It is left out if nothing is.
+    pub fn change(mut self, input_total: u64, script: &[u8]) -> TransactionBuilder {
+        self.change = Some((input_total, script.to_vec()));
+        self
+    }
+
+    pub fn build(self) -> Result<Transaction, BuildError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let locked = self.lock_time != 0 || self.inputs.iter().any(|i| i.sequence != SEQUENCE_FINAL);
+        let assets = self.issuance.is_some() || self.outputs.iter().any(|o| !o.is_native());
+        let version = match self.version {
+            Some(version) => version,
+            None if assets => ASSET_VERSION,
+            None if !self.writes.is_empty() => STATE_VERSION,
+            None if locked => LOCK_TIME_VERSION,
+            None => 1,
+        };
+        if version == 0 || version > ASSET_VERSION {
+            return Err(BuildError::UnsupportedVersion(version));
+        }
+        if locked && version < LOCK_TIME_VERSION {
+            return Err(BuildError::LockTimeNeedsVersion2);
+        }
+        if !self.writes.is_empty() && version < STATE_VERSION {
+            return Err(BuildError::WritesNeedVersion3);
+        }
+        if assets && version < ASSET_VERSION {
+            return Err(BuildError::AssetsNeedVersion4);
+        }
+        if self.issuance.is_some() && self.inputs.is_empty() {
+            return Err(BuildError::IssuanceNeedsInput);
         }
-        write!(f, "\n")
+
+        let mut spent = HashSet::new();
+        for input in &self.inputs {
+            if !spent.insert(input.previous_out) {
+                return Err(BuildError::DuplicateInput(input.previous_out));
+            }
+        }
+
+        let mut tx = Transaction {
+            version,
+            timestamp: self.timestamp,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            lock_time: self.lock_time,
+            writes: self.writes,
+            issuance: self.issuance,
+        };
+        if let Some((available, script)) = self.change {
+            // Amounts are fixed width, so the size with the change output
+            // does not depend on its amount.
+            tx.outputs.push(TransactionOutput::new(0, &script));
+            let fee = self.fee_rate.saturating_mul(tx.to_bytes().len() as u64).div_ceil(1000);
+            let needed = tx.get_output_total().unwrap_or(u64::MAX).saturating_add(fee);
+            if needed > available {
+                return Err(BuildError::InsufficientFunds { needed, available });
+            }
+            match available - needed {
+                0 => {
+                    tx.outputs.pop();
+                },
+                left => tx.outputs.last_mut().unwrap().amount = left,
+            }
+        }
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{fee_rate, Block, BlockUndo};
+
+    fn out(index: u32) -> OutPoint {
+        OutPoint::builder(TxId::default()).index(index).build()
+    }
+
+    #[test]
+    fn builder_matches_setters() {
+        let mut expected = Transaction::new();
+        expected.set_timestamp(7);
+        expected.add_input(out(0));
+        expected.add_output(50, b"alice");
+        let tx = Transaction::builder().timestamp(7).spend(out(0)).output(50, b"alice").build();
+        assert_eq!(tx, Ok(expected.clone()));
+
+        expected.add_input_with_sequence(out(1), 10);
+        let input = TransactionInput::builder(out(1)).relative_lock(RelativeLock::Blocks(10)).build().unwrap();
+        let tx = Transaction::builder().timestamp(7).spend(out(0)).output(50, b"alice").input(input).build();
+        assert_eq!(tx, Ok(expected));
+    }
+
+    #[test]
+    fn builder_picks_the_lowest_version() {
+        assert_eq!(out(1), OutPoint::new(TxId::default(), 1));
+        let version = |builder: TransactionBuilder| builder.spend(out(0)).output(50, b"alice").build().unwrap().get_version();
+        assert_eq!(version(Transaction::builder()), 1);
+        assert_eq!(version(Transaction::builder().lock_time(100)), 2);
+        assert_eq!(version(Transaction::builder().lock_time(100).write(b"k", b"v")), 3);
+        assert_eq!(version(Transaction::builder().write(b"k", b"v").issue(10, "gold")), 4);
+    }
+
+    #[test]
+    fn builder_errors() {
+        assert_eq!(Transaction::builder().version(5).build(), Err(BuildError::UnsupportedVersion(5)));
+        assert_eq!(Transaction::builder().version(2).write(b"k", b"v").build(), Err(BuildError::WritesNeedVersion3));
+        assert_eq!(Transaction::builder().version(1).lock_time(5).build(), Err(BuildError::LockTimeNeedsVersion2));
+        assert_eq!(Transaction::builder().version(3).spend(out(0)).issue(10, "gold").build(),
+                   Err(BuildError::AssetsNeedVersion4));
+        assert_eq!(Transaction::builder().issue(10, "gold").build(), Err(BuildError::IssuanceNeedsInput));
+        assert_eq!(Transaction::builder().spend(out(0)).spend(out(1)).spend(out(0)).build(),
+                   Err(BuildError::DuplicateInput(out(0))));
+
+        let lock = RelativeLock::Seconds((SEQUENCE_MASK as u64 + 1) * SEQUENCE_GRANULARITY);
+        assert_eq!(TransactionInput::builder(out(0)).relative_lock(lock).build(),
+                   Err(BuildError::RelativeLockTooLong(lock)));
+        let input = TransactionInput::builder(out(0)).relative_lock(RelativeLock::Seconds(1000)).build().unwrap();
+        assert_eq!(input.get_relative_lock(), Some(RelativeLock::Seconds(1024)));
+    }
+
+    #[test]
+    fn builder_pays_fee_from_change() {
+        let builder = Transaction::builder().spend(out(0)).output(300, b"bob");
+        // At 1000 per 1000 bytes the fee is the size.
+        let tx = builder.clone().fee_rate(1000).change(1000, b"alice").build().unwrap();
+        let size = tx.to_bytes().len() as u64;
+        assert_eq!(tx.get_outputs()[1].get_native_amount(), 1000 - 300 - size);
+        assert_eq!(fee_rate(1000 - tx.get_output_total().unwrap(), size as usize), 1000);
+
+        let tx = builder.clone().fee_rate(1000).change(300 + size, b"alice").build().unwrap();
+        assert_eq!(tx.get_outputs().len(), 1);
+        assert_eq!(builder.clone().fee_rate(1000).change(300 + size - 1, b"alice").build(),
+                   Err(BuildError::InsufficientFunds { needed: 300 + size, available: 300 + size - 1 }));
+
+        let mut estimator = FeeEstimator::new();
+        assert_eq!(builder.clone().estimated_fee_rate(&estimator, 1).change(1000, b"alice").build(),
+                   Err(BuildError::NoFeeEstimate(1)));
+        // The only block seen had no fee paying transactions to set a minimum.
+        estimator.connect_block(&Block::new(), &BlockUndo::default());
+        let tx = builder.estimated_fee_rate(&estimator, 1).change(1000, b"alice").build().unwrap();
+        assert_eq!(tx.get_outputs()[1].get_native_amount(), 700);
     }
 }
@@ -0,0 +1,452 @@
+extern crate byteorder;
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map;
+use std::io;
+use std::io::{Read, Write};
+use std::iter::FromIterator;
+
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use blockchain::{check_conservation, Balances, Block, OutPoint, RelativeLock, Transaction, TransactionInput, TransactionOutput, ASSET_VERSION};
+use blockchain::encode::{Decodable, Encodable};
+use util::hash::HashAlgorithm;
+
+
+// An unspent output and the block that confirmed it, which relative lock
+// times count from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coin {
+    output: TransactionOutput,
+    height: usize,
+    time: u64,
+}
+
+impl Coin {
+    pub fn new(output: TransactionOutput, height: usize, time: u64) -> Coin {
+        Coin {
+            output,
+            height,
+            time,
+        }
+    }
+
+    pub fn get_output(&self) -> &TransactionOutput {
+        &self.output
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_time(&self) -> u64 {
+        self.time
+    }
+}
+
+impl Encodable for Coin {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.output.encode(w, ASSET_VERSION)?;
+        w.write_u64::<LittleEndian>(self.height as u64)?;
+        w.write_u64::<LittleEndian>(self.time)
+    }
+}
+
+impl Decodable for Coin {
+    fn decode<R: Read>(r: &mut R) -> io::Result<Coin> {
+        Ok(Coin {
+            output: TransactionOutput::decode(r, ASSET_VERSION)?,
+            height: r.read_u64::<LittleEndian>()? as usize,
+            time: r.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+// Checks the relative lock of `input`, spending `coin`, against a block at
+// `height` with timestamp `time`.
+fn check_relative_lock(input: &TransactionInput, coin: &Coin, height: usize, time: u64) -> Result<(), String> {
+    let (unlocked, now, unit) = match input.get_relative_lock() {
+        None => return Ok(()),
+        Some(RelativeLock::Blocks(n)) => ((coin.height as u64).saturating_add(n), height as u64, "height"),
+        Some(RelativeLock::Seconds(s)) => (coin.time.saturating_add(s), time, "time"),
+    };
+    if unlocked > now {
+        return Err(format!("input {} is locked until {} {}; block {} is {}",
+                           input.get_previous_out(), unit, unlocked, unit, now));
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct UtxoSet {
+    outputs: HashMap<OutPoint, Coin>,
+}
+
+impl UtxoSet {
+    pub fn new() -> UtxoSet {
+        UtxoSet {
+            outputs: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, out: &OutPoint) -> Option<&TransactionOutput> {
+        self.outputs.get(out).map(|c| &c.output)
+    }
+
+    pub fn get_coin(&self, out: &OutPoint) -> Option<&Coin> {
+        self.outputs.get(out)
+    }
+
+    pub fn contains(&self, out: &OutPoint) -> bool {
+        self.outputs.contains_key(out)
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, OutPoint, Coin> {
+        self.outputs.iter()
+    }
+
+    // Sum of unspent native amounts, restricted to outputs paying `script`
+    // if given.
+    pub fn get_balance(&self, script: Option<&[u8]>) -> u64 {
+        self.get_balances(script).get_native()
+    }
+
+    // As `get_balance`, for every asset.
+    pub fn get_balances(&self, script: Option<&[u8]>) -> Balances {
+        let mut balances = Balances::new();
+        for o in self.outputs.values().map(|c| &c.output) {
+            if script.is_none_or(|s| o.get_script() == s) {
+                balances.add_saturating(o.get_asset(), o.get_amount());
+            }
+        }
+        balances
+    }
+
+    // Returns the fee paid by `tx`, the native amount its inputs exceed its
+    // outputs, if it could be included in a block at `height` with timestamp
+    // `time`.
+    pub fn check_transaction(&self, algorithm: HashAlgorithm, tx: &Transaction, height: usize, time: u64) -> Result<u64, String> {
+        tx.check_lock_time(algorithm, height, time)?;
+        tx.check_assets(algorithm)?;
+        if tx.get_output_total().is_none() {
+            return Err("output total overflows".to_string());
+        }
+        if tx.is_coinbase() {
+            return Ok(0);
+        }
+
+        let mut spent = Balances::new();
+        let mut seen = HashSet::new();
+        for input in tx.get_inputs() {
+            let previous_out = input.get_previous_out();
+            if !seen.insert(*previous_out) {
+                return Err(format!("input {} spent twice", previous_out));
+            }
+            match self.outputs.get(previous_out) {
+                Some(c) => {
+                    check_relative_lock(input, c, height, time)?;
+                    spent.add_output(&c.output).map_err(|e| format!("inputs: {}", e))?;
+                },
+                None => return Err(format!("input {} is missing or spent", previous_out)),
+            }
+        }
+        check_conservation(algorithm, tx, &spent)
+    }
+
+    // Validates every transaction of `block`, to be connected at `height`,
+    // against the set, in order, without changing it. The undo data lists
+    // the coin each input spends.
+    pub fn check_block(&self, algorithm: HashAlgorithm, block: &Block, height: usize) -> Result<BlockUndo, String> {
+        let mut view = UtxoView::new(self, algorithm, height, block.get_timestamp());
+        for tx in block.get_transactions() {
+            view.apply_transaction(tx)?;
+        }
+        Ok(view.undo)
+    }
+
+    // Applies a block `check_block` accepted.
+    pub fn connect_block(&mut self, algorithm: HashAlgorithm, block: &Block, height: usize) {
+        for tx in block.get_transactions() {
+            for input in tx.get_inputs() {
+                self.outputs.remove(input.get_previous_out());
+            }
+            let hash = tx.get_hash(algorithm);
+            for (index, output) in tx.get_outputs().iter().enumerate().filter(|(_, o)| !o.is_anchor()) {
+                let coin = Coin::new(output.clone(), height, block.get_timestamp());
+                self.outputs.insert(OutPoint::new(hash, index as u32), coin);
+            }
+        }
+    }
+
+    // Validates and applies `block`; a rejected block leaves the set
+    // untouched.
+    pub fn apply_block(&mut self, algorithm: HashAlgorithm, block: &Block, height: usize) -> Result<BlockUndo, String> {
+        let undo = self.check_block(algorithm, block, height)?;
+        self.connect_block(algorithm, block, height);
+        Ok(undo)
+    }
+
+    // Reverts `block`, which must be the last block applied, using the undo
+    // data `apply_block` returned for it.
+    pub fn undo_block(&mut self, algorithm: HashAlgorithm, block: &Block, undo: &BlockUndo) {
+        let mut spent = undo.spent.iter().rev();
+        for tx in block.get_transactions().iter().rev() {
+            let hash = tx.get_hash(algorithm);
+            for index in 0..tx.get_outputs().len() {
+                self.outputs.remove(&OutPoint::new(hash, index as u32));
+            }
+
+            for _ in tx.get_inputs() {
+                let (out, coin) = spent.next().unwrap();
+                self.outputs.insert(*out, coin.clone());
+            }
+        }
+    }
+}
+
+impl Default for UtxoSet {
+    fn default() -> UtxoSet {
+        UtxoSet::new()
+    }
+}
+
+impl FromIterator<(OutPoint, Coin)> for UtxoSet {
+    fn from_iter<I: IntoIterator<Item = (OutPoint, Coin)>>(iter: I) -> UtxoSet {
+        UtxoSet {
+            outputs: iter.into_iter().collect(),
+        }
+    }
+}
+
+// The coins spent by a block, in input order, so it can be disconnected.
+#[derive(Default)]
+pub struct BlockUndo {
+    spent: Vec<(OutPoint, Coin)>,
+}
+
+impl BlockUndo {
+    pub fn get_spent(&self) -> &[(OutPoint, Coin)] {
+        &self.spent
+    }
+}
+
+// Pending changes of a block on top of a `UtxoSet`.
+struct UtxoView<'a> {
+    base: &'a UtxoSet,
+    algorithm: HashAlgorithm,
+    height: usize,
+    time: u64,
+    spent: HashSet<OutPoint>,
+    created: HashMap<OutPoint, Coin>,
+    undo: BlockUndo,
+}
+
+impl<'a> UtxoView<'a> {
+    fn new(base: &'a UtxoSet, algorithm: HashAlgorithm, height: usize, time: u64) -> UtxoView<'a> {
+        UtxoView {
+            base,
+            algorithm,
+            height,
+            time,
+            spent: HashSet::new(),
+            created: HashMap::new(),
+            undo: BlockUndo {
+                spent: Vec::new(),
+            },
+        }
+    }
+
+    fn apply_transaction(&mut self, tx: &Transaction) -> Result<(), String> {
+        let hash = tx.get_hash(self.algorithm);
+        tx.check_lock_time(self.algorithm, self.height, self.time)?;
+        tx.check_assets(self.algorithm)?;
+        if tx.get_output_total().is_none() {
+            return Err(format!("tx {}: output total overflows", hash));
+        }
+
+        let mut spent = Balances::new();
+        for input in tx.get_inputs() {
+            let previous_out = input.get_previous_out();
+            let coin = match self.created.remove(previous_out) {
+                Some(c) => c,
+                None => {
+                    if self.spent.contains(previous_out) {
+                        return Err(format!("tx {}: input {} spent twice", hash, previous_out));
+                    }
+                    match self.base.get_coin(previous_out) {
+                        Some(c) => c.clone(),
+                        None => return Err(format!("tx {}: input {} is missing or spent",
+                                                   hash, previous_out)),
+                    }
+                },
+            };
+            check_relative_lock(input, &coin, self.height, self.time)
+                .map_err(|e| format!("tx {}: {}", hash, e))?;
+            self.spent.insert(*previous_out);
+            spent.add_output(&coin.output).map_err(|e| format!("tx {}: inputs: {}", hash, e))?;
+            self.undo.spent.push((*previous_out, coin));
+        }
+
+        if !tx.is_coinbase() {
+            check_conservation(self.algorithm, tx, &spent).map_err(|e| format!("tx {}: {}", hash, e))?;
+        }
+
+        for (index, output) in tx.get_outputs().iter().enumerate().filter(|(_, o)| !o.is_anchor()) {
+            let out = OutPoint::new(hash, index as u32);
+            if self.base.contains(&out) || self.created.contains_key(&out) {
+                return Err(format!("tx {}: duplicate output {}", hash, out));
+            }
+            self.created.insert(out, Coin::new(output.clone(), self.height, self.time));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_yaml;
+
+    use super::*;
+    use blockchain::{LOCK_TIME_THRESHOLD, SEQUENCE_FINAL, SEQUENCE_TYPE_FLAG};
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    const TIME: u64 = LOCK_TIME_THRESHOLD + 1_000_000;
+
+    // A set holding one coin confirmed at height 10 and time `TIME`, and an
+    // unsigned transaction spending it.
+    fn setup() -> (UtxoSet, OutPoint) {
+        let mut coinbase = Transaction::new();
+        coinbase.add_output(50, b"s");
+        let mut block = Block::new();
+        block.set_timestamp(TIME);
+        block.add_transaction(coinbase.clone());
+
+        let mut utxos = UtxoSet::new();
+        utxos.apply_block(ALGORITHM, &block, 10).unwrap();
+        (utxos, OutPoint::new(coinbase.get_hash(ALGORITHM), 0))
+    }
+
+    fn spend(out: OutPoint, sequence: u32, lock_time: u64) -> Transaction {
+        let mut tx = Transaction::new();
+        tx.add_input_with_sequence(out, sequence);
+        tx.add_output(50, b"t");
+        if lock_time != 0 {
+            tx.set_lock_time(lock_time);
+        }
+        tx
+    }
+
+    #[test]
+    fn absolute_lock_times() {
+        let (utxos, out) = setup();
+
+        let by_height = spend(out, SEQUENCE_FINAL, 20);
+        assert!(utxos.check_transaction(ALGORITHM, &by_height, 19, TIME).unwrap_err().contains("locked until height 20"));
+        assert!(utxos.check_transaction(ALGORITHM, &by_height, 20, TIME).is_ok());
+
+        let by_time = spend(out, SEQUENCE_FINAL, TIME + 600);
+        assert!(utxos.check_transaction(ALGORITHM, &by_time, 100, TIME + 599).is_err());
+        assert!(utxos.check_transaction(ALGORITHM, &by_time, 11, TIME + 600).is_ok());
+    }
+
+    #[test]
+    fn relative_lock_times() {
+        let (utxos, out) = setup();
+
+        let by_blocks = spend(out, 5, 0);
+        assert!(utxos.check_transaction(ALGORITHM, &by_blocks, 14, TIME).unwrap_err().contains("locked until height 15"));
+        assert!(utxos.check_transaction(ALGORITHM, &by_blocks, 15, TIME).is_ok());
+
+        // Two units of 512 seconds.
+        let by_time = spend(out, SEQUENCE_TYPE_FLAG | 2, 0);
+        assert!(utxos.check_transaction(ALGORITHM, &by_time, 11, TIME + 1023).is_err());
+        assert!(utxos.check_transaction(ALGORITHM, &by_time, 11, TIME + 1024).is_ok());
+
+        // Coins created earlier in the same block count from that block.
+        let first = spend(out, SEQUENCE_FINAL, 0);
+        for &(sequence, ok) in &[(0, true), (1, false)] {
+            let mut block = Block::new();
+            block.set_timestamp(TIME);
+            block.add_transaction(first.clone());
+            block.add_transaction(spend(OutPoint::new(first.get_hash(ALGORITHM), 0), sequence, 0));
+            assert_eq!(utxos.check_block(ALGORITHM, &block, 11).is_ok(), ok);
+        }
+    }
+
+    #[test]
+    fn issued_assets() {
+        let (mut utxos, out) = setup();
+        let mut issue = Transaction::new();
+        issue.add_input(out);
+        issue.set_issuance(1000, "gold");
+        let gold = issue.get_issued_asset(ALGORITHM).unwrap();
+        issue.add_asset_output(gold, 1000, b"a");
+        issue.add_output(40, b"a");
+        assert_eq!(utxos.check_transaction(ALGORITHM, &issue, 11, TIME), Ok(10));
+
+        let mut block = Block::new();
+        block.add_transaction(issue.clone());
+        utxos.apply_block(ALGORITHM, &block, 11).unwrap();
+        let balances = utxos.get_balances(Some(b"a"));
+        assert_eq!((balances.get_native(), balances.get(&gold)), (40, 1000));
+        assert_eq!(utxos.get_balance(None), 40);
+
+        // Moving gold must keep its total, and only the native coin pays fees.
+        let mut spend = Transaction::new();
+        spend.add_input(OutPoint::new(issue.get_hash(ALGORITHM), 0));
+        spend.add_asset_output(gold, 999, b"b");
+        assert!(utxos.check_transaction(ALGORITHM, &spend, 12, TIME).unwrap_err().contains("differ from inputs 1000"));
+        spend.add_asset_output(gold, 1, b"a");
+        assert_eq!(utxos.check_transaction(ALGORITHM, &spend, 12, TIME), Ok(0));
+
+        // A coinbase cannot mint an issued asset.
+        let mut coinbase = Transaction::new();
+        coinbase.add_asset_output(gold, 5, b"a");
+        assert!(utxos.check_transaction(ALGORITHM, &coinbase, 12, TIME).unwrap_err().contains("must be native"));
+    }
+
+    #[test]
+    fn output_totals_must_not_overflow() {
+        let mut coinbase = Transaction::new();
+        coinbase.add_output(u64::MAX, b"s");
+        let mut block = Block::new();
+        block.add_transaction(coinbase.clone());
+        let mut utxos = UtxoSet::new();
+        utxos.apply_block(ALGORITHM, &block, 0).unwrap();
+
+        // Two outputs of the whole input would create coins.
+        let mut spend = Transaction::new();
+        spend.add_input(OutPoint::new(coinbase.get_hash(ALGORITHM), 0));
+        spend.add_output(u64::MAX, b"a");
+        spend.add_output(u64::MAX, b"b");
+        assert!(utxos.check_transaction(ALGORITHM, &spend, 1, TIME).unwrap_err().contains("overflows"));
+        let mut block = Block::new();
+        block.add_transaction(spend);
+        assert!(utxos.check_block(ALGORITHM, &block, 1).err().unwrap().contains("overflows"));
+        assert_eq!(utxos.len(), 1);
+
+        let mut coinbase = Transaction::new();
+        coinbase.add_output(u64::MAX, b"a");
+        coinbase.add_output(1, b"b");
+        assert!(utxos.check_transaction(ALGORITHM, &coinbase, 1, TIME).is_err());
+    }
+
+    #[test]
+    fn lock_times_need_version_2() {
+        let (utxos, out) = setup();
+        // Only a chain file can give a version 1 transaction a lock time.
+        let document = format!("version: 1\ntimestamp: 0\ninputs:\n  - hash: {}\n    index: 0\n\
+                                outputs:\n  - amount: 50\nlock_time: 20\n", out.get_tx_hash());
+        let tx: Transaction = serde_yaml::from_str(&document).unwrap();
+        assert!(utxos.check_transaction(ALGORITHM, &tx, 100, TIME).unwrap_err().contains("need version 2"));
+    }
+}
@@ -0,0 +1,192 @@
+extern crate rayon;
+
+use std::sync::Arc;
+
+use self::rayon::prelude::*;
+
+use blockchain::{Block, BlockUndo, Transaction, TransactionOutput};
+use util::hash::HashAlgorithm;
+use util::hex::ToHex;
+
+
+// Checks one input against the output it spends, e.g. its script and
+// signature. Inputs are verified concurrently and in no particular order, so
+// a verifier must not depend on other inputs.
+pub trait InputVerifier: Send + Sync {
+    fn verify(&self, tx: &Transaction, input: usize, spent: &TransactionOutput) -> Result<(), String>;
+}
+
+// Outputs carry no spending conditions yet, so any input may spend them.
+pub struct AcceptAll;
+
+impl InputVerifier for AcceptAll {
+    fn verify(&self, _tx: &Transaction, _input: usize, _spent: &TransactionOutput) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Checks that need nothing but the block itself.
+pub fn check_block(algorithm: HashAlgorithm, block: &Block) -> Result<(), String> {
+    let merkle_root = block.compute_merkle_root(algorithm);
+    if merkle_root != block.get_merkle_root() {
+        return Err(format!("append expected merkle_root '{}'; actual '{}'",
+                           merkle_root.to_hex(), block.get_merkle_root().to_hex()));
+    }
+    Ok(())
+}
+
+struct InputJob<'a> {
+    tx: &'a Transaction,
+    input: usize,
+    spent: &'a TransactionOutput,
+}
+
+// Runs an `InputVerifier` over every input of a block, on rayon's thread pool
+// unless created with `serial`. Both report the first failing input in block
+// order, so the result does not depend on scheduling.
+#[derive(Clone)]
+pub struct Validator {
+    verifier: Arc<dyn InputVerifier>,
+    parallel: bool,
+}
+
+impl Validator {
+    pub fn new(verifier: Arc<dyn InputVerifier>) -> Validator {
+        Validator {
+            verifier,
+            parallel: true,
+        }
+    }
+
+    pub fn serial(verifier: Arc<dyn InputVerifier>) -> Validator {
+        Validator {
+            verifier,
+            parallel: false,
+        }
+    }
+
+    pub fn is_parallel(&self) -> bool {
+        self.parallel
+    }
+
+    // `undo` lists the coin spent by each input of `block`, in order, as
+    // `UtxoSet::check_block` returns it.
+    pub fn verify_block(&self, algorithm: HashAlgorithm, block: &Block, undo: &BlockUndo) -> Result<(), String> {
+        let mut spent = undo.get_spent().iter();
+        let mut jobs = Vec::with_capacity(undo.get_spent().len());
+        for tx in block.get_transactions() {
+            for input in 0..tx.get_inputs().len() {
+                let (_, coin) = spent.next().unwrap();
+                jobs.push(InputJob { tx, input, spent: coin.get_output() });
+            }
+        }
+        self.run(algorithm, &jobs)
+    }
+
+    // Verifies a loose transaction; `spent` holds the output each input
+    // spends.
+    pub fn verify_transaction(&self, algorithm: HashAlgorithm, tx: &Transaction, spent: &[&TransactionOutput]) -> Result<(), String> {
+        let jobs: Vec<InputJob> = spent.iter().enumerate()
+            .map(|(input, output)| InputJob { tx, input, spent: output })
+            .collect();
+        self.run(algorithm, &jobs)
+    }
+
+    fn run(&self, algorithm: HashAlgorithm, jobs: &[InputJob]) -> Result<(), String> {
+        let verify = |job: &InputJob| {
+            self.verifier.verify(job.tx, job.input, job.spent)
+                .err()
+                .map(|e| format!("tx {}: input {}: {}", job.tx.get_hash(algorithm), job.input, e))
+        };
+
+        let failure = if self.parallel {
+            jobs.par_iter().find_map_first(verify)
+        } else {
+            jobs.iter().find_map(verify)
+        };
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{OutPoint, UtxoSet};
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    // Rejects inputs spending outputs with an odd amount.
+    struct RejectOdd;
+
+    impl InputVerifier for RejectOdd {
+        fn verify(&self, _tx: &Transaction, _input: usize, spent: &TransactionOutput) -> Result<(), String> {
+            if spent.get_amount() & 1 == 1 {
+                return Err(format!("odd amount {}", spent.get_amount()));
+            }
+            Ok(())
+        }
+    }
+
+    // A coinbase with `amounts` as outputs and a block spending each of them
+    // in its own transaction.
+    fn blocks(amounts: &[u64]) -> (Block, Block) {
+        let mut coinbase = Transaction::new();
+        for &amount in amounts {
+            coinbase.add_output(amount, b"s");
+        }
+        let mut first = Block::new();
+        first.add_transaction(coinbase.clone());
+        first.update_merkle_root(ALGORITHM);
+
+        let mut second = Block::new();
+        second.set_previous(first.get_hash(ALGORITHM));
+        for (index, &amount) in amounts.iter().enumerate() {
+            let mut tx = Transaction::new();
+            tx.add_input(OutPoint::new(coinbase.get_hash(ALGORITHM), index as u32));
+            tx.add_output(amount, b"t");
+            second.add_transaction(tx);
+        }
+        second.update_merkle_root(ALGORITHM);
+        (first, second)
+    }
+
+    fn verify(validator: &Validator, amounts: &[u64]) -> Result<(), String> {
+        let (first, second) = blocks(amounts);
+        let mut utxos = UtxoSet::new();
+        utxos.apply_block(ALGORITHM, &first, 0).unwrap();
+        let undo = utxos.check_block(ALGORITHM, &second, 1).unwrap();
+        validator.verify_block(ALGORITHM, &second, &undo)
+    }
+
+    #[test]
+    fn serial_and_parallel_agree() {
+        let serial = Validator::serial(Arc::new(RejectOdd));
+        let parallel = Validator::new(Arc::new(RejectOdd));
+
+        let valid: Vec<u64> = (0..200).map(|i| i * 2).collect();
+        assert!(verify(&serial, &valid).is_ok());
+        assert!(verify(&parallel, &valid).is_ok());
+
+        // Several failures; both must report the first one.
+        let mut invalid = valid.clone();
+        invalid[137] = 7;
+        invalid[151] = 9;
+        invalid[190] = 11;
+        let expected = verify(&serial, &invalid).unwrap_err();
+        assert!(expected.ends_with("input 0: odd amount 7"));
+        for _ in 0..10 {
+            assert_eq!(verify(&parallel, &invalid).unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn check_block_rejects_wrong_merkle_root() {
+        let (mut first, _) = blocks(&[1, 2]);
+        assert!(check_block(ALGORITHM, &first).is_ok());
+        first.add_transaction(Transaction::new());
+        assert!(check_block(ALGORITHM, &first).is_err());
+    }
+}
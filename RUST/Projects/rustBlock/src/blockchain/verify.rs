@@ -0,0 +1,254 @@
+extern crate serde_yaml;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use blockchain::{check_block, check_block_limits, Block, BlockHash, ChainParams, StateStore, TxId, UtxoSet};
+use blockchain::chain::read_documents;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    Parse,
+    Linkage,
+    MerkleRoot,
+    Limits,
+    Timestamp,
+    Utxo,
+    State,
+    DuplicateTransaction,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Problem {
+    pub height: usize,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+// Everything wrong with the blocks of a chain file. Unlike `read_chain`,
+// which stops at the first bad block, the walk goes on to the end, treating
+// each block as if it had been accepted. Warnings are for rules the chain
+// does not enforce; they do not end the valid prefix.
+#[derive(Serialize)]
+pub struct VerifyReport {
+    blocks: usize,
+    valid_blocks: usize,
+    valid_tip: Option<BlockHash>,
+    problems: Vec<Problem>,
+    warnings: Vec<Problem>,
+    #[serde(skip)]
+    documents: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn get_problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    pub fn get_warnings(&self) -> &[Problem] {
+        &self.warnings
+    }
+
+    // The number of blocks before the first problem.
+    pub fn get_valid_blocks(&self) -> usize {
+        self.valid_blocks
+    }
+
+    pub fn get_valid_tip(&self) -> Option<BlockHash> {
+        self.valid_tip
+    }
+
+    // Writes the chain parameters and the valid blocks to `file`, exactly as
+    // they appear in the verified file.
+    pub fn write_valid_prefix(&self, file: &Path) -> io::Result<()> {
+        let mut f = File::create(file)?;
+        f.write_all(self.documents[..self.valid_blocks + 1].join("---").as_bytes())?;
+        f.sync_all()
+    }
+
+    // Cuts `file`, the file that was verified, back to its valid blocks.
+    pub fn truncate(&self, file: &Path) -> io::Result<()> {
+        let tmp = file.with_extension("tmp");
+        self.write_valid_prefix(&tmp)?;
+        fs::rename(&tmp, file)
+    }
+}
+
+struct Walk {
+    report: VerifyReport,
+    height: usize,
+}
+
+impl Walk {
+    fn problem(&mut self, kind: ProblemKind, message: String) {
+        if self.report.problems.is_empty() {
+            self.report.valid_blocks = self.height;
+        }
+        self.report.problems.push(Problem { height: self.height, kind, message });
+    }
+
+    fn warning(&mut self, kind: ProblemKind, message: String) {
+        self.report.warnings.push(Problem { height: self.height, kind, message });
+    }
+}
+
+// Checks every block of `file` for broken links, wrong Merkle roots, blocks
+// over the chain's limits, spends the UTXO set does not allow and
+// transactions that appear twice, and warns of timestamps going backwards.
+pub fn verify_chain(file: &Path) -> Result<VerifyReport, String> {
+    let documents = read_documents(file)?;
+    let params: ChainParams = if documents[0].trim().is_empty() {
+        ChainParams::new()
+    } else {
+        serde_yaml::from_str(&documents[0]).map_err(|e| format!("chain params: {}", e))?
+    };
+    let algorithm = params.get_hash_algorithm();
+
+    let blocks = documents.len() - 1;
+    let mut walk = Walk {
+        report: VerifyReport {
+            blocks,
+            valid_blocks: blocks,
+            valid_tip: None,
+            problems: Vec::new(),
+            warnings: Vec::new(),
+            documents: Vec::new(),
+        },
+        height: 0,
+    };
+    let mut previous: Option<Block> = None;
+    let mut utxos = UtxoSet::new();
+    let mut state = StateStore::new();
+    let mut seen: HashMap<TxId, usize> = HashMap::new();
+
+    for (height, document) in documents[1..].iter().enumerate() {
+        walk.height = height;
+        let block: Block = match serde_yaml::from_str(document) {
+            Ok(block) => block,
+            Err(e) => {
+                walk.problem(ProblemKind::Parse, e.to_string());
+                continue;
+            },
+        };
+
+        let expected = previous.as_ref().map(|b| b.get_hash(algorithm)).unwrap_or_default();
+        if block.get_previous() != expected {
+            walk.problem(ProblemKind::Linkage,
+                         format!("expected previous '{}'; actual '{}'", expected, block.get_previous()));
+        }
+        if let Err(e) = check_block(algorithm, &block) {
+            walk.problem(ProblemKind::MerkleRoot, e);
+        }
+        if let Err(e) = check_block_limits(&block, &params) {
+            walk.problem(ProblemKind::Limits, e.to_string());
+        }
+
+        // `append` accepts these, so they do not make a block invalid.
+        if let Some(ref previous) = previous {
+            if block.get_timestamp() < previous.get_timestamp() {
+                walk.warning(ProblemKind::Timestamp,
+                             format!("timestamp {} is before the previous block's {}",
+                                     block.get_timestamp(), previous.get_timestamp()));
+            }
+        }
+        for tx in block.get_transactions() {
+            let hash = tx.get_hash(algorithm);
+            if tx.get_timestamp() > block.get_timestamp() {
+                walk.warning(ProblemKind::Timestamp,
+                             format!("tx {}: timestamp {} is after the block's {}",
+                                     hash, tx.get_timestamp(), block.get_timestamp()));
+            }
+            match seen.get(&hash) {
+                Some(first) => walk.problem(ProblemKind::DuplicateTransaction,
+                                            format!("tx {} is also in block {}", hash, first)),
+                None => {
+                    seen.insert(hash, height);
+                },
+            }
+        }
+
+        // Connected even when rejected, so one bad spend is reported once
+        // rather than again for every block that depends on it.
+        if let Err(e) = utxos.check_block(algorithm, &block, height) {
+            walk.problem(ProblemKind::Utxo, e);
+        }
+        utxos.connect_block(algorithm, &block, height);
+        if let Err(e) = state.check_block(algorithm, &block) {
+            walk.problem(ProblemKind::State, e);
+        }
+        state.connect_block(&block);
+
+        if walk.report.problems.is_empty() {
+            walk.report.valid_tip = Some(block.get_hash(algorithm));
+        }
+        previous = Some(block);
+    }
+
+    walk.report.documents = documents;
+    Ok(walk.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use blockchain::sample::CHAIN;
+
+    fn write_temp(name: &str, contents: &str) -> ::std::path::PathBuf {
+        let path = env::temp_dir().join(format!("rustychain-{}-{}.yaml", name, process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn clean_chain_has_no_problems() {
+        let path = write_temp("clean", CHAIN);
+        let report = verify_chain(&path).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.get_valid_blocks(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_every_problem_and_exports_valid_prefix() {
+        // Spending more than the first coinbase breaks the last block's
+        // Merkle root and its spend.
+        let path = write_temp("broken", &CHAIN.replacen("amount: 3000", "amount: 6000", 1));
+        let report = verify_chain(&path).unwrap();
+        let kinds: Vec<ProblemKind> = report.get_problems().iter().map(|p| p.kind).collect();
+        assert_eq!(kinds, vec![ProblemKind::MerkleRoot, ProblemKind::Utxo]);
+        assert!(report.get_problems().iter().all(|p| p.height == 2));
+        assert_eq!(report.get_valid_blocks(), 2);
+
+        let prefix = path.with_extension("prefix");
+        report.write_valid_prefix(&prefix).unwrap();
+        let repaired = verify_chain(&prefix).unwrap();
+        assert!(repaired.is_ok());
+        assert_eq!(repaired.get_valid_tip(), report.get_valid_tip());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&prefix).unwrap();
+    }
+
+    #[test]
+    fn timestamps_are_warnings() {
+        // The last block's header is stamped before the block below it and
+        // before both of its transactions.
+        let path = write_temp("timestamps", &CHAIN.replacen("timestamp: 1507001200", "timestamp: 1507000300", 1));
+        let report = verify_chain(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.get_valid_blocks(), 3);
+        assert_eq!(report.get_warnings().len(), 3);
+        assert!(report.get_warnings().iter().all(|w| w.kind == ProblemKind::Timestamp && w.height == 2));
+    }
+}
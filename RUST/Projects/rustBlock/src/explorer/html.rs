@@ -0,0 +1,63 @@
+use util::hash::HASH256_BYTES;
+
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Escapes `text`, turning every hex encoded hash in it into a search link so
+// the `Display` output of blocks and transactions can be browsed.
+pub fn linkify(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut rest = text;
+    while !rest.is_empty() {
+        let start = rest.find(|c: char| c.is_ascii_hexdigit()).unwrap_or(rest.len());
+        out.push_str(&escape(&rest[..start]));
+        rest = &rest[start..];
+
+        let end = rest.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(rest.len());
+        let word = &rest[..end];
+        if word.len() == HASH256_BYTES * 2 {
+            out.push_str(&format!("<a href=\"/search?q={0}\">{0}</a>", word));
+        } else {
+            out.push_str(word);
+        }
+        rest = &rest[end..];
+    }
+    out
+}
+
+pub fn page(title: &str, body: &str) -> String {
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title} - rustychain</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+pre, td.mono {{ font-family: monospace; }}
+table {{ border-collapse: collapse; }}
+td, th {{ padding: 0.2em 1em 0.2em 0; text-align: left; }}
+</style>
+</head>
+<body>
+<form action="/search"><a href="/">rustychain</a>
+<input name="q" size="70" placeholder="block height, block hash or transaction hash">
+<input type="submit" value="search"></form>
+<h1>{title}</h1>
+{body}
+</body>
+</html>
+"#, title = escape(title), body = body)
+}
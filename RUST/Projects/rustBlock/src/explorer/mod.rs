@@ -0,0 +1,5 @@
+
+mod html;
+mod server;
+
+pub use self::server::Explorer;
@@ -0,0 +1,271 @@
+use std::io;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, RwLock};
+
+use blockchain::{BlockChain, BlockHash, OutPoint, TransactionOutput, TxId};
+use explorer::html::{escape, linkify, page};
+use util::hex::ToHex;
+use util::http::{self, Request, Response};
+
+
+const RECENT_BLOCKS: usize = 20;
+
+// Read-only HTML views of a `BlockChain`.
+#[derive(Clone)]
+pub struct Explorer {
+    chain: Arc<RwLock<BlockChain>>,
+}
+
+fn html(status: u16, body: String) -> Response {
+    Response::new(status, "text/html; charset=utf-8", body.into_bytes())
+}
+
+fn not_found(what: &str) -> Response {
+    html(404, page("Not found", &format!("<p>{}</p>", escape(what))))
+}
+
+// The amount of an output, followed by its asset unless native.
+fn amount(output: &TransactionOutput) -> String {
+    if output.is_native() {
+        output.get_amount().to_string()
+    } else {
+        format!("{} <span class=\"mono\">{}</span>", output.get_amount(), output.get_asset())
+    }
+}
+
+impl Explorer {
+    // `chain` may be shared with, and updated by, other threads.
+    pub fn new(chain: Arc<RwLock<BlockChain>>) -> Explorer {
+        Explorer { chain }
+    }
+
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let explorer = self.clone();
+        http::serve(addr, move |request| explorer.handle(request))
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        if request.method != "GET" {
+            return Response::text(405, "only GET is supported\n");
+        }
+
+        let chain = self.chain.read().unwrap();
+        let route = request.route();
+        if route == "/" {
+            index_page(&chain)
+        } else if route == "/chain" {
+            html(200, page("Chain", &format!("<pre>{}</pre>", linkify(&chain.to_string()))))
+        } else if route == "/search" {
+            search(&chain, request.query("q").unwrap_or_default().trim())
+        } else if let Some(arg) = route.strip_prefix("/block/") {
+            match arg.parse::<usize>().ok().or_else(|| arg.parse().ok().and_then(|h| chain.get_height(&h))) {
+                Some(height) if chain.get_block(height).is_some() => block_page(&chain, height),
+                _ => not_found(&format!("no block {}", arg)),
+            }
+        } else if let Some(arg) = route.strip_prefix("/tx/") {
+            match arg.parse() {
+                Ok(hash) => tx_page(&chain, &hash),
+                Err(_) => not_found(&format!("no transaction {}", arg)),
+            }
+        } else {
+            not_found(&format!("no page {}", route))
+        }
+    }
+}
+
+// Pruned blocks keep their heights and hashes but have no page.
+fn search(chain: &BlockChain, q: &str) -> Response {
+    if let Ok(height) = q.parse::<usize>() {
+        if chain.get_block(height).is_some() {
+            return block_page(chain, height);
+        }
+    }
+    if let Ok(hash) = q.parse::<BlockHash>() {
+        if let Some(height) = chain.get_height(&hash).filter(|&height| chain.get_block(height).is_some()) {
+            return block_page(chain, height);
+        }
+    }
+    if let Ok(hash) = q.parse::<TxId>() {
+        if chain.find_transaction(&hash).is_some() {
+            return tx_page(chain, &hash);
+        }
+    }
+    not_found(&format!("nothing matches '{}'", q))
+}
+
+fn index_page(chain: &BlockChain) -> Response {
+    let mut body = String::new();
+    match chain.get_best_hash() {
+        Some(hash) => {
+            body.push_str(&format!("<p>height {}, tip {}</p>\n", chain.len() - 1, linkify(&hash.to_string())));
+        },
+        None => body.push_str("<p>the chain is empty</p>\n"),
+    }
+    body.push_str(&format!("<p>{} unspent outputs worth {}</p>\n",
+                           chain.get_utxos().len(), chain.get_utxos().get_balance(None)));
+
+    // The `BlockChain` listing, newest first.
+    let listing = chain.to_string();
+    let recent: Vec<&str> = listing.lines().rev().take(RECENT_BLOCKS).collect();
+    body.push_str(&format!("<h2>Recent blocks</h2>\n<pre>{}</pre>\n", linkify(&recent.join("\n"))));
+    body.push_str("<p><a href=\"/chain\">all blocks</a></p>\n");
+
+    html(200, page("Chain tip", &body))
+}
+
+fn block_page(chain: &BlockChain, height: usize) -> Response {
+    let block = chain.get_block(height).unwrap();
+
+    let mut nav = Vec::new();
+    if height > 0 && chain.get_block(height - 1).is_some() {
+        nav.push(format!("<a href=\"/block/{}\">previous</a>", height - 1));
+    }
+    if height + 1 < chain.len() {
+        nav.push(format!("<a href=\"/block/{}\">next</a>", height + 1));
+    }
+
+    // Hashes depend on the chain's algorithm, so the block's listing leaves
+    // them out.
+    let algorithm = chain.get_hash_algorithm();
+    let txids: Vec<String> = block.get_tx_hashes(algorithm).iter().map(|h| h.to_string()).collect();
+    let body = format!("<p>block {}</p>\n<p>height {}, {} transactions, {}</p>\n<pre>{}</pre>\n<pre>{}</pre>\n",
+                       block.get_hash(algorithm), height, block.get_transactions().len(), nav.join(" | "),
+                       linkify(&txids.join("\n")), linkify(&block.to_string()));
+    html(200, page(&format!("Block {}", height), &body))
+}
+
+fn tx_page(chain: &BlockChain, hash: &TxId) -> Response {
+    let (height, tx) = match chain.find_transaction(hash) {
+        Some(found) => found,
+        None => return not_found(&format!("no transaction {}", hash)),
+    };
+
+    let mut body = format!("<p>tx {0}</p>\n<p>confirmed in <a href=\"/block/{1}\">block {1}</a> ({2} confirmations)</p>\n",
+                           hash, height, chain.len() - height);
+    if let Some(asset) = tx.get_issued_asset(chain.get_hash_algorithm()) {
+        body.push_str(&format!("<p>issues asset {}</p>\n", asset));
+    }
+    body.push_str(&format!("<pre>{}</pre>\n", linkify(&tx.to_string())));
+
+    // Amounts of the spent outputs are not part of the transaction, but the
+    // block's undo data lists the coins its inputs spent, in order.
+    let block = chain.get_block(height).unwrap();
+    let first_input: usize = block.get_transactions().iter()
+        .take_while(|other| other.get_hash(chain.get_hash_algorithm()) != *hash)
+        .map(|other| other.get_inputs().len())
+        .sum();
+    let spent_coins = chain.get_undo(height).map_or(&[][..], |undo| undo.get_spent());
+    let mut input_total = 0u64;
+    body.push_str("<h2>Inputs</h2>\n<table>\n<tr><th>spends</th><th>amount</th></tr>\n");
+    for (i, input) in tx.get_inputs().iter().enumerate() {
+        let previous_out = input.get_previous_out();
+        let spent = spent_coins.get(first_input + i).map(|(_, coin)| coin.get_output());
+        input_total = input_total.saturating_add(spent.map_or(0, |o| o.get_native_amount()));
+        body.push_str(&format!("<tr><td class=\"mono\">{}</td><td>{}</td></tr>\n",
+                               linkify(&previous_out.to_string()),
+                               spent.map(amount).unwrap_or_else(|| "?".to_string())));
+    }
+    body.push_str("</table>\n");
+
+    body.push_str("<h2>Outputs</h2>\n<table>\n<tr><th>n</th><th>amount</th><th>script</th><th></th></tr>\n");
+    for (n, output) in tx.get_outputs().iter().enumerate() {
+        let spent = !chain.get_utxos().contains(&OutPoint::new(*hash, n as u32));
+        body.push_str(&format!("<tr><td>{}</td><td>{}</td><td class=\"mono\">{}</td><td>{}</td></tr>\n",
+                               n, amount(output), output.get_script().to_hex(),
+                               if spent { "spent" } else { "unspent" }));
+    }
+    body.push_str("</table>\n");
+
+    if !tx.is_coinbase() {
+        let fee = tx.get_output_total().map_or(0, |total| input_total.saturating_sub(total));
+        body.push_str(&format!("<p>fee {}</p>\n", fee));
+    }
+    html(200, page("Transaction", &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::sample;
+    use util::hash::HashAlgorithm;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn explorer() -> Explorer {
+        Explorer::new(Arc::new(RwLock::new(sample::chain())))
+    }
+
+    fn get(explorer: &Explorer, path: &str) -> (u16, String) {
+        let request = Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        let response = explorer.handle(&request);
+        (response.status, String::from_utf8(response.body).unwrap())
+    }
+
+    #[test]
+    fn routes() {
+        let explorer = explorer();
+        let (tip, spend) = {
+            let chain = explorer.chain.read().unwrap();
+            (chain.get_best_hash().unwrap(), chain.get_block(2).unwrap().get_transactions()[1].get_hash(ALGORITHM))
+        };
+
+        assert_eq!(get(&explorer, "/").0, 200);
+        assert!(get(&explorer, "/block/1").1.contains("<h1>Block 1</h1>"));
+        assert!(get(&explorer, &format!("/block/{}", tip)).1.contains("<h1>Block 2</h1>"));
+        assert_eq!(get(&explorer, "/block/3").0, 404);
+
+        // The spend's input amount comes from the coin it spent.
+        let (status, body) = get(&explorer, &format!("/tx/{}", spend));
+        assert_eq!(status, 200);
+        assert!(body.contains("<td>5000</td>"));
+        assert!(body.contains("<p>fee 10</p>"));
+
+        assert!(get(&explorer, "/search?q=0").1.contains("<h1>Block 0</h1>"));
+        assert!(get(&explorer, &format!("/search?q={}", tip)).1.contains("<h1>Block 2</h1>"));
+        assert!(get(&explorer, &format!("/search?q=+{}+", spend)).1.contains("<h1>Transaction</h1>"));
+        assert_eq!(get(&explorer, "/search?q=7").0, 404);
+        assert_eq!(get(&explorer, "/nowhere").0, 404);
+
+        let post = Request {
+            method: "POST".to_string(),
+            path: "/".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        assert_eq!(explorer.handle(&post).status, 405);
+    }
+
+    #[test]
+    fn pruned_blocks_have_no_page() {
+        let explorer = explorer();
+        let first = {
+            let mut chain = explorer.chain.write().unwrap();
+            chain.set_prune_depth(1).unwrap();
+            chain.get_header(0).unwrap().get_hash(ALGORITHM)
+        };
+
+        assert_eq!(get(&explorer, "/block/0").0, 404);
+        assert_eq!(get(&explorer, &format!("/block/{}", first)).0, 404);
+        assert_eq!(get(&explorer, "/search?q=0").0, 404);
+        assert_eq!(get(&explorer, &format!("/search?q={}", first)).0, 404);
+        assert!(get(&explorer, "/search?q=2").1.contains("<h1>Block 2</h1>"));
+    }
+
+    #[test]
+    fn escapes_what_it_echoes() {
+        let explorer = explorer();
+        for path in &["/search?q=%3Cscript%3Ealert(1)%3C%2Fscript%3E", "/block/<script>alert(1)</script>",
+                      "/tx/<script>alert(1)</script>", "/<script>alert(1)</script>"] {
+            let (status, body) = get(&explorer, path);
+            assert_eq!(status, 404);
+            assert!(body.contains("&lt;script&gt;alert(1)&lt;/script&gt;"), "{}", body);
+            assert!(!body.contains("<script>"), "{}", body);
+        }
+        assert_eq!(linkify("<a> & 'b'"), "&lt;a&gt; &amp; &#39;b&#39;");
+    }
+}
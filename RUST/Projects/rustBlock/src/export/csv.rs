@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use export::{open_table, Sink, Table, Value, TABLES};
+
+
+// One `<table>.csv` file per table in a directory, each starting with a
+// header row.
+pub struct CsvSink {
+    dir: PathBuf,
+    files: HashMap<&'static str, BufWriter<File>>,
+}
+
+impl CsvSink {
+    pub fn new(dir: PathBuf) -> CsvSink {
+        CsvSink {
+            dir,
+            files: HashMap::new(),
+        }
+    }
+}
+
+// Quotes fields containing a separator, quote or line break, as RFC 4180
+// does.
+fn field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl Sink for CsvSink {
+    fn start(&mut self, from: usize) -> Result<(), String> {
+        for table in TABLES.iter() {
+            // Heights come first and are never quoted.
+            let height = |line: &str| line.split(',').next().and_then(|h| h.parse::<u64>().ok());
+            let (mut file, empty) = open_table(&self.dir, table, "csv", from, height)?;
+            if empty {
+                writeln!(file, "{}", table.columns.join(",")).map_err(|e| format!("{}: {}", table.name, e))?;
+            }
+            self.files.insert(table.name, file);
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, table: &Table, row: &[Value]) -> Result<(), String> {
+        let fields: Vec<String> = row.iter()
+            .map(|value| match *value {
+                Value::Int(n) | Value::Amount(n) => n.to_string(),
+                Value::Text(ref s) => field(s),
+                Value::Null => String::new(),
+            })
+            .collect();
+        let file = self.files.get_mut(table.name).unwrap();
+        writeln!(file, "{}", fields.join(",")).map_err(|e| format!("{}: {}", table.name, e))
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        for (name, mut file) in self.files.drain() {
+            file.flush().map_err(|e| format!("{}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use export::export;
+    use blockchain::sample;
+    use export::tests::temp_path;
+    use util::hash::HashAlgorithm;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    #[test]
+    fn quotes_only_when_needed() {
+        assert_eq!(field("00ff"), "00ff");
+        assert_eq!(field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn writes_one_header() {
+        let chain = sample::chain();
+        let dir = temp_path("csv");
+        export(&chain, 0, &mut CsvSink::new(dir.clone())).unwrap();
+        export(&chain, 3, &mut CsvSink::new(dir.clone())).unwrap();
+        export(&chain, 1, &mut CsvSink::new(dir.clone())).unwrap();
+
+        let blocks = fs::read_to_string(dir.join("blocks.csv")).unwrap();
+        let lines: Vec<&str> = blocks.lines().collect();
+        assert_eq!(lines[0], "height,hash,previous,merkle_root,version,timestamp");
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with(&format!("0,{},", chain.get_block(0).unwrap().get_hash(ALGORITHM))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use self::serde_json::{Map, Value as Json};
+
+use export::{open_table, Sink, Table, Value, TABLES};
+
+
+// Newline-delimited JSON: one `<table>.jsonl` file per table in a directory,
+// one object per row.
+pub struct JsonSink {
+    dir: PathBuf,
+    files: HashMap<&'static str, BufWriter<File>>,
+}
+
+impl JsonSink {
+    pub fn new(dir: PathBuf) -> JsonSink {
+        JsonSink {
+            dir,
+            files: HashMap::new(),
+        }
+    }
+}
+
+impl Sink for JsonSink {
+    fn start(&mut self, from: usize) -> Result<(), String> {
+        for table in TABLES.iter() {
+            let height = |line: &str| serde_json::from_str::<Json>(line).ok().and_then(|row| row["height"].as_u64());
+            let (file, _) = open_table(&self.dir, table, "jsonl", from, height)?;
+            self.files.insert(table.name, file);
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, table: &Table, row: &[Value]) -> Result<(), String> {
+        let object: Map<String, Json> = table.columns.iter().zip(row)
+            .map(|(column, value)| {
+                let value = match *value {
+                    Value::Int(n) | Value::Amount(n) => Json::from(n),
+                    Value::Text(ref s) => Json::from(s.clone()),
+                    Value::Null => Json::Null,
+                };
+                (column.to_string(), value)
+            })
+            .collect();
+        let file = self.files.get_mut(table.name).unwrap();
+        writeln!(file, "{}", Json::Object(object)).map_err(|e| format!("{}: {}", table.name, e))
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        for (name, mut file) in self.files.drain() {
+            file.flush().map_err(|e| format!("{}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use export::export;
+    use blockchain::sample;
+    use export::tests::temp_path;
+
+    #[test]
+    fn replaces_rows_from_a_height() {
+        let chain = sample::chain();
+        let dir = temp_path("json");
+        export(&chain, 0, &mut JsonSink::new(dir.clone())).unwrap();
+        export(&chain, 2, &mut JsonSink::new(dir.clone())).unwrap();
+
+        let blocks = fs::read_to_string(dir.join("blocks.jsonl")).unwrap();
+        let heights: Vec<u64> = blocks.lines()
+            .map(|line| serde_json::from_str::<Json>(line).unwrap()["height"].as_u64().unwrap())
+            .collect();
+        assert_eq!(heights, vec![0, 1, 2]);
+        let outputs = fs::read_to_string(dir.join("outputs.jsonl")).unwrap();
+        let first: Json = serde_json::from_str(outputs.lines().next().unwrap()).unwrap();
+        assert_eq!(first["output"], 0);
+        assert!(first["amount"].is_u64());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+mod csv;
+mod json;
+mod sqlite;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use blockchain::{Block, BlockChain};
+use util::hash::HashAlgorithm;
+use util::hex::ToHex;
+
+pub use self::csv::CsvSink;
+pub use self::json::JsonSink;
+pub use self::sqlite::SqliteSink;
+
+
+pub enum Value {
+    Int(u64),
+    // Any `u64`, where `Int` values fit the formats' signed integers.
+    Amount(u64),
+    Text(String),
+    // A column with nothing in this row.
+    Null,
+}
+
+// One of the normalized tables an export writes. Transactions are keyed by
+// their block height and position in it rather than by hash, which two
+// transactions may share.
+pub struct Table {
+    pub name: &'static str,
+    pub columns: &'static [&'static str],
+}
+
+pub const BLOCKS: Table = Table {
+    name: "blocks",
+    columns: &["height", "hash", "previous", "merkle_root", "version", "timestamp"],
+};
+
+pub const TRANSACTIONS: Table = Table {
+    name: "transactions",
+    columns: &["height", "position", "txid", "version", "timestamp", "lock_time"],
+};
+
+pub const INPUTS: Table = Table {
+    name: "inputs",
+    columns: &["height", "position", "input", "previous_txid", "previous_index", "sequence"],
+};
+
+pub const OUTPUTS: Table = Table {
+    name: "outputs",
+    columns: &["height", "position", "output", "amount", "asset", "anchor", "script"],
+};
+
+pub const TABLES: [&Table; 4] = [&BLOCKS, &TRANSACTIONS, &INPUTS, &OUTPUTS];
+
+// Where an export writes its rows. Rows of a block come after the block's,
+// and those of an input or output after its transaction's.
+pub trait Sink {
+    // Called before the first row. Rows for `from` and later heights that a
+    // previous export wrote are replaced.
+    fn start(&mut self, from: usize) -> Result<(), String>;
+
+    fn write_row(&mut self, table: &Table, row: &[Value]) -> Result<(), String>;
+
+    fn finish(&mut self) -> Result<(), String>;
+
+    // Called instead of `finish` when the export fails after `start`.
+    fn abort(&mut self) {}
+}
+
+// Writes the blocks from height `from` to the tip, returning how many.
+pub fn export(chain: &BlockChain, from: usize, sink: &mut dyn Sink) -> Result<usize, String> {
+    if from > chain.len() {
+        return Err(format!("height {} is past the tip", from));
+    }
+    if let Some(height) = (from..chain.len()).find(|&h| chain.get_block(h).is_none()) {
+        return Err(format!("block {} is not available", height));
+    }
+
+    let result = sink.start(from)
+        .and_then(|_| (from..chain.len())
+            .try_for_each(|height| write_block(chain.get_hash_algorithm(), height, chain.get_block(height).unwrap(), sink)))
+        .and_then(|_| sink.finish());
+    if result.is_err() {
+        sink.abort();
+    }
+    result.map(|_| chain.len() - from)
+}
+
+fn write_block(algorithm: HashAlgorithm, height: usize, block: &Block, sink: &mut dyn Sink) -> Result<(), String> {
+    let height = height as u64;
+    sink.write_row(&BLOCKS, &[
+        Value::Int(height),
+        Value::Text(block.get_hash(algorithm).to_string()),
+        Value::Text(block.get_previous().to_string()),
+        Value::Text(block.get_merkle_root().to_hex()),
+        Value::Int(block.get_version() as u64),
+        Value::Int(block.get_timestamp()),
+    ])?;
+
+    for (position, tx) in block.get_transactions().iter().enumerate() {
+        let position = position as u64;
+        sink.write_row(&TRANSACTIONS, &[
+            Value::Int(height),
+            Value::Int(position),
+            Value::Text(tx.get_hash(algorithm).to_string()),
+            Value::Int(tx.get_version() as u64),
+            Value::Int(tx.get_timestamp()),
+            Value::Int(tx.get_lock_time()),
+        ])?;
+        for (index, input) in tx.get_inputs().iter().enumerate() {
+            sink.write_row(&INPUTS, &[
+                Value::Int(height),
+                Value::Int(position),
+                Value::Int(index as u64),
+                Value::Text(input.get_previous_out().get_tx_hash().to_string()),
+                Value::Int(input.get_previous_out().get_index() as u64),
+                Value::Int(input.get_sequence() as u64),
+            ])?;
+        }
+        for (index, output) in tx.get_outputs().iter().enumerate() {
+            sink.write_row(&OUTPUTS, &[
+                Value::Int(height),
+                Value::Int(position),
+                Value::Int(index as u64),
+                Value::Amount(output.get_amount()),
+                if output.is_native() { Value::Null } else { Value::Text(output.get_asset().to_string()) },
+                output.get_anchor().map_or(Value::Null, |hash| Value::Text(hash.to_hex())),
+                Value::Text(output.get_script().to_hex()),
+            ])?;
+        }
+    }
+    Ok(())
+}
+
+// Opens the file for `table` in `dir` to append to, first dropping the rows
+// for heights `from` and above; `height` reads a row's height from its line,
+// and lines it cannot read, such as a header, are kept. Also returns whether
+// the file is empty.
+fn open_table<F>(dir: &Path, table: &Table, extension: &str, from: usize, height: F)
+    -> Result<(BufWriter<File>, bool), String>
+    where F: Fn(&str) -> Option<u64>
+{
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let path = dir.join(table.name).with_extension(extension);
+    if from > 0 && path.exists() {
+        // The kept rows go to a copy that then replaces the file, so an
+        // interrupted export loses no rows below `from`.
+        let tmp = path.with_extension(format!("{}.tmp", extension));
+        let rewrite = || -> io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if height(&line).is_none_or(|h| h < from as u64) {
+                    writeln!(writer, "{}", line)?;
+                }
+            }
+            writer.flush()?;
+            fs::rename(&tmp, &path)
+        };
+        rewrite().map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(from > 0)
+        .truncate(from == 0)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let empty = file.metadata().map_err(|e| format!("{}: {}", path.display(), e))?.len() == 0;
+    Ok((BufWriter::new(file), empty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+    use blockchain::sample;
+
+    pub fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rustychain-export-{}-{}", name, process::id()))
+    }
+
+    struct Counter {
+        tables: Vec<&'static str>,
+    }
+
+    impl Sink for Counter {
+        fn start(&mut self, _from: usize) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn write_row(&mut self, table: &Table, row: &[Value]) -> Result<(), String> {
+            assert_eq!(row.len(), table.columns.len());
+            self.tables.push(table.name);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rows_follow_their_parents() {
+        let chain = sample::chain();
+        let mut counter = Counter { tables: Vec::new() };
+        assert_eq!(export(&chain, 1, &mut counter).unwrap(), 2);
+        assert_eq!(counter.tables[0], "blocks");
+        assert_eq!(counter.tables.iter().filter(|&&t| t == "blocks").count(), 2);
+        assert!(export(&chain, 4, &mut counter).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+extern crate rusqlite;
+
+use std::path::Path;
+
+use self::rusqlite::{params_from_iter, Connection};
+use self::rusqlite::types::Value as SqlValue;
+
+use export::{Sink, Table, Value};
+
+
+// Foreign keys tie each row to the block or transaction it belongs to, and
+// deleting a block deletes everything in it. Amounts may not fit SQLite's
+// signed integers, so they are stored as decimal text.
+const SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+CREATE TABLE IF NOT EXISTS blocks (
+    height INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    previous TEXT NOT NULL,
+    merkle_root TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS transactions (
+    height INTEGER NOT NULL REFERENCES blocks (height) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    txid TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    lock_time INTEGER NOT NULL,
+    PRIMARY KEY (height, position)
+);
+CREATE INDEX IF NOT EXISTS transactions_txid ON transactions (txid);
+CREATE TABLE IF NOT EXISTS inputs (
+    height INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    input INTEGER NOT NULL,
+    previous_txid TEXT NOT NULL,
+    previous_index INTEGER NOT NULL,
+    sequence INTEGER NOT NULL,
+    PRIMARY KEY (height, position, input),
+    FOREIGN KEY (height, position) REFERENCES transactions (height, position) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS inputs_previous ON inputs (previous_txid, previous_index);
+CREATE TABLE IF NOT EXISTS outputs (
+    height INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    output INTEGER NOT NULL,
+    amount TEXT NOT NULL,
+    asset TEXT,
+    anchor TEXT,
+    script TEXT NOT NULL,
+    PRIMARY KEY (height, position, output),
+    FOREIGN KEY (height, position) REFERENCES transactions (height, position) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS outputs_anchor ON outputs (anchor);
+";
+
+// A normalized SQLite database. An export runs in one database transaction,
+// so readers never see part of one.
+pub struct SqliteSink {
+    conn: Connection,
+}
+
+impl SqliteSink {
+    pub fn open(file: &Path) -> Result<SqliteSink, String> {
+        let conn = Connection::open(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        conn.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        Ok(SqliteSink { conn })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn start(&mut self, from: usize) -> Result<(), String> {
+        self.conn.execute_batch("BEGIN").map_err(|e| e.to_string())?;
+        self.conn.execute("DELETE FROM blocks WHERE height >= ?1", [from as i64])
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn write_row(&mut self, table: &Table, row: &[Value]) -> Result<(), String> {
+        let mut values = Vec::with_capacity(row.len());
+        for (column, value) in table.columns.iter().zip(row) {
+            values.push(match *value {
+                Value::Int(n) if n > i64::MAX as u64 =>
+                    return Err(format!("{}.{}: {} does not fit an SQLite integer", table.name, column, n)),
+                Value::Int(n) => SqlValue::Integer(n as i64),
+                Value::Amount(n) => SqlValue::Text(n.to_string()),
+                Value::Text(ref s) => SqlValue::Text(s.clone()),
+                Value::Null => SqlValue::Null,
+            });
+        }
+
+        let placeholders = vec!["?"; row.len()].join(", ");
+        let sql = format!("INSERT INTO {} ({}) VALUES ({})", table.name, table.columns.join(", "), placeholders);
+        let mut statement = self.conn.prepare_cached(&sql).map_err(|e| e.to_string())?;
+        statement.execute(params_from_iter(values)).map(|_| ()).map_err(|e| format!("{}: {}", table.name, e))
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.conn.execute_batch("COMMIT").map_err(|e| e.to_string())
+    }
+
+    fn abort(&mut self) {
+        // Fails only when no transaction is open, which is then as good.
+        let _ = self.conn.execute_batch("ROLLBACK");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use blockchain::{AssetId, Block, OutPoint, Transaction, TxId};
+    use export::{export, write_block, BLOCKS, OUTPUTS, TRANSACTIONS};
+    use util::hash::HashAlgorithm;
+    use util::hex::ToHex;
+    use blockchain::sample;
+    use export::tests::temp_path;
+
+    fn count(sink: &SqliteSink, sql: &str) -> i64 {
+        sink.conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn reexporting_replaces_rows() {
+        let chain = sample::chain();
+        let file = temp_path("sqlite.db");
+        export(&chain, 0, &mut SqliteSink::open(&file).unwrap()).unwrap();
+        let sink = SqliteSink::open(&file).unwrap();
+        let outputs = count(&sink, "SELECT COUNT(*) FROM outputs");
+        assert_eq!(count(&sink, "SELECT COUNT(*) FROM blocks"), 3);
+
+        let mut sink = sink;
+        export(&chain, 1, &mut sink).unwrap();
+        assert_eq!(count(&sink, "SELECT COUNT(*) FROM blocks"), 3);
+        assert_eq!(count(&sink, "SELECT COUNT(*) FROM outputs"), outputs);
+
+        // A failed export changes nothing and leaves the sink usable.
+        sink.conn.execute_batch("CREATE TEMP TRIGGER fail BEFORE INSERT ON outputs BEGIN SELECT RAISE(ABORT, 'full'); END")
+            .unwrap();
+        assert!(export(&chain, 1, &mut sink).unwrap_err().contains("full"));
+        assert_eq!(count(&sink, "SELECT COUNT(*) FROM blocks"), 3);
+        sink.conn.execute_batch("DROP TRIGGER fail").unwrap();
+        export(&chain, 1, &mut sink).unwrap();
+        assert_eq!(count(&sink, "SELECT COUNT(*) FROM outputs"), outputs);
+
+        // Every spend of an exported output joins back to it.
+        let joined = count(&sink, "SELECT COUNT(*) FROM inputs i JOIN transactions t ON t.txid = i.previous_txid
+                                   JOIN outputs o ON o.height = t.height AND o.position = t.position
+                                   AND o.output = i.previous_index");
+        assert_eq!(joined, count(&sink, "SELECT COUNT(*) FROM inputs"));
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn amounts_above_i64_are_stored() {
+        let file = temp_path("amounts.db");
+        let mut sink = SqliteSink::open(&file).unwrap();
+        sink.start(0).unwrap();
+        sink.write_row(&BLOCKS, &[Value::Int(0), Value::Text("b".to_string()), Value::Text("p".to_string()),
+                                   Value::Text("m".to_string()), Value::Int(1), Value::Int(0)]).unwrap();
+        sink.write_row(&TRANSACTIONS, &[Value::Int(0), Value::Int(0), Value::Text("t".to_string()),
+                                        Value::Int(1), Value::Int(0), Value::Int(0)]).unwrap();
+        sink.write_row(&OUTPUTS, &[Value::Int(0), Value::Int(0), Value::Int(0), Value::Amount(u64::MAX),
+                                   Value::Null, Value::Null, Value::Text(String::new())]).unwrap();
+        sink.finish().unwrap();
+
+        let amount: String = sink.conn.query_row("SELECT amount FROM outputs", [], |row| row.get(0)).unwrap();
+        assert_eq!(amount, u64::MAX.to_string());
+        // Other columns still have to fit.
+        assert!(sink.write_row(&BLOCKS, &[Value::Int(1), Value::Text("b".to_string()), Value::Text("p".to_string()),
+                                          Value::Text("m".to_string()), Value::Int(1), Value::Int(u64::MAX)])
+            .unwrap_err().contains("does not fit"));
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn outputs_record_assets_and_anchors() {
+        let gold = AssetId::from_bytes([7u8; 32]);
+        let mut tx = Transaction::new();
+        tx.add_input(OutPoint::new(TxId::default(), 0));
+        tx.add_output(5, b"alice");
+        tx.add_asset_output(gold, 10, b"bob");
+        tx.add_anchor(&[9u8; 32]);
+        let mut block = Block::new();
+        block.add_transaction(tx);
+
+        let file = temp_path("assets.db");
+        let mut sink = SqliteSink::open(&file).unwrap();
+        sink.start(0).unwrap();
+        write_block(HashAlgorithm::Blake2s, 0, &block, &mut sink).unwrap();
+        sink.finish().unwrap();
+
+        let mut statement = sink.conn.prepare("SELECT asset, anchor FROM outputs ORDER BY output").unwrap();
+        let rows: Vec<(Option<String>, Option<String>)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(rows, vec![
+            (None, None),
+            (Some(gold.to_string()), None),
+            (None, Some([9u8; 32].to_hex())),
+        ]);
+        drop(statement);
+        fs::remove_file(&file).unwrap();
+    }
+}
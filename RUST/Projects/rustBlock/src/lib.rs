@@ -0,0 +1,24 @@
+// A small UTXO blockchain: blocks and transactions with their YAML and
+// binary encodings, a validating `BlockChain` with snapshots, pruning and
+// indexes, a mempool, and the JSON-RPC server, block explorer, light client,
+// async node interface and document notarization built on them, peer
+// management, and a deterministic multi-node network simulator. The
+// `rustychain` binary is a command line front end to this crate.
+
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(test)]
+extern crate proptest;
+
+pub mod blockchain;
+pub mod explorer;
+pub mod export;
+pub mod node;
+pub mod notary;
+pub mod peers;
+pub mod rpc;
+pub mod sim;
+pub mod spv;
+pub mod util;
@@ -1,20 +1,381 @@
-#[macro_use]
-extern crate serde_derive;
+extern crate rustychain;
+extern crate serde_json;
 
 use std::env;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, RwLock};
+use std::thread;
 
-mod util;
-mod blockchain;
-use blockchain::BlockChain;
+mod bench;
 
+use rustychain::blockchain::{append_document, verify_chain, verify_history, BlockChain, ChainError, ChainStats, HistoryKind, TxId, UtxoSnapshot};
+use rustychain::explorer::Explorer;
+use rustychain::export;
+use rustychain::notary::{self, AnchorProof};
+use rustychain::rpc::{RpcClient, RpcServer};
+use rustychain::spv::LightClient;
+use rustychain::util::hash::HashAlgorithm;
+use rustychain::util::hex::{FromHex, ToHex};
+use rustychain::util::time;
+
+
+const DEFAULT_RPC_ADDR: &str = "127.0.0.1:8332";
+const DEFAULT_EXPLORER_ADDR: &str = "127.0.0.1:8080";
+const DEFAULT_BENCH_BLOCKS: usize = 100;
+const DEFAULT_BENCH_TXS: usize = 200;
+
+fn usage() -> ! {
+    eprintln!("usage: rustychain [--txindex] [--addrindex] [--blockfilters] [--snapshot-every=<n>]");
+    eprintln!("                  [--snapshot-dir=<dir>] [--load-snapshot=<file>] [--prune=<depth>]");
+    eprintln!("                  <chain_file> [command]");
+    eprintln!("       rustychain --spv=<node_addr> [--hash=<algorithm>] [txid...]");
+    eprintln!("       rustychain --bench [blocks] [txs_per_block]");
+    eprintln!();
+    eprintln!("commands:");
+    eprintln!("    (none)           print the hash of every block");
+    eprintln!("    rpc [addr]       serve JSON-RPC and /metrics (default {})", DEFAULT_RPC_ADDR);
+    eprintln!("    explorer [addr]  serve the block explorer (default {})", DEFAULT_EXPLORER_ADDR);
+    eprintln!("    stats            print block, transaction, supply and fee statistics as JSON");
+    eprintln!("    reindex          rebuild the enabled indexes and print their sizes");
+    eprintln!("    history <script> list outputs and spends of a hex output script");
+    eprintln!("    balance [script] print unspent amounts per asset, optionally of one script");
+    eprintln!("    snapshot <file>  write the UTXO set at the tip to a snapshot file");
+    eprintln!("    export <json | csv | sqlite> <path> [from_height]");
+    eprintln!("                     write blocks, transactions, inputs and outputs from");
+    eprintln!("                     a height (default 0) to files in a directory or a database");
+    eprintln!("    anchor <file> [bundle]");
+    eprintln!("                     anchor the hash of a file in a new block, unless it is");
+    eprintln!("                     already, and write a proof bundle (default <file>.anchor.json)");
+    eprintln!("    verify-anchor <file> <bundle>");
+    eprintln!("                     check a proof bundle for a file against the chain");
+    eprintln!("    verify [truncate | export <file>]");
+    eprintln!("                     report every problem in the chain file as JSON, then");
+    eprintln!("                     optionally cut it, or copy it, up to the first bad block");
+    process::exit(2);
+}
+
+fn print_history(chain: &BlockChain, script_arg: &str) {
+    let script = script_arg.from_hex().unwrap_or_else(|_| usage());
+    let index = match chain.get_address_index() {
+        Some(index) => index,
+        None => {
+            eprintln!("history: requires --addrindex");
+            process::exit(1);
+        },
+    };
+
+    for entry in index.get_history(&script) {
+        let amount = if entry.asset.is_zero() {
+            entry.amount.to_string()
+        } else {
+            format!("{} {}", entry.amount, entry.asset)
+        };
+        match entry.kind {
+            HistoryKind::Output => {
+                println!("{:08}: +{} {}", entry.height, amount, entry.out);
+            },
+            HistoryKind::Spend { tx, input } => {
+                println!("{:08}: -{} {} by {}:{}", entry.height, amount, entry.out, tx, input);
+            },
+        }
+    }
+}
+
+fn print_balance(chain: &BlockChain, script_arg: Option<String>) {
+    let script = script_arg.map(|s| s.from_hex().unwrap_or_else(|_| usage()));
+    print!("{}", chain.get_utxos().get_balances(script.as_ref().map(|s| &s[..])));
+}
+
+fn write_snapshot(chain: &BlockChain, file: &str) {
+    let snapshot = match chain.get_snapshot() {
+        Some(snapshot) => snapshot,
+        None => {
+            eprintln!("snapshot: the chain is empty");
+            process::exit(1);
+        },
+    };
+    if let Err(e) = snapshot.write_to(Path::new(file)) {
+        eprintln!("snapshot: {}: {}", file, e);
+        process::exit(1);
+    }
+    println!("snapshot: height {}, hash {}", snapshot.get_height(), snapshot.get_hash());
+}
+
+fn run_export(chain: &BlockChain, format: &str, path: &str, from: usize) {
+    let path = PathBuf::from(path);
+    let exported = match format {
+        "json" => export::export(chain, from, &mut export::JsonSink::new(path)),
+        "csv" => export::export(chain, from, &mut export::CsvSink::new(path)),
+        "sqlite" => export::SqliteSink::open(&path).and_then(|mut sink| export::export(chain, from, &mut sink)),
+        _ => usage(),
+    };
+    match exported {
+        Ok(blocks) => println!("export: {} blocks from height {}", blocks, from),
+        Err(e) => {
+            eprintln!("export: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+fn fail(command: &str, e: String) -> ! {
+    eprintln!("{}: {}", command, e);
+    process::exit(1);
+}
+
+// Anchors `file` in a block appended to both `chain` and the chain file.
+fn run_anchor(chain: &mut BlockChain, chain_file: &Path, file: &str, bundle: Option<String>) {
+    let hash = notary::hash_file(chain.get_hash_algorithm(), Path::new(file)).unwrap_or_else(|e| fail("anchor", e));
+    let proof = match AnchorProof::new(chain, &hash) {
+        Some(proof) => {
+            println!("anchor: {} is already anchored", hash.to_hex());
+            proof
+        },
+        None => {
+            let block = notary::anchor_block(chain, &hash, time::now());
+            chain.reorganize(vec![block.clone()]).unwrap_or_else(|e| fail("anchor", e.to_string()));
+            append_document(chain_file, &block).unwrap_or_else(|e| fail("anchor", e));
+            AnchorProof::new(chain, &hash).unwrap()
+        },
+    };
+
+    let bundle = bundle.unwrap_or_else(|| format!("{}.anchor.json", file));
+    let json = serde_json::to_string_pretty(&proof.to_json()).unwrap();
+    fs::write(&bundle, json).unwrap_or_else(|e| fail("anchor", format!("{}: {}", bundle, e)));
+    println!("anchor: {} in block {} at height {}, time {}; proof in {}", hash.to_hex(),
+             proof.get_header().get_hash(chain.get_hash_algorithm()), proof.get_height(),
+             proof.get_header().get_timestamp(), bundle);
+}
+
+fn run_verify_anchor(chain: &BlockChain, file: &str, bundle: &str) {
+    let hash = notary::hash_file(chain.get_hash_algorithm(), Path::new(file))
+        .unwrap_or_else(|e| fail("verify-anchor", e));
+    let proof = fs::read_to_string(bundle)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+        .and_then(|json| AnchorProof::from_json(chain.get_hash_algorithm(), &json))
+        .unwrap_or_else(|e| fail("verify-anchor", format!("{}: {}", bundle, e)));
+    proof.verify_in(chain, &hash).unwrap_or_else(|e| fail("verify-anchor", e));
+    println!("verify-anchor: {} existed by time {}, block {} at height {}", file,
+             proof.get_header().get_timestamp(), proof.get_header().get_hash(chain.get_hash_algorithm()),
+             proof.get_height());
+}
+
+// Exits with status 1 if the chain file has problems, even if repaired.
+fn run_verify(chain_file: &Path, args: &[String]) {
+    let report = verify_chain(chain_file).unwrap_or_else(|e| {
+        eprintln!("verify: {}", e);
+        process::exit(1);
+    });
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+    let repaired = match args.iter().map(|a| a.as_str()).collect::<Vec<_>>()[..] {
+        [] => Ok(()),
+        ["truncate"] => report.truncate(chain_file),
+        ["export", file] => report.write_valid_prefix(Path::new(file)),
+        _ => usage(),
+    };
+    if let Err(e) = repaired {
+        eprintln!("verify: {}", e);
+        process::exit(1);
+    }
+    if !report.is_ok() {
+        process::exit(1);
+    }
+}
+
+// Starts `chain` from the snapshot in `snapshot_file` and the blocks after
+// it, leaving the blocks before it to `verify_history`.
+fn load_snapshot(chain: &mut BlockChain, chain_file: &Path, snapshot_file: &str) {
+    let loaded = chain.read_params(chain_file)
+        .and_then(|_| UtxoSnapshot::read_from(Path::new(snapshot_file)).map_err(|e| ChainError::Io(e.to_string())))
+        .and_then(|snapshot| chain.load_snapshot(snapshot));
+    if let Err(e) = loaded {
+        eprintln!("load-snapshot: {}: {}", snapshot_file, e);
+        process::exit(1);
+    }
+    println!("load-snapshot: starting at height {}", chain.get_history_start() - 1);
+}
+
+fn report_history(verifier: thread::JoinHandle<Result<usize, ChainError>>) {
+    match verifier.join().unwrap() {
+        Ok(blocks) => println!("history: verified {} blocks", blocks),
+        Err(e) => {
+            eprintln!("history: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+// Syncs headers from the node's RPC server and reports how deeply each
+// transaction is buried, verified against the headers. `algorithm` must be
+// the hash function of the node's chain.
+fn run_light_client(node_addr: &str, algorithm: HashAlgorithm, txids: &[String]) {
+    let mut client = LightClient::new(RpcClient::new(node_addr), algorithm);
+    let mut watched = Vec::new();
+    for txid in txids {
+        let hash: TxId = txid.parse().unwrap_or_else(|_| usage());
+        client.watch(&hash);
+        watched.push(hash);
+    }
+
+    if let Err(e) = client.sync() {
+        eprintln!("spv: {}", e);
+        process::exit(1);
+    }
+
+    let headers = client.get_headers();
+    match headers.len().checked_sub(1) {
+        Some(tip) => println!("spv: {} headers, tip {}", headers.len(), headers.get_hash(tip).unwrap()),
+        None => println!("spv: no headers"),
+    }
+    for hash in &watched {
+        match client.get_confirmations(hash).unwrap() {
+            0 => println!("{}: unconfirmed", hash),
+            n => println!("{}: {} confirmations", hash, n),
+        }
+    }
+}
 
 fn main() {
-    let mut args = env::args();
-    let chain_file_arg = args.nth(1).unwrap();
+    let (options, args): (Vec<String>, Vec<String>) = env::args().skip(1)
+        .partition(|a| a.starts_with("--"));
+
+    if let Some(node_addr) = options.iter().find_map(|o| o.strip_prefix("--spv=")) {
+        let algorithm = options.iter().find_map(|o| o.strip_prefix("--hash="))
+            .map_or(Ok(HashAlgorithm::default()), |name| name.parse())
+            .unwrap_or_else(|_| usage());
+        run_light_client(node_addr, algorithm, &args);
+        return;
+    }
+    if options.iter().any(|o| o == "--bench") {
+        let mut counts = args.iter().map(|a| a.parse::<usize>().unwrap_or_else(|_| usage()));
+        bench::run(counts.next().unwrap_or(DEFAULT_BENCH_BLOCKS), counts.next().unwrap_or(DEFAULT_BENCH_TXS));
+        return;
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("verify") {
+        run_verify(Path::new(&args[0]), &args[2..]);
+        return;
+    }
+    let mut args = args.into_iter();
+
+    let chain_file_arg = args.next().unwrap_or_else(|| usage());
     let chain_file = Path::new(&chain_file_arg);
 
     let mut chain = BlockChain::new();
+    let mut snapshot_every = None;
+    let mut snapshot_dir = PathBuf::from(".");
+    let mut snapshot_file = None;
+    let mut prune_depth = None;
+    for option in &options {
+        match option.as_str() {
+            // Pruning is only set after the options are read, so these succeed;
+            // `set_prune_depth` reports the conflict.
+            "--txindex" => chain.enable_tx_index().unwrap(),
+            "--addrindex" => chain.enable_address_index().unwrap(),
+            "--blockfilters" => chain.enable_filter_index(),
+            o if o.starts_with("--snapshot-every=") => {
+                let interval = o["--snapshot-every=".len()..].parse::<usize>().unwrap_or_else(|_| usage());
+                snapshot_every = Some(if interval > 0 { interval } else { usage() });
+            },
+            o if o.starts_with("--snapshot-dir=") => snapshot_dir = PathBuf::from(&o["--snapshot-dir=".len()..]),
+            o if o.starts_with("--load-snapshot=") => snapshot_file = Some(o["--load-snapshot=".len()..].to_string()),
+            o if o.starts_with("--prune=") => {
+                prune_depth = Some(o["--prune=".len()..].parse::<usize>().unwrap_or_else(|_| usage()));
+            },
+            _ => usage(),
+        }
+    }
+    if let Some(interval) = snapshot_every {
+        chain.set_snapshot_interval(interval, &snapshot_dir);
+    }
+    if let Some(depth) = prune_depth {
+        if let Err(e) = chain.set_prune_depth(depth) {
+            eprintln!("prune: {}", e);
+            process::exit(2);
+        }
+    }
+    if let Some(ref file) = snapshot_file {
+        load_snapshot(&mut chain, chain_file, file);
+    }
     chain.read_chain(chain_file);
-    println!("{}", chain);
+
+    // With a snapshot loaded the blocks before it are verified in the
+    // background; servers start right away, other commands wait for it.
+    let chain = Arc::new(RwLock::new(chain));
+    let verifier = snapshot_file.map(|_| verify_history(chain.clone(), chain_file.to_path_buf()));
+
+    match args.next().as_deref() {
+        Some("rpc") => {
+            let addr = args.next().unwrap_or_else(|| DEFAULT_RPC_ADDR.to_string());
+            if let Some(verifier) = verifier {
+                thread::spawn(move || report_history(verifier));
+            }
+            println!("rpc: listening on {}", addr);
+            if let Err(e) = RpcServer::new(chain).serve(&addr[..]) {
+                eprintln!("rpc: {}", e);
+                process::exit(1);
+            }
+        },
+        Some("explorer") => {
+            let addr = args.next().unwrap_or_else(|| DEFAULT_EXPLORER_ADDR.to_string());
+            if let Some(verifier) = verifier {
+                thread::spawn(move || report_history(verifier));
+            }
+            println!("explorer: listening on http://{}/", addr);
+            if let Err(e) = Explorer::new(chain).serve(&addr[..]) {
+                eprintln!("explorer: {}", e);
+                process::exit(1);
+            }
+        },
+        command => {
+            if let Some(verifier) = verifier {
+                report_history(verifier);
+            }
+            let mut chain = chain.write().unwrap();
+            match command {
+                None => println!("{}", chain),
+                Some("stats") => println!("{}", serde_json::to_string_pretty(&ChainStats::new(&chain)).unwrap()),
+                Some("reindex") => {
+                    chain.reindex();
+                    if let Some(index) = chain.get_tx_index() {
+                        println!("txindex:   {} transactions", index.len());
+                    }
+                    if let Some(index) = chain.get_address_index() {
+                        println!("addrindex: {} scripts, {} entries", index.len(), index.get_entry_count());
+                    }
+                    if let Some(index) = chain.get_filter_index() {
+                        println!("blockfilters: {} filters", index.len());
+                    }
+                },
+                Some("history") => {
+                    let script = args.next().unwrap_or_else(|| usage());
+                    print_history(&chain, &script);
+                },
+                Some("balance") => print_balance(&chain, args.next()),
+                Some("anchor") => {
+                    let file = args.next().unwrap_or_else(|| usage());
+                    run_anchor(&mut chain, chain_file, &file, args.next());
+                },
+                Some("verify-anchor") => {
+                    let file = args.next().unwrap_or_else(|| usage());
+                    let bundle = args.next().unwrap_or_else(|| usage());
+                    run_verify_anchor(&chain, &file, &bundle);
+                },
+                Some("snapshot") => {
+                    let file = args.next().unwrap_or_else(|| usage());
+                    write_snapshot(&chain, &file);
+                },
+                Some("export") => {
+                    let format = args.next().unwrap_or_else(|| usage());
+                    let path = args.next().unwrap_or_else(|| usage());
+                    let from = args.next().map_or(0, |a| a.parse::<usize>().unwrap_or_else(|_| usage()));
+                    run_export(&chain, &format, &path, from);
+                },
+                Some(_) => usage(),
+            }
+        },
+    }
 }
@@ -0,0 +1,209 @@
+extern crate tokio;
+
+use std::future::Future;
+use std::panic;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+
+use self::tokio::task::{self, JoinHandle};
+
+use blockchain::{follow_chain, Block, BlockChain, BlockFilter, BlockHash, ChainError, ChainStats, Mempool, RejectReason, Transaction,
+                 TxId};
+use util::hash::HASH256_BYTES;
+
+
+// The result of a call run on tokio's blocking thread pool. Validation and
+// the chain's locks stay synchronous, so async callers wait on this rather
+// than blocking a runtime worker. Like other futures it does nothing until
+// polled, so it can be created outside a runtime. A panic in the call
+// resumes in the task awaiting it.
+pub struct Blocking<T> {
+    state: BlockingState<T>,
+}
+
+enum BlockingState<T> {
+    Pending(Option<Box<dyn FnOnce() -> T + Send>>),
+    Running(JoinHandle<T>),
+}
+
+impl<T: Send + 'static> Future for Blocking<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        if let BlockingState::Pending(ref mut f) = self.state {
+            let f = f.take().unwrap();
+            self.state = BlockingState::Running(task::spawn_blocking(f));
+        }
+        let handle = match self.state {
+            BlockingState::Running(ref mut handle) => handle,
+            BlockingState::Pending(_) => unreachable!(),
+        };
+        match Pin::new(handle).poll(cx) {
+            Poll::Ready(Ok(value)) => Poll::Ready(value),
+            Poll::Ready(Err(e)) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Poll::Ready(Err(e)) => panic!("blocking call: {}", e),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn blocking<T, F>(f: F) -> Blocking<T>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static
+{
+    Blocking {
+        state: BlockingState::Pending(Some(Box::new(f))),
+    }
+}
+
+// A chain and its mempool behind an async interface, for embedding in
+// services on tokio. The futures it returns must be polled by a runtime. The
+// chain is the same `Arc<RwLock<BlockChain>>` the RPC server and explorer
+// take, so they can serve it alongside.
+#[derive(Clone)]
+pub struct Node {
+    chain: Arc<RwLock<BlockChain>>,
+    mempool: Arc<Mutex<Mempool>>,
+}
+
+impl Node {
+    // `chain` may be shared with, and updated by, other threads.
+    pub fn new(chain: Arc<RwLock<BlockChain>>) -> Node {
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        follow_chain(mempool.clone(), chain.clone());
+        Node {
+            chain,
+            mempool,
+        }
+    }
+
+    // Reads the parameters and blocks of `file` into `chain`, which may have
+    // indexes or pruning set up already, and starts a node on it.
+    pub fn load(mut chain: BlockChain, file: PathBuf) -> Blocking<Result<Node, ChainError>> {
+        blocking(move || {
+            chain.read_params(&file)?;
+            chain.read_blocks(&file, None)?;
+            Ok(Node::new(Arc::new(RwLock::new(chain))))
+        })
+    }
+
+    pub fn get_chain(&self) -> &Arc<RwLock<BlockChain>> {
+        &self.chain
+    }
+
+    pub fn get_mempool(&self) -> &Arc<Mutex<Mempool>> {
+        &self.mempool
+    }
+
+    // Runs `f` with the chain read-locked; for queries the methods below do
+    // not cover.
+    pub fn query<T, F>(&self, f: F) -> Blocking<T>
+        where F: FnOnce(&BlockChain) -> T + Send + 'static, T: Send + 'static
+    {
+        let chain = self.chain.clone();
+        blocking(move || f(&chain.read().unwrap()))
+    }
+
+    // The height and hash of the tip; `None` while the chain is empty.
+    pub fn get_tip(&self) -> Blocking<Option<(usize, BlockHash)>> {
+        self.query(|chain| chain.get_best_hash().map(|hash| (chain.len() - 1, hash)))
+    }
+
+    // `None` if the block is not in the active chain or its data is pruned.
+    pub fn get_block(&self, hash: BlockHash) -> Blocking<Option<Block>> {
+        self.query(move |chain| chain.get_block_by_hash(&hash).cloned())
+    }
+
+    // A confirmed transaction and the height of its block.
+    pub fn get_transaction(&self, hash: TxId) -> Blocking<Option<(usize, Transaction)>> {
+        self.query(move |chain| chain.find_transaction(&hash).map(|(height, tx)| (height, tx.clone())))
+    }
+
+    // The filter of a block in the active chain and its filter header;
+    // `None` if the block is not there or the filter index is not enabled.
+    pub fn get_block_filter(&self, hash: BlockHash) -> Blocking<Option<(BlockFilter, [u8; HASH256_BYTES])>> {
+        self.query(move |chain| {
+            let index = chain.get_filter_index()?;
+            let (filter, header) = index.get(chain.get_height(&hash)?)?;
+            Some((filter.clone(), *header))
+        })
+    }
+
+    pub fn get_stats(&self) -> Blocking<ChainStats> {
+        self.query(ChainStats::new)
+    }
+
+    // Connects `block` on top of the tip. The mempool follows as it does for
+    // any other change to the chain.
+    pub fn submit_block(&self, block: Block) -> Blocking<Result<(), ChainError>> {
+        let chain = self.chain.clone();
+        blocking(move || chain.write().unwrap().reorganize(vec![block]).map(|_| ()))
+    }
+
+    // Switches to the longer branch `blocks`, as `BlockChain::reorganize`,
+    // and returns the blocks that were disconnected.
+    pub fn submit_branch(&self, blocks: Vec<Block>) -> Blocking<Result<Vec<Block>, ChainError>> {
+        let chain = self.chain.clone();
+        blocking(move || chain.write().unwrap().reorganize(blocks))
+    }
+
+    pub fn submit_transaction(&self, tx: Transaction) -> Blocking<Result<TxId, RejectReason>> {
+        let chain = self.chain.clone();
+        let mempool = self.mempool.clone();
+        blocking(move || {
+            let chain = chain.read().unwrap();
+            mempool.lock().unwrap().accept(tx, &chain)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::tokio::runtime::Builder;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use blockchain::sample::CHAIN;
+    use util::hash::HashAlgorithm;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    #[test]
+    fn load_query_and_submit() {
+        let path = env::temp_dir().join(format!("rustychain-node-{}.yaml", process::id()));
+        fs::write(&path, CHAIN).unwrap();
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let mut chain = BlockChain::new();
+        chain.enable_filter_index();
+        let node = runtime.block_on(Node::load(chain, path.clone())).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let (height, tip) = runtime.block_on(node.get_tip()).unwrap();
+        assert_eq!(height, 2);
+        assert_eq!(runtime.block_on(node.get_block(tip)).unwrap().get_hash(ALGORITHM), tip);
+        assert_eq!(runtime.block_on(node.get_stats()).blocks, 3);
+        let (filter, _) = runtime.block_on(node.get_block_filter(tip)).unwrap();
+        assert_eq!(filter.len(), 3);
+        assert!(runtime.block_on(node.get_block_filter(BlockHash::default())).is_none());
+
+        let mut coinbase = Transaction::new();
+        coinbase.add_output(5000, b"miner");
+        let mut block = Block::new();
+        block.set_previous(tip);
+        block.add_transaction(coinbase.clone());
+        block.update_merkle_root(ALGORITHM);
+        runtime.block_on(node.submit_block(block.clone())).unwrap();
+        assert_eq!(runtime.block_on(node.get_tip()), Some((3, block.get_hash(ALGORITHM))));
+        assert_eq!(runtime.block_on(node.get_transaction(coinbase.get_hash(ALGORITHM))), Some((3, coinbase.clone())));
+        assert!(runtime.block_on(node.get_block_filter(block.get_hash(ALGORITHM))).is_some());
+
+        match runtime.block_on(node.submit_block(block)) {
+            Err(ChainError::BadBranch(_)) => {},
+            result => panic!("expected BadBranch; actual {:?}", result),
+        }
+        assert_eq!(runtime.block_on(node.submit_transaction(coinbase.clone())),
+                   Err(RejectReason::AlreadyInChain(coinbase.get_hash(ALGORITHM))));
+    }
+}
@@ -0,0 +1,211 @@
+extern crate serde_json;
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use self::serde_json::Value;
+
+use blockchain::{Block, BlockChain, BlockHeader, Decodable, Encodable, MerkleBranch, Transaction, STATE_ROOT_VERSION};
+use util::hash::{Hash256, HashAlgorithm, HASH256_BYTES};
+use util::hex::{FromHex, ToHex};
+
+
+// The hash a file is anchored by, with the hash function of the chain it
+// is anchored in.
+pub fn hash_file(algorithm: HashAlgorithm, file: &Path) -> Result<[u8; HASH256_BYTES], String> {
+    let mut hash = Hash256::new(algorithm);
+    File::open(file)
+        .and_then(|mut f| io::copy(&mut f, &mut hash))
+        .map_err(|e| format!("{}: {}", file.display(), e))?;
+
+    let mut buf = [0u8; HASH256_BYTES];
+    hash.finalize(&mut buf);
+    Ok(buf)
+}
+
+// A block on top of the tip whose only transaction anchors `hash`. It has
+// no inputs, so it needs nothing to spend; `time` stamps both.
+pub fn anchor_block(chain: &BlockChain, hash: &[u8; HASH256_BYTES], time: u64) -> Block {
+    let algorithm = chain.get_hash_algorithm();
+    let mut tx = Transaction::new();
+    tx.set_timestamp(time);
+    tx.add_anchor(hash);
+
+    let mut block = Block::new();
+    if let Some(tip) = chain.len().checked_sub(1).and_then(|h| chain.get_header(h)) {
+        block.set_previous(tip.get_hash(algorithm));
+        block.set_version(tip.get_version());
+        block.set_timestamp(time.max(tip.get_timestamp()));
+    } else {
+        block.set_timestamp(time);
+    }
+    block.add_transaction(tx);
+    block.update_merkle_root(algorithm);
+    if block.get_version() >= STATE_ROOT_VERSION {
+        let root = chain.compute_state_root(&block).unwrap();
+        block.set_state_root(root);
+    }
+    block
+}
+
+// That a transaction anchoring some hash is in the block `header` at
+// `height`: the block's timestamp is a time the hashed content existed by.
+// Everything but `height` is checked against the header, and the header
+// against a chain. Hashes are checked with `algorithm`, that of the chain
+// the proof was made from.
+pub struct AnchorProof {
+    algorithm: HashAlgorithm,
+    height: usize,
+    tx: Transaction,
+    branch: MerkleBranch,
+    header: BlockHeader,
+}
+
+impl AnchorProof {
+    // Proves the earliest anchor of `hash` in the blocks of `chain` that
+    // are not pruned.
+    pub fn new(chain: &BlockChain, hash: &[u8; HASH256_BYTES]) -> Option<AnchorProof> {
+        let algorithm = chain.get_hash_algorithm();
+        (chain.get_history_start()..chain.len()).find_map(|height| {
+            let block = chain.get_block(height)?;
+            let position = block.get_transactions().iter()
+                .position(|tx| tx.get_outputs().iter().any(|o| o.get_anchor().as_ref() == Some(hash)))?;
+            Some(AnchorProof {
+                algorithm,
+                height,
+                tx: block.get_transactions()[position].clone(),
+                branch: block.get_merkle_branch(algorithm, position),
+                header: block.get_header(),
+            })
+        })
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_transaction(&self) -> &Transaction {
+        &self.tx
+    }
+
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    // Checks that the transaction anchors `hash` and is in the block.
+    pub fn verify(&self, hash: &[u8; HASH256_BYTES]) -> Result<(), String> {
+        if !self.tx.get_outputs().iter().any(|o| o.get_anchor().as_ref() == Some(hash)) {
+            return Err(format!("tx {} does not anchor {}", self.tx.get_hash(self.algorithm), hash.to_hex()));
+        }
+        let txid = self.tx.get_hash(self.algorithm);
+        if !self.branch.verify(self.algorithm, txid.as_bytes(), self.header.get_merkle_root()) {
+            return Err(format!("tx {} is not in block {}", txid, self.header.get_hash(self.algorithm)));
+        }
+        Ok(())
+    }
+
+    // As `verify`, and checks that the block is the one `chain` has at the
+    // proof's height. Pruned blocks keep their headers, so they can still
+    // be checked against.
+    pub fn verify_in(&self, chain: &BlockChain, hash: &[u8; HASH256_BYTES]) -> Result<(), String> {
+        if self.algorithm != chain.get_hash_algorithm() {
+            return Err(format!("the proof is hashed with {}; the chain uses {}",
+                               self.algorithm, chain.get_hash_algorithm()));
+        }
+        self.verify(hash)?;
+        match chain.get_header(self.height) {
+            Some(header) if header == self.header => Ok(()),
+            Some(header) => Err(format!("block {} is not in the chain; height {} is {}",
+                                        self.header.get_hash(self.algorithm), self.height,
+                                        header.get_hash(self.algorithm))),
+            None => Err(format!("the chain has no block at height {}", self.height)),
+        }
+    }
+
+    // The bundle a file's owner keeps: the transaction and the header as
+    // raw hex, and the branch linking them.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "height": self.height,
+            "block_hash": self.header.get_hash(self.algorithm).to_string(),
+            "timestamp": self.header.get_timestamp(),
+            "tx": self.tx.to_bytes().to_hex(),
+            "header": self.header.to_bytes().to_hex(),
+            "index": self.branch.get_index(),
+            "branch": self.branch.get_hashes().iter().map(|h| h.to_hex()).collect::<Vec<_>>(),
+        })
+    }
+
+    // Reads a bundle made on a chain hashing with `algorithm`.
+    pub fn from_json(algorithm: HashAlgorithm, value: &Value) -> Result<AnchorProof, String> {
+        let field = |name: &str| value.get(name).ok_or_else(|| format!("missing '{}'", name));
+        let bytes = |name: &str| -> Result<Vec<u8>, String> {
+            field(name)?.as_str().ok_or_else(|| format!("'{}' must be a hex string", name))?
+                .from_hex().map_err(|e| format!("'{}': {}", name, e))
+        };
+        let number = |name: &str| field(name)?.as_u64().ok_or_else(|| format!("'{}' must be a number", name));
+
+        let mut hashes = Vec::new();
+        for hash in field("branch")?.as_array().ok_or("'branch' must be an array")? {
+            let hash = hash.as_str().and_then(|h| h.from_hex().ok())
+                .filter(|h| h.len() == HASH256_BYTES)
+                .ok_or("'branch' must hold 32 byte hex hashes")?;
+            let mut buf = [0u8; HASH256_BYTES];
+            buf.copy_from_slice(&hash);
+            hashes.push(buf);
+        }
+        Ok(AnchorProof {
+            algorithm,
+            height: number("height")? as usize,
+            tx: Transaction::from_bytes(&bytes("tx")?).map_err(|e| format!("'tx': {}", e))?,
+            branch: MerkleBranch::new(number("index")? as u32, hashes),
+            header: BlockHeader::from_bytes(&bytes("header")?).map_err(|e| format!("'header': {}", e))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::append_document;
+    use blockchain::sample::CHAIN;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn read(file: &Path) -> BlockChain {
+        let mut chain = BlockChain::new();
+        chain.read_params(file).unwrap();
+        chain.read_blocks(file, None).unwrap();
+        chain
+    }
+
+    #[test]
+    fn anchor_prove_and_verify() {
+        let file = env::temp_dir().join(format!("rustychain-notary-{}.yaml", process::id()));
+        fs::write(&file, CHAIN).unwrap();
+        let chain = read(&file);
+        let hash = [7u8; HASH256_BYTES];
+        assert!(AnchorProof::new(&chain, &hash).is_none());
+
+        let block = anchor_block(&chain, &hash, 1_507_002_000);
+        append_document(&file, &block).unwrap();
+        let chain = read(&file);
+        fs::remove_file(&file).unwrap();
+        assert_eq!(chain.get_best_hash(), Some(block.get_hash(chain.get_hash_algorithm())));
+        // Anchors are never spent, so they stay out of the UTXO set.
+        assert!(chain.get_utxos().iter().all(|(_, coin)| !coin.get_output().is_anchor()));
+
+        let proof = AnchorProof::new(&chain, &hash).unwrap();
+        assert_eq!(proof.get_height(), 3);
+        let proof = AnchorProof::from_json(chain.get_hash_algorithm(), &proof.to_json()).unwrap();
+        assert_eq!(proof.verify_in(&chain, &hash), Ok(()));
+        assert!(proof.verify(&[8u8; HASH256_BYTES]).unwrap_err().contains("does not anchor"));
+
+        // A bundle for a block the chain does not have.
+        let mut forged = proof.to_json();
+        forged["height"] = json!(2);
+        assert!(AnchorProof::from_json(chain.get_hash_algorithm(), &forged).unwrap().verify_in(&chain, &hash).unwrap_err().contains("not in the chain"));
+    }
+}
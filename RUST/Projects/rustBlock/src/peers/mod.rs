@@ -0,0 +1,551 @@
+extern crate serde_json;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+
+use util::time::{Clock, SystemClock};
+
+
+// What a peer did wrong. Each adds its score to the peer's; reaching the
+// ban score bans it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    // A block that fails validation.
+    InvalidBlock,
+    // Blocks that do not link to each other, or to any block of the chain.
+    BadPrevious,
+    // A message over the size limit.
+    Oversized,
+    // A message over the rate limit.
+    Flooding,
+}
+
+impl Misbehavior {
+    pub fn get_score(&self) -> u32 {
+        match *self {
+            Misbehavior::InvalidBlock => 100,
+            Misbehavior::BadPrevious => 20,
+            Misbehavior::Oversized => 50,
+            Misbehavior::Flooding => 5,
+        }
+    }
+}
+
+impl fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Misbehavior::InvalidBlock => "invalid block",
+            Misbehavior::BadPrevious => "bad previous",
+            Misbehavior::Oversized => "oversized message",
+            Misbehavior::Flooding => "flooding",
+        };
+        f.write_str(name)
+    }
+}
+
+// Times are in seconds.
+#[derive(Clone, Debug)]
+pub struct PeerConfig {
+    ban_score: u32,
+    ban_duration: u64,
+    max_message_size: usize,
+    // Inbound messages per second, and how many may come at once.
+    rate: u32,
+    burst: u32,
+    min_backoff: u64,
+    max_backoff: u64,
+    max_tracked: usize,
+}
+
+impl PeerConfig {
+    pub fn new() -> PeerConfig {
+        PeerConfig {
+            ban_score: 100,
+            ban_duration: 24 * 60 * 60,
+            max_message_size: 4_000_000,
+            rate: 100,
+            burst: 500,
+            min_backoff: 1,
+            max_backoff: 60 * 60,
+            max_tracked: 1000,
+        }
+    }
+
+    pub fn set_ban_score(&mut self, score: u32) {
+        self.ban_score = score;
+    }
+
+    pub fn set_ban_duration(&mut self, seconds: u64) {
+        self.ban_duration = seconds;
+    }
+
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    pub fn get_max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    pub fn set_rate_limit(&mut self, rate: u32, burst: u32) {
+        self.rate = rate;
+        self.burst = burst;
+    }
+
+    // Failed connections are retried after `min`, then twice as long after
+    // each further failure, up to `max`.
+    pub fn set_backoff(&mut self, min: u64, max: u64) {
+        self.min_backoff = min;
+        self.max_backoff = max;
+    }
+
+    // At most this many addresses, and as many hosts, are tracked.
+    pub fn set_max_tracked(&mut self, max: usize) {
+        self.max_tracked = max;
+    }
+}
+
+impl Default for PeerConfig {
+    fn default() -> PeerConfig {
+        PeerConfig::new()
+    }
+}
+
+// What is known of an IP address, whatever port it connects from: scores,
+// bans and the rate limit apply to the host rather than to one connection.
+#[derive(Clone, Debug, Default)]
+pub struct Host {
+    score: u32,
+    banned_until: u64,
+    connections: u32,
+    tokens: u32,
+    // When `tokens` was last topped up; none before the first message.
+    refilled: Option<u64>,
+}
+
+impl Host {
+    pub fn get_score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn get_connections(&self) -> u32 {
+        self.connections
+    }
+}
+
+// An address to connect to, and how connecting to it has gone.
+#[derive(Clone, Debug, Default)]
+pub struct Peer {
+    connected: bool,
+    failures: u32,
+    next_attempt: u64,
+}
+
+impl Peer {
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn get_failures(&self) -> u32 {
+        self.failures
+    }
+
+    // When the next connection may be tried.
+    pub fn get_next_attempt(&self) -> u64 {
+        self.next_attempt
+    }
+}
+
+// What is saved: addresses, and bans, which outlast restarts; scores do not.
+#[derive(Default, Serialize, Deserialize)]
+struct SavedPeers {
+    addresses: Vec<SocketAddr>,
+    #[serde(default)]
+    bans: Vec<SavedBan>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedBan {
+    ip: IpAddr,
+    until: u64,
+}
+
+// The addresses a node connects to, and how the hosts it talks to have
+// behaved. It decides nothing about transports: callers report connections,
+// failures and misbehavior, and ask before handling each inbound message.
+// Inbound connections come from ports that change on every connection, so
+// only addresses added with `add_address` are tracked by port. When the
+// tracked addresses are at the limit, the unconnected one that failed most
+// often makes room; when the hosts are, an idle one with the lowest score,
+// and failing that the ban that ends first.
+pub struct PeerManager {
+    config: PeerConfig,
+    clock: Arc<dyn Clock>,
+    peers: BTreeMap<SocketAddr, Peer>,
+    hosts: BTreeMap<IpAddr, Host>,
+}
+
+impl PeerManager {
+    pub fn new(config: PeerConfig) -> PeerManager {
+        PeerManager::with_clock(config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(config: PeerConfig, clock: Arc<dyn Clock>) -> PeerManager {
+        PeerManager {
+            config,
+            clock,
+            peers: BTreeMap::new(),
+            hosts: BTreeMap::new(),
+        }
+    }
+
+    pub fn get_config(&self) -> &PeerConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn get(&self, address: &SocketAddr) -> Option<&Peer> {
+        self.peers.get(address)
+    }
+
+    pub fn get_host(&self, ip: &IpAddr) -> Option<&Host> {
+        self.hosts.get(ip)
+    }
+
+    pub fn get_addresses(&self) -> Vec<SocketAddr> {
+        self.peers.keys().cloned().collect()
+    }
+
+    // Returns whether the address is new and now tracked.
+    pub fn add_address(&mut self, address: SocketAddr) -> bool {
+        if self.peers.contains_key(&address) {
+            return false;
+        }
+        if self.peers.len() >= self.config.max_tracked {
+            let evict = self.peers.iter()
+                .filter(|&(_, p)| !p.connected)
+                .max_by_key(|&(_, p)| p.failures)
+                .map(|(address, _)| *address);
+            match evict {
+                Some(evict) => self.peers.remove(&evict),
+                None => return false,
+            };
+        }
+        self.peers.insert(address, Peer::default());
+        true
+    }
+
+    // The host of `ip`, making room for it if it is new. `None` if every
+    // tracked host is connected.
+    fn host_entry(&mut self, ip: IpAddr) -> Option<&mut Host> {
+        if !self.hosts.contains_key(&ip) && self.hosts.len() >= self.config.max_tracked {
+            let now = self.clock.now();
+            let evict = self.hosts.iter()
+                .filter(|&(_, h)| h.connections == 0)
+                .min_by_key(|&(_, h)| if h.banned_until > now { (1, h.banned_until) } else { (0, h.score as u64) })
+                .map(|(ip, _)| *ip)?;
+            self.hosts.remove(&evict);
+        }
+        Some(self.hosts.entry(ip).or_default())
+    }
+
+    pub fn is_banned(&self, address: &SocketAddr) -> bool {
+        self.hosts.get(&address.ip()).is_some_and(|h| h.banned_until > self.clock.now())
+    }
+
+    // Unbanned, unconnected addresses whose backoff has run out, in address
+    // order.
+    pub fn get_candidates(&self) -> Vec<SocketAddr> {
+        let now = self.clock.now();
+        self.peers.iter()
+            .filter(|&(address, p)| !p.connected && p.next_attempt <= now && !self.is_banned(address))
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    // A connection to or from `address`. Connections from banned hosts are
+    // refused, as are new hosts while every tracked one is connected.
+    pub fn connected(&mut self, address: SocketAddr) -> Result<(), String> {
+        if self.is_banned(&address) {
+            return Err(format!("peer {} is banned", address));
+        }
+        let host = self.host_entry(address.ip())
+            .ok_or_else(|| format!("too many peers to track {}", address))?;
+        host.connections += 1;
+        if let Some(peer) = self.peers.get_mut(&address) {
+            peer.connected = true;
+            peer.failures = 0;
+            peer.next_attempt = 0;
+        }
+        Ok(())
+    }
+
+    pub fn disconnected(&mut self, address: &SocketAddr) {
+        if let Some(host) = self.hosts.get_mut(&address.ip()) {
+            host.connections = host.connections.saturating_sub(1);
+        }
+        if let Some(peer) = self.peers.get_mut(address) {
+            peer.connected = false;
+        }
+    }
+
+    // A connection to `address` failed or dropped; the next attempt waits
+    // out the backoff.
+    pub fn connect_failed(&mut self, address: &SocketAddr) {
+        let now = self.clock.now();
+        let (min, max) = (self.config.min_backoff, self.config.max_backoff);
+        if let Some(peer) = self.peers.get_mut(address) {
+            let backoff = min.checked_shl(peer.failures).filter(|&b| b <= max).unwrap_or(max);
+            peer.connected = false;
+            peer.failures = peer.failures.saturating_add(1);
+            peer.next_attempt = now + backoff;
+        }
+    }
+
+    // Adds the score of `what` to the host of `address`. Returns true if
+    // that bans the host, whose connections are then dropped; its score
+    // starts over when the ban ends. Hosts that never connected are ignored.
+    pub fn misbehaving(&mut self, address: &SocketAddr, what: Misbehavior) -> bool {
+        let now = self.clock.now();
+        let (ban_score, ban_duration) = (self.config.ban_score, self.config.ban_duration);
+        let host = match self.hosts.get_mut(&address.ip()) {
+            Some(host) => host,
+            None => return false,
+        };
+        host.score = host.score.saturating_add(what.get_score());
+        if host.score < ban_score {
+            return false;
+        }
+        host.score = 0;
+        host.connections = 0;
+        host.banned_until = now + ban_duration;
+        for (_, peer) in self.peers.iter_mut().filter(|&(a, _)| a.ip() == address.ip()) {
+            peer.connected = false;
+        }
+        true
+    }
+
+    pub fn unban(&mut self, address: &SocketAddr) {
+        if let Some(host) = self.hosts.get_mut(&address.ip()) {
+            host.banned_until = 0;
+        }
+    }
+
+    // Whether to handle a message of `size` bytes from `address`. Messages
+    // from hosts that are not connected or are banned are refused;
+    // oversized and rate limited ones are also scored as misbehavior.
+    pub fn check_message(&mut self, address: &SocketAddr, size: usize) -> Result<(), String> {
+        if self.hosts.get(&address.ip()).is_none_or(|h| h.connections == 0) {
+            return Err(format!("peer {} is not connected", address));
+        }
+        if self.is_banned(address) {
+            return Err(format!("peer {} is banned", address));
+        }
+        if size > self.config.max_message_size {
+            self.misbehaving(address, Misbehavior::Oversized);
+            return Err(format!("message of {} bytes exceeds {}", size, self.config.max_message_size));
+        }
+
+        let now = self.clock.now();
+        let (rate, burst) = (self.config.rate, self.config.burst);
+        let host = self.hosts.get_mut(&address.ip()).unwrap();
+        let refill = match host.refilled {
+            Some(refilled) => now.saturating_sub(refilled).saturating_mul(rate as u64),
+            None => burst as u64,
+        };
+        host.tokens = (host.tokens as u64).saturating_add(refill).min(burst as u64) as u32;
+        host.refilled = Some(now);
+        if host.tokens == 0 {
+            self.misbehaving(address, Misbehavior::Flooding);
+            return Err(format!("peer {} is over {} messages per second", address, rate));
+        }
+        host.tokens -= 1;
+        Ok(())
+    }
+
+    // Saves the addresses and any bans still in effect as JSON.
+    pub fn save(&self, file: &Path) -> Result<(), String> {
+        let now = self.clock.now();
+        let saved = SavedPeers {
+            addresses: self.get_addresses(),
+            bans: self.hosts.iter()
+                .filter(|&(_, host)| host.banned_until > now)
+                .map(|(ip, host)| SavedBan { ip: *ip, until: host.banned_until })
+                .collect(),
+        };
+        let json = serde_json::to_string_pretty(&saved).unwrap();
+        let tmp = file.with_extension("tmp");
+        fs::write(&tmp, json)
+            .and_then(|_| fs::rename(&tmp, file))
+            .map_err(|e| format!("{}: {}", file.display(), e))
+    }
+
+    // Adds the addresses and bans saved in `file`, as far as the limit on
+    // tracked ones allows; a missing file is an empty one, as on a node's
+    // first start.
+    pub fn load(&mut self, file: &Path) -> Result<(), String> {
+        let json = match fs::read_to_string(file) {
+            Ok(json) => json,
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("{}: {}", file.display(), e)),
+        };
+        let saved: SavedPeers = serde_json::from_str(&json)
+            .map_err(|e| format!("{}: {}", file.display(), e))?;
+        for address in saved.addresses {
+            self.add_address(address);
+        }
+        for ban in saved.bans {
+            if let Some(host) = self.host_entry(ban.ip) {
+                host.banned_until = host.banned_until.max(ban.until);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use util::time::VirtualClock;
+
+    fn manager() -> (PeerManager, VirtualClock) {
+        let clock = VirtualClock::new(1_000_000);
+        let mut config = PeerConfig::new();
+        config.set_rate_limit(2, 4);
+        config.set_max_message_size(1000);
+        config.set_backoff(1, 8);
+        config.set_max_tracked(3);
+        (PeerManager::with_clock(config, Arc::new(clock.clone())), clock)
+    }
+
+    fn addr(host: u8) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, host], 8333))
+    }
+
+    #[test]
+    fn bans_rate_limits_and_backs_off() {
+        let (mut peers, clock) = manager();
+        peers.connected(addr(1)).unwrap();
+
+        // The burst, then one message per half second.
+        for _ in 0..4 {
+            peers.check_message(&addr(1), 10).unwrap();
+        }
+        assert!(peers.check_message(&addr(1), 10).unwrap_err().contains("messages per second"));
+        clock.advance_millis(1000);
+        assert!(peers.check_message(&addr(1), 10).is_ok());
+        assert!(peers.check_message(&addr(1), 10).is_ok());
+        assert!(peers.check_message(&addr(1), 10).is_err());
+        assert_eq!(peers.get_host(&addr(1).ip()).unwrap().get_score(), 10);
+
+        // Oversized messages and bad linkage add up to a ban.
+        assert!(peers.check_message(&addr(1), 1001).unwrap_err().contains("exceeds"));
+        assert!(!peers.misbehaving(&addr(1), Misbehavior::BadPrevious));
+        assert!(peers.misbehaving(&addr(1), Misbehavior::BadPrevious));
+        assert!(peers.is_banned(&addr(1)));
+        assert!(peers.connected(addr(1)).is_err());
+        clock.advance_millis(24 * 60 * 60 * 1000);
+        assert!(!peers.is_banned(&addr(1)));
+
+        // An invalid block bans at once.
+        peers.connected(addr(2)).unwrap();
+        assert!(peers.misbehaving(&addr(2), Misbehavior::InvalidBlock));
+        assert_eq!(peers.get_host(&addr(2).ip()).unwrap().get_connections(), 0);
+
+        // Retries wait 1, 2, 4, 8 and then at most 8 seconds.
+        peers.add_address(addr(3));
+        let now = clock.now();
+        for backoff in &[1, 2, 4, 8, 8] {
+            peers.connect_failed(&addr(3));
+            assert_eq!(peers.get(&addr(3)).unwrap().get_next_attempt(), now + backoff);
+        }
+        assert!(!peers.get_candidates().contains(&addr(3)));
+        clock.advance_millis(8000);
+        assert!(peers.get_candidates().contains(&addr(3)));
+        peers.connected(addr(3)).unwrap();
+        assert_eq!(peers.get(&addr(3)).unwrap().get_failures(), 0);
+        assert!(peers.get(&addr(3)).unwrap().is_connected());
+    }
+
+    #[test]
+    fn other_ports_are_the_same_host() {
+        let (mut peers, _clock) = manager();
+        let port = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        peers.connected(port(50000)).unwrap();
+        for _ in 0..4 {
+            peers.check_message(&port(50000), 10).unwrap();
+        }
+
+        // Reconnecting gets no new burst, and the ban covers every port.
+        peers.disconnected(&port(50000));
+        peers.connected(port(50001)).unwrap();
+        assert!(peers.check_message(&port(50001), 10).is_err());
+        assert!(peers.misbehaving(&port(50001), Misbehavior::InvalidBlock));
+        assert!(peers.connected(port(50002)).unwrap_err().contains("banned"));
+        // Inbound ports are not addresses to connect to.
+        assert!(peers.is_empty());
+    }
+
+    #[test]
+    fn tracking_is_capped() {
+        let (mut peers, _clock) = manager();
+        for host in 1..4 {
+            assert!(peers.add_address(addr(host)));
+        }
+        // The address that failed most makes room.
+        peers.connect_failed(&addr(2));
+        assert!(peers.add_address(addr(4)));
+        assert_eq!(peers.get_addresses(), vec![addr(1), addr(3), addr(4)]);
+
+        // Connected hosts stay; an idle one makes room before a banned one.
+        for host in &[1, 3, 4] {
+            peers.connected(addr(*host)).unwrap();
+        }
+        assert!(!peers.add_address(addr(5)));
+        assert!(peers.connected(addr(5)).unwrap_err().contains("too many"));
+        peers.misbehaving(&addr(1), Misbehavior::InvalidBlock);
+        peers.disconnected(&addr(3));
+        peers.connected(addr(5)).unwrap();
+        assert!(peers.get_host(&addr(3).ip()).is_none());
+        assert!(peers.is_banned(&addr(1)));
+    }
+
+    #[test]
+    fn addresses_and_bans_persist() {
+        let file = env::temp_dir().join(format!("rustychain-peers-{}.json", process::id()));
+        let (mut peers, clock) = manager();
+        assert!(peers.add_address(addr(1)));
+        assert!(!peers.add_address(addr(1)));
+        peers.connected(addr(2)).unwrap();
+        peers.misbehaving(&addr(2), Misbehavior::InvalidBlock);
+        // Hosts that never connected are neither tracked nor saved.
+        assert!(peers.check_message(&addr(3), 10).unwrap_err().contains("not connected"));
+        assert!(!peers.misbehaving(&addr(3), Misbehavior::InvalidBlock));
+        peers.save(&file).unwrap();
+
+        let mut restarted = PeerManager::with_clock(PeerConfig::new(), Arc::new(clock.clone()));
+        restarted.load(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(restarted.get_addresses(), vec![addr(1)]);
+        assert!(restarted.is_banned(&SocketAddr::from(([127, 0, 0, 2], 1))));
+        assert!(!restarted.is_banned(&addr(1)));
+        assert!(restarted.get_host(&addr(3).ip()).is_none());
+
+        // No file yet is no peers yet.
+        restarted.load(&file).unwrap();
+        assert_eq!(restarted.len(), 1);
+    }
+}
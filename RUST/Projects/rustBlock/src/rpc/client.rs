@@ -0,0 +1,47 @@
+extern crate serde_json;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use self::serde_json::Value;
+
+use rpc::RpcError;
+use rpc::error::MISC_ERROR;
+use util::http;
+
+
+// Calls methods on an `RpcServer`.
+pub struct RpcClient {
+    addr: String,
+    next_id: AtomicUsize,
+}
+
+impl RpcClient {
+    pub fn new(addr: &str) -> RpcClient {
+        RpcClient {
+            addr: addr.to_string(),
+            next_id: AtomicUsize::new(1),
+        }
+    }
+
+    // Transport and decoding failures are reported as MISC_ERROR.
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let body = serde_json::to_vec(&request).unwrap();
+
+        let (status, body) = http::post(&self.addr[..], "/", "application/json", &body)
+            .map_err(|e| RpcError::new(MISC_ERROR, format!("{}: {}", self.addr, e)))?;
+        if status != 200 {
+            return Err(RpcError::new(MISC_ERROR, format!("{}: HTTP status {}", self.addr, status)));
+        }
+
+        let mut response: Value = serde_json::from_slice(&body)
+            .map_err(|e| RpcError::new(MISC_ERROR, format!("{}: invalid response: {}", self.addr, e)))?;
+        if let Some(error) = response.get("error") {
+            let code = error["code"].as_i64().unwrap_or(MISC_ERROR);
+            let message = error["message"].as_str().unwrap_or("unknown error");
+            return Err(RpcError::new(code, format!("{}: {}", method, message)));
+        }
+        Ok(response["result"].take())
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+
+
+// Error codes follow JSON-RPC 2.0 and, for chain specific failures, bitcoind.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const MISC_ERROR: i64 = -1;
+pub const INVALID_ADDRESS_OR_KEY: i64 = -5;
+pub const INVALID_PARAMETER: i64 = -8;
+pub const DESERIALIZATION_ERROR: i64 = -22;
+pub const VERIFY_REJECTED: i64 = -26;
+pub const VERIFY_ALREADY_IN_CHAIN: i64 = -27;
+
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: String) -> RpcError {
+        RpcError {
+            code,
+            message,
+        }
+    }
+
+    pub fn invalid_params(message: &str) -> RpcError {
+        RpcError::new(INVALID_PARAMS, message.to_string())
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
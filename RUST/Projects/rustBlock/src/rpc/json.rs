@@ -0,0 +1,116 @@
+extern crate serde_json;
+
+use self::serde_json::Value;
+
+use blockchain::{Block, BlockChain, Encodable, HistoryEntry, HistoryKind, Transaction, STATE_ROOT_VERSION};
+use util::hash::HashAlgorithm;
+use util::hex::ToHex;
+
+
+pub fn transaction_to_json(algorithm: HashAlgorithm, tx: &Transaction) -> Value {
+    let hash = tx.get_hash(algorithm);
+
+    let inputs: Vec<Value> = tx.get_inputs().iter().map(|i| {
+        let previous_out = i.get_previous_out();
+        json!({
+            "hash": previous_out.get_tx_hash().to_string(),
+            "index": previous_out.get_index(),
+            "sequence": i.get_sequence(),
+        })
+    }).collect();
+
+    let outputs: Vec<Value> = tx.get_outputs().iter().enumerate().map(|(n, o)| {
+        let mut output = json!({
+            "n": n,
+            "amount": o.get_amount(),
+            "script": o.get_script().to_hex(),
+        });
+        if !o.is_native() {
+            output["asset"] = json!(o.get_asset().to_string());
+        }
+        output
+    }).collect();
+
+    let mut value = json!({
+        "txid": hash.to_string(),
+        "version": tx.get_version(),
+        "timestamp": tx.get_timestamp(),
+        "lock_time": tx.get_lock_time(),
+        "size": tx.to_bytes().len(),
+        "inputs": inputs,
+        "outputs": outputs,
+    });
+
+    if !tx.get_writes().is_empty() {
+        value["writes"] = tx.get_writes().iter().map(|w| json!({
+            "key": w.get_key().to_hex(),
+            "value": w.get_value().to_hex(),
+        })).collect();
+    }
+
+    if let Some(issuance) = tx.get_issuance() {
+        value["issuance"] = json!({
+            "amount": issuance.get_amount(),
+            "name": issuance.get_name(),
+            "asset": tx.get_issued_asset(algorithm).map(|a| a.to_string()),
+        });
+    }
+    value
+}
+
+// `verbosity` 1 lists transaction hashes, 2 expands them to objects.
+pub fn block_to_json(chain: &BlockChain, height: usize, block: &Block, verbosity: u64) -> Value {
+    let algorithm = chain.get_hash_algorithm();
+    let hash = block.get_hash(algorithm);
+
+    let transactions: Vec<Value> = block.get_transactions().iter().map(|tx| {
+        if verbosity > 1 {
+            transaction_to_json(algorithm, tx)
+        } else {
+            let tx_hash = tx.get_hash(algorithm);
+            Value::String(tx_hash.to_string())
+        }
+    }).collect();
+
+    let mut value = json!({
+        "hash": hash.to_string(),
+        "height": height,
+        "confirmations": chain.len() - height,
+        "version": block.get_version(),
+        "timestamp": block.get_timestamp(),
+        "previous": block.get_previous().to_string(),
+        "merkle_root": block.get_merkle_root().to_hex(),
+        "size": block.to_bytes().len(),
+        "transactions": transactions,
+    });
+
+    if block.get_version() >= STATE_ROOT_VERSION {
+        value["state_root"] = Value::String(block.get_state_root().to_hex());
+    }
+    if let Some(next) = chain.get_header(height + 1) {
+        let next_hash = next.get_hash(algorithm);
+        value["next"] = Value::String(next_hash.to_string());
+    }
+    value
+}
+
+pub fn history_entry_to_json(entry: &HistoryEntry) -> Value {
+    let mut value = json!({
+        "height": entry.height,
+        "hash": entry.out.get_tx_hash().to_string(),
+        "index": entry.out.get_index(),
+        "amount": entry.amount,
+    });
+    if !entry.asset.is_zero() {
+        value["asset"] = json!(entry.asset.to_string());
+    }
+
+    match entry.kind {
+        HistoryKind::Output => value["kind"] = json!("output"),
+        HistoryKind::Spend { tx, input } => {
+            value["kind"] = json!("spend");
+            value["spent_by"] = json!({ "txid": tx.to_string(), "input": input });
+        },
+    }
+    value
+}
@@ -0,0 +1,46 @@
+use std::fmt::Write;
+
+use blockchain::{ChainStats, Mempool};
+
+
+// Every metric is a gauge: reorganizations can lower even the counts.
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    writeln!(out, "# HELP rustychain_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE rustychain_{} gauge", name).unwrap();
+    writeln!(out, "rustychain_{} {}", name, value).unwrap();
+}
+
+// The chain statistics and mempool size in the Prometheus text format.
+pub fn render(stats: &ChainStats, mempool: &Mempool) -> String {
+    let mut out = String::new();
+    gauge(&mut out, "blocks", "Blocks in the chain, the height of the tip plus one.", stats.blocks as f64);
+    gauge(&mut out, "blocks_with_data", "Blocks whose transactions are kept.", stats.blocks_with_data as f64);
+    gauge(&mut out, "transactions", "Transactions in the blocks with data.", stats.transactions as f64);
+    gauge(&mut out, "transactions_per_block", "Average transactions per block with data.",
+          stats.transactions_per_block);
+    gauge(&mut out, "block_interval_seconds", "Average time between block timestamps.",
+          stats.average_block_interval);
+    gauge(&mut out, "supply", "Amount held by unspent outputs.", stats.total_supply as f64);
+    gauge(&mut out, "utxos", "Unspent transaction outputs.", stats.utxo_count as f64);
+    gauge(&mut out, "fees", "Fees paid in the blocks with data.", stats.total_fees as f64);
+    gauge(&mut out, "mempool_transactions", "Transactions in the mempool.", mempool.len() as f64);
+    gauge(&mut out, "mempool_bytes", "Encoded size of the mempool transactions.",
+          mempool.get_size_bytes() as f64);
+    gauge(&mut out, "mempool_fees", "Fees the mempool transactions pay.", mempool.get_total_fee() as f64);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::BlockChain;
+
+    #[test]
+    fn every_metric_has_help_and_type() {
+        let text = render(&ChainStats::new(&BlockChain::new()), &Mempool::new());
+        let samples: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(samples.len(), 11);
+        assert_eq!(text.lines().count(), 3 * samples.len());
+        assert!(samples.contains(&"rustychain_blocks 0"));
+    }
+}
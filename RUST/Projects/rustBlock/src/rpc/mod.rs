@@ -0,0 +1,10 @@
+
+mod client;
+pub mod error;
+mod json;
+mod metrics;
+mod server;
+
+pub use self::client::RpcClient;
+pub use self::error::RpcError;
+pub use self::server::RpcServer;
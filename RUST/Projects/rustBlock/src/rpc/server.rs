@@ -0,0 +1,465 @@
+extern crate serde_json;
+
+use std::io;
+use std::iter;
+use std::str::FromStr;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use self::serde_json::Value;
+
+use blockchain::{follow_chain, BlockChain, BlockHash, ChainStats, Decodable, Encodable, Mempool, RejectReason, Transaction, TxId, MAX_FEE_BLOCKS};
+use rpc::RpcError;
+use rpc::error::*;
+use rpc::json::{block_to_json, history_entry_to_json, transaction_to_json};
+use rpc::metrics;
+use util::hex::{FromHex, ToHex};
+use util::http;
+
+
+const MAX_HEADERS: usize = 2000;
+
+// How often an idle event stream sends a comment, which also notices clients
+// that have gone away.
+const EVENT_KEEPALIVE: Duration = Duration::from_secs(15);
+
+// JSON-RPC 2.0 over HTTP POST, backed by a `BlockChain` and its mempool.
+// GET /metrics serves chain statistics for Prometheus, and GET /events a
+// server-sent event stream of the chain's and mempool's events.
+#[derive(Clone)]
+pub struct RpcServer {
+    chain: Arc<RwLock<BlockChain>>,
+    mempool: Arc<Mutex<Mempool>>,
+}
+
+fn param(params: &[Value], index: usize) -> Option<&Value> {
+    params.get(index).and_then(|v| if v.is_null() { None } else { Some(v) })
+}
+
+fn parse_hash<T: FromStr<Err = String>>(value: Option<&Value>) -> Result<T, RpcError> {
+    let s = value.and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::invalid_params("expected a hex hash"))?;
+    s.parse().map_err(|e| RpcError::new(INVALID_PARAMETER, e))
+}
+
+fn parse_hex(value: Option<&Value>) -> Result<Vec<u8>, RpcError> {
+    let s = value.and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::invalid_params("expected a hex string"))?;
+    s.from_hex().map_err(|e| RpcError::new(DESERIALIZATION_ERROR, format!("{}", e)))
+}
+
+impl RpcServer {
+    // `chain` may be shared with, and updated by, other threads.
+    pub fn new(chain: Arc<RwLock<BlockChain>>) -> RpcServer {
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        follow_chain(mempool.clone(), chain.clone());
+        RpcServer {
+            chain,
+            mempool,
+        }
+    }
+
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let server = self.clone();
+        http::serve(addr, move |request| {
+            if request.method == "GET" && request.route() == "/metrics" {
+                return http::Response::new(200, "text/plain; version=0.0.4", server.metrics().into_bytes());
+            }
+            if request.method == "GET" && request.route() == "/events" {
+                return server.events();
+            }
+            if request.method != "POST" {
+                return http::Response::text(405, "JSON-RPC requests must be POSTed\n");
+            }
+            let body = serde_json::to_vec(&server.handle_request(&request.body)).unwrap();
+            http::Response::new(200, "application/json", body)
+        })
+    }
+
+    pub fn metrics(&self) -> String {
+        let stats = ChainStats::new(&self.chain.read().unwrap());
+        metrics::render(&stats, &self.mempool.lock().unwrap())
+    }
+
+    fn events(&self) -> http::Response {
+        let events = self.chain.read().unwrap().get_events().subscribe();
+        let chunks = iter::from_fn(move || match events.recv_timeout(EVENT_KEEPALIVE) {
+            Ok(event) => Some(format!("event: {}\ndata: {}\n\n",
+                                      event.get_name(), serde_json::to_string(&event).unwrap()).into_bytes()),
+            Err(RecvTimeoutError::Timeout) => Some(b": keepalive\n\n".to_vec()),
+            Err(RecvTimeoutError::Disconnected) => None,
+        });
+        http::Response::stream("text/event-stream", chunks)
+    }
+
+    // Handles a single JSON-RPC request body and returns the response object.
+    pub fn handle_request(&self, body: &[u8]) -> Value {
+        let request: Value = match serde_json::from_slice(body) {
+            Ok(v) => v,
+            Err(e) => return error_response(Value::Null, RpcError::new(PARSE_ERROR, format!("{}", e))),
+        };
+
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = match request.get("method").and_then(|m| m.as_str()) {
+            Some(m) => m,
+            None => return error_response(id, RpcError::new(INVALID_REQUEST, "missing method".to_string())),
+        };
+        let params = match request.get("params") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(a)) => a.clone(),
+            Some(_) => return error_response(id, RpcError::invalid_params("params must be an array")),
+        };
+
+        match self.dispatch(method, &params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+            Err(e) => error_response(id, e),
+        }
+    }
+
+    fn dispatch(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "getblock" => self.get_block(params),
+            "getblockhash" => self.get_block_hash(params),
+            "getbestblockhash" => self.get_best_block_hash(),
+            "getheaders" => self.get_headers(params),
+            "getblockfilter" => self.get_block_filter(params),
+            "getmerkleproof" => self.get_merkle_proof(params),
+            "getstateproof" => self.get_state_proof(params),
+            "gettransaction" => self.get_transaction(params),
+            "sendrawtransaction" => self.send_raw_transaction(params),
+            "getbalance" => self.get_balance(params),
+            "getbalances" => self.get_balances(params),
+            "getmempoolinfo" => self.get_mempool_info(),
+            "estimatesmartfee" => self.estimate_smart_fee(params),
+            "getaddresshistory" => self.get_address_history(params),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("method '{}' not found", method))),
+        }
+    }
+
+    // getblock "hash" ( verbosity )
+    fn get_block(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let hash: BlockHash = parse_hash(param(params, 0))?;
+        let verbosity = match param(params, 1) {
+            Some(v) => v.as_u64().ok_or_else(|| RpcError::invalid_params("verbosity must be 0, 1 or 2"))?,
+            None => 1,
+        };
+
+        let chain = self.chain.read().unwrap();
+        let height = chain.get_height(&hash)
+            .ok_or_else(|| RpcError::new(INVALID_ADDRESS_OR_KEY, "block not found".to_string()))?;
+        let block = chain.get_block(height)
+            .ok_or_else(|| RpcError::new(MISC_ERROR, "block data is pruned".to_string()))?;
+
+        if verbosity == 0 {
+            return Ok(Value::String(block.to_bytes().to_hex()));
+        }
+        Ok(block_to_json(&chain, height, block, verbosity))
+    }
+
+    // getblockhash height
+    fn get_block_hash(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let height = param(params, 0).and_then(|v| v.as_u64())
+            .ok_or_else(|| RpcError::invalid_params("expected a block height"))?;
+
+        let chain = self.chain.read().unwrap();
+        let header = chain.get_header(height as usize)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMETER, "block height out of range".to_string()))?;
+
+        Ok(Value::String(header.get_hash(chain.get_hash_algorithm()).to_string()))
+    }
+
+    fn get_best_block_hash(&self) -> Result<Value, RpcError> {
+        let chain = self.chain.read().unwrap();
+        match chain.get_best_hash() {
+            Some(hash) => Ok(Value::String(hash.to_string())),
+            None => Err(RpcError::new(INVALID_ADDRESS_OR_KEY, "chain is empty".to_string())),
+        }
+    }
+
+    // getheaders start_height ( count ) -- raw hex headers, at most
+    // MAX_HEADERS per call.
+    fn get_headers(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let start = param(params, 0).and_then(|v| v.as_u64())
+            .ok_or_else(|| RpcError::invalid_params("expected a start height"))? as usize;
+        let count = match param(params, 1) {
+            Some(v) => v.as_u64().ok_or_else(|| RpcError::invalid_params("expected a count"))? as usize,
+            None => MAX_HEADERS,
+        };
+
+        let chain = self.chain.read().unwrap();
+        let headers = (start..start.saturating_add(count.min(MAX_HEADERS)))
+            .map_while(|height| chain.get_header(height))
+            .map(|header| Value::String(header.to_bytes().to_hex()))
+            .collect();
+        Ok(Value::Array(headers))
+    }
+
+    // getblockfilter "blockhash" -- requires the filter index.
+    fn get_block_filter(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let hash: BlockHash = parse_hash(param(params, 0))?;
+
+        let chain = self.chain.read().unwrap();
+        let index = chain.get_filter_index()
+            .ok_or_else(|| RpcError::new(MISC_ERROR, "block filter index is not enabled".to_string()))?;
+        let (filter, header) = chain.get_height(&hash)
+            .and_then(|height| index.get(height))
+            .ok_or_else(|| RpcError::new(INVALID_ADDRESS_OR_KEY, "block not found".to_string()))?;
+
+        Ok(json!({
+            "filter": filter.to_bytes().to_hex(),
+            "header": header.to_hex(),
+        }))
+    }
+
+    // getmerkleproof "txid" -- the branch linking a confirmed transaction to
+    // the merkle_root of its block.
+    fn get_merkle_proof(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let hash: TxId = parse_hash(param(params, 0))?;
+
+        let chain = self.chain.read().unwrap();
+        let (height, branch) = chain.get_merkle_proof(&hash)
+            .ok_or_else(|| RpcError::new(INVALID_ADDRESS_OR_KEY, "transaction not in chain".to_string()))?;
+        let block_hash = chain.get_block(height).unwrap().get_hash(chain.get_hash_algorithm());
+
+        Ok(json!({
+            "block_hash": block_hash.to_string(),
+            "height": height,
+            "index": branch.get_index(),
+            "branch": branch.get_hashes().iter().map(|h| h.to_hex()).collect::<Vec<_>>(),
+        }))
+    }
+
+    // getstateproof "key" ( height ) -- the value of a hex encoded state key
+    // after the block at `height`, the tip by default, and the branch linking
+    // it to that block's state_root.
+    fn get_state_proof(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let key = parse_hex(param(params, 0))?;
+        let chain = self.chain.read().unwrap();
+        let height = match param(params, 1) {
+            Some(v) => v.as_u64().ok_or_else(|| RpcError::invalid_params("expected a block height"))? as usize,
+            None => chain.len().checked_sub(1)
+                .ok_or_else(|| RpcError::new(INVALID_ADDRESS_OR_KEY, "chain is empty".to_string()))?,
+        };
+
+        let header = chain.get_header(height)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMETER, "block height out of range".to_string()))?;
+        let proof = chain.get_state_proof(&key, height)
+            .ok_or_else(|| RpcError::new(INVALID_ADDRESS_OR_KEY, "key not set at that height or no state root".to_string()))?;
+
+        Ok(json!({
+            "block_hash": header.get_hash(chain.get_hash_algorithm()).to_string(),
+            "height": height,
+            "state_root": header.get_state_root().to_hex(),
+            "key": proof.key.to_hex(),
+            "value": proof.value.to_hex(),
+            "index": proof.branch.get_index(),
+            "branch": proof.branch.get_hashes().iter().map(|h| h.to_hex()).collect::<Vec<_>>(),
+        }))
+    }
+
+    // gettransaction "txid"
+    fn get_transaction(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let hash: TxId = parse_hash(param(params, 0))?;
+
+        let chain = self.chain.read().unwrap();
+        if let Some((height, tx)) = chain.find_transaction(&hash) {
+            let block_hash = chain.get_block(height).unwrap().get_hash(chain.get_hash_algorithm());
+
+            let mut value = transaction_to_json(chain.get_hash_algorithm(), tx);
+            value["block_hash"] = Value::String(block_hash.to_string());
+            value["height"] = json!(height);
+            value["confirmations"] = json!(chain.len() - height);
+            return Ok(value);
+        }
+
+        let mempool = self.mempool.lock().unwrap();
+        match mempool.get(&hash) {
+            Some(entry) => {
+                let mut value = transaction_to_json(chain.get_hash_algorithm(), entry.get_transaction());
+                value["confirmations"] = json!(0);
+                value["fee"] = json!(entry.get_fee());
+                value["time"] = json!(entry.get_time());
+                Ok(value)
+            },
+            None => Err(RpcError::new(INVALID_ADDRESS_OR_KEY, "transaction not found".to_string())),
+        }
+    }
+
+    // sendrawtransaction "hexstring"
+    fn send_raw_transaction(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let raw = parse_hex(param(params, 0))?;
+        let tx = Transaction::from_bytes(&raw)
+            .map_err(|e| RpcError::new(DESERIALIZATION_ERROR, format!("tx decode failed: {}", e)))?;
+
+        let chain = self.chain.read().unwrap();
+        let mut mempool = self.mempool.lock().unwrap();
+        let hash = mempool.accept(tx, &chain).map_err(|e| match e {
+            RejectReason::AlreadyInChain(_) => RpcError::new(VERIFY_ALREADY_IN_CHAIN, e.to_string()),
+            _ => RpcError::new(VERIFY_REJECTED, e.to_string()),
+        })?;
+        Ok(Value::String(hash.to_string()))
+    }
+
+    // getbalance ( "script" ) -- confirmed unspent amount, optionally only
+    // that paying the hex encoded output script.
+    fn get_balance(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let script = match param(params, 0) {
+            Some(v) => Some(parse_hex(Some(v))?),
+            None => None,
+        };
+
+        let chain = self.chain.read().unwrap();
+        Ok(json!(chain.get_utxos().get_balance(script.as_ref().map(|s| &s[..]))))
+    }
+
+    // getbalances ( "script" ) -- as getbalance, per asset: the native coin
+    // under "native", issued assets under their ID.
+    fn get_balances(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let script = match param(params, 0) {
+            Some(v) => Some(parse_hex(Some(v))?),
+            None => None,
+        };
+
+        let chain = self.chain.read().unwrap();
+        let balances = chain.get_utxos().get_balances(script.as_ref().map(|s| &s[..]));
+        let mut value = json!({ "native": balances.get_native() });
+        for (asset, amount) in balances.iter().filter(|(asset, _)| !asset.is_zero()) {
+            value[asset.to_string()] = json!(amount);
+        }
+        Ok(value)
+    }
+
+    // getaddresshistory "script" -- requires the address index.
+    fn get_address_history(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let script = parse_hex(param(params, 0))?;
+
+        let chain = self.chain.read().unwrap();
+        let index = chain.get_address_index()
+            .ok_or_else(|| RpcError::new(MISC_ERROR, "address index is not enabled".to_string()))?;
+        Ok(Value::Array(index.get_history(&script).iter().map(history_entry_to_json).collect()))
+    }
+
+    // estimatesmartfee conf_target -- the fee per 1000 bytes to confirm
+    // within conf_target blocks.
+    fn estimate_smart_fee(&self, params: &[Value]) -> Result<Value, RpcError> {
+        let target = param(params, 0).and_then(|v| v.as_u64())
+            .ok_or_else(|| RpcError::invalid_params("expected a confirmation target"))? as usize;
+        let target = target.clamp(1, MAX_FEE_BLOCKS);
+
+        let chain = self.chain.read().unwrap();
+        Ok(match chain.get_fee_estimator().estimate(target) {
+            Some(rate) => json!({ "feerate": rate, "blocks": target }),
+            None => json!({ "errors": ["insufficient data"], "blocks": target }),
+        })
+    }
+
+    fn get_mempool_info(&self) -> Result<Value, RpcError> {
+        let mempool = self.mempool.lock().unwrap();
+        Ok(json!({
+            "size": mempool.len(),
+            "bytes": mempool.get_size_bytes(),
+            "fees": mempool.get_total_fee(),
+        }))
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": error.code, "message": error.message },
+        "id": id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{Block, BlockFilter, OutPoint};
+    use blockchain::sample;
+    use util::hash::HashAlgorithm;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn server() -> RpcServer {
+        RpcServer::new(Arc::new(RwLock::new(sample::chain())))
+    }
+
+    fn call(server: &RpcServer, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": 1 });
+        server.handle_request(&serde_json::to_vec(&request).unwrap())
+    }
+
+    fn error_code(response: &Value) -> i64 {
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    #[test]
+    fn queries() {
+        let server = server();
+        let tip = call(&server, "getbestblockhash", json!([]));
+        assert_eq!(tip["result"], call(&server, "getblockhash", json!([2]))["result"]);
+        assert_eq!(tip["id"], json!(1));
+
+        let block = call(&server, "getblock", json!([tip["result"]]))["result"].clone();
+        assert_eq!(block["height"], json!(2));
+        assert_eq!(block["transactions"].as_array().unwrap().len(), 2);
+        let raw = call(&server, "getblock", json!([tip["result"], 0]))["result"].clone();
+        assert_eq!(Block::from_bytes(&raw.as_str().unwrap().from_hex().unwrap()).unwrap().get_hash(ALGORITHM).to_string(), tip["result"]);
+
+        let txid = block["transactions"][1].as_str().unwrap().to_string();
+        let tx = call(&server, "gettransaction", json!([txid]))["result"].clone();
+        assert_eq!((tx["height"].clone(), tx["confirmations"].clone()), (json!(2), json!(1)));
+
+        assert_eq!(call(&server, "getbalance", json!(["a11ce0"]))["result"], json!(6990));
+        assert_eq!(call(&server, "getbalance", json!([]))["result"], json!(14990));
+        assert_eq!(call(&server, "getheaders", json!([1, 5]))["result"].as_array().unwrap().len(), 2);
+
+        server.chain.write().unwrap().enable_filter_index();
+        let filter = call(&server, "getblockfilter", json!([tip["result"]]))["result"].clone();
+        let raw = filter["filter"].as_str().unwrap().from_hex().unwrap();
+        assert_eq!(BlockFilter::from_bytes(&raw).unwrap().len(), 3);
+        assert_eq!(filter["header"].as_str().unwrap().len(), 64);
+    }
+
+    #[test]
+    fn errors() {
+        let server = server();
+        assert_eq!(error_code(&server.handle_request(b"{")), PARSE_ERROR);
+        assert_eq!(error_code(&server.handle_request(b"{\"id\": 1}")), INVALID_REQUEST);
+        assert_eq!(error_code(&call(&server, "nosuchmethod", json!([]))), METHOD_NOT_FOUND);
+        assert_eq!(error_code(&call(&server, "getblockhash", json!(["one"]))), INVALID_PARAMS);
+        assert_eq!(error_code(&call(&server, "getblockhash", json!([3]))), INVALID_PARAMETER);
+        assert_eq!(error_code(&call(&server, "getblock", json!(["00"]))), INVALID_PARAMETER);
+        assert_eq!(error_code(&call(&server, "gettransaction", json!([TxId::default().to_string()]))), INVALID_ADDRESS_OR_KEY);
+        assert_eq!(error_code(&call(&server, "getaddresshistory", json!(["a11ce0"]))), MISC_ERROR);
+        assert_eq!(error_code(&call(&server, "getblockfilter", json!([BlockHash::default().to_string()]))), MISC_ERROR);
+        server.chain.write().unwrap().enable_filter_index();
+        assert_eq!(error_code(&call(&server, "getblockfilter", json!([BlockHash::default().to_string()]))),
+                   INVALID_ADDRESS_OR_KEY);
+        assert_eq!(error_code(&call(&server, "sendrawtransaction", json!(["zz"]))), DESERIALIZATION_ERROR);
+    }
+
+    #[test]
+    fn send_raw_transaction() {
+        let server = server();
+        let coinbase = call(&server, "getblock", json!([call(&server, "getblockhash", json!([1]))["result"]]))["result"]["transactions"][0]
+            .as_str().unwrap().parse().unwrap();
+        let mut tx = Transaction::new();
+        tx.add_input(OutPoint::new(coinbase, 0));
+        tx.add_output(4000, b"carol");
+        let raw = tx.to_bytes().to_hex();
+
+        let sent = call(&server, "sendrawtransaction", json!([raw]));
+        assert_eq!(sent["result"], json!(tx.get_hash(ALGORITHM).to_string()));
+        let pending = call(&server, "gettransaction", json!([tx.get_hash(ALGORITHM).to_string()]))["result"].clone();
+        assert_eq!((pending["confirmations"].clone(), pending["fee"].clone()), (json!(0), json!(1000)));
+        assert_eq!(call(&server, "getmempoolinfo", json!([]))["result"]["size"], json!(1));
+
+        // Again, and a spend of the same coin.
+        assert_eq!(error_code(&call(&server, "sendrawtransaction", json!([raw]))), VERIFY_REJECTED);
+        tx.add_output(1, b"dave");
+        assert_eq!(error_code(&call(&server, "sendrawtransaction", json!([tx.to_bytes().to_hex()]))), VERIFY_REJECTED);
+    }
+}
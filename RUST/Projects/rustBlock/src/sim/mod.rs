@@ -0,0 +1,421 @@
+// An in-process network of full nodes on a virtual clock, for testing fork
+// choice and block sync. Everything random in a run is drawn from one
+// seeded generator and events are handled one at a time in a fixed order,
+// so the same configuration always gives the same run. Nodes score and ban
+// each other with a `PeerManager`, so misbehaving nodes can be simulated
+// too.
+
+mod network;
+mod node;
+
+pub use self::network::{node_address, Message, NodeId};
+pub use self::node::{SimNode, COINBASE_AMOUNT};
+
+use blockchain::{Block, BlockHash, RejectReason, Transaction, TxId};
+use peers::PeerConfig;
+use util::hash::HashAlgorithm;
+use util::time::VirtualClock;
+use self::network::{Event, Network};
+use self::node::Outgoing;
+
+
+// SplitMix64, which is enough for drawing latencies and mining times and
+// needs no dependency that could change its output.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng {
+            state: seed,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // In [0, `n`); `n` must not be 0.
+    pub fn next_below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    // In [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Times are in milliseconds of virtual time.
+#[derive(Clone, Debug)]
+pub struct SimConfig {
+    nodes: usize,
+    seed: u64,
+    min_latency: u64,
+    max_latency: u64,
+    loss: f64,
+    block_interval: u64,
+    announce_interval: u64,
+    start_time: u64,
+    peer_config: PeerConfig,
+}
+
+impl SimConfig {
+    pub fn new(nodes: usize, seed: u64) -> SimConfig {
+        SimConfig {
+            nodes,
+            seed,
+            min_latency: 50,
+            max_latency: 200,
+            loss: 0.0,
+            block_interval: 10_000,
+            announce_interval: 30_000,
+            start_time: 1_507_000_000_000,
+            peer_config: PeerConfig::new(),
+        }
+    }
+
+    pub fn set_latency(&mut self, min: u64, max: u64) {
+        self.min_latency = min;
+        self.max_latency = max;
+    }
+
+    // The probability of any one message being lost.
+    pub fn set_loss(&mut self, loss: f64) {
+        self.loss = loss;
+    }
+
+    // The mean time between blocks of the whole network while mining.
+    pub fn set_block_interval(&mut self, interval: u64) {
+        self.block_interval = interval;
+    }
+
+    // How often each node announces its tip unprompted; 0 never.
+    pub fn set_announce_interval(&mut self, interval: u64) {
+        self.announce_interval = interval;
+    }
+
+    pub fn set_start_time(&mut self, millis: u64) {
+        self.start_time = millis;
+    }
+
+    // Every node's limits on, and punishment of, the others.
+    pub fn set_peer_config(&mut self, config: PeerConfig) {
+        self.peer_config = config;
+    }
+}
+
+// A block some node mined: when, by which node, and its hash.
+pub type MinedBlock = (u64, NodeId, BlockHash);
+
+pub struct Simulation {
+    config: SimConfig,
+    clock: VirtualClock,
+    rng: Rng,
+    network: Network,
+    nodes: Vec<SimNode>,
+    mining: bool,
+    mined: Vec<MinedBlock>,
+}
+
+impl Simulation {
+    // All nodes start from the same genesis block, connected to each other
+    // and mining.
+    pub fn new(config: SimConfig) -> Simulation {
+        assert!(config.nodes > 0, "a simulation needs at least one node");
+        assert!(config.nodes <= 1 << 16, "node addresses have room for 65536 nodes");
+        let clock = VirtualClock::new(config.start_time);
+
+        let mut coinbase = Transaction::new();
+        coinbase.set_timestamp(config.start_time / 1000);
+        coinbase.add_output(COINBASE_AMOUNT, b"genesis");
+        let mut genesis = Block::new();
+        genesis.set_timestamp(config.start_time / 1000);
+        genesis.add_transaction(coinbase);
+        genesis.update_merkle_root(HashAlgorithm::default());
+
+        let mut simulation = Simulation {
+            rng: Rng::new(config.seed),
+            network: Network::new(config.nodes, config.min_latency, config.max_latency, config.loss),
+            nodes: (0..config.nodes)
+                .map(|id| SimNode::new(id, config.nodes, genesis.clone(), clock.clone(), config.peer_config.clone()))
+                .collect(),
+            mining: true,
+            mined: Vec::new(),
+            clock,
+            config,
+        };
+        for id in 0..simulation.nodes.len() {
+            let time = simulation.next_mining_time();
+            simulation.network.schedule(time, Event::Mine(id));
+            if simulation.config.announce_interval > 0 {
+                let time = simulation.now() + simulation.config.announce_interval;
+                simulation.network.schedule(time, Event::Announce(id));
+            }
+        }
+        simulation
+    }
+
+    pub fn now(&self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    pub fn get_clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    pub fn get_node(&self, id: NodeId) -> &SimNode {
+        &self.nodes[id]
+    }
+
+    pub fn get_nodes(&self) -> &[SimNode] {
+        &self.nodes
+    }
+
+    pub fn get_mined(&self) -> &[MinedBlock] {
+        &self.mined
+    }
+
+    pub fn get_messages_sent(&self) -> u64 {
+        self.network.get_sent()
+    }
+
+    pub fn get_messages_dropped(&self) -> u64 {
+        self.network.get_dropped()
+    }
+
+    // Stopping mining lets the network settle, though nodes on different
+    // tips of equal height stay there until another block is mined.
+    pub fn set_mining(&mut self, mining: bool) {
+        self.mining = mining;
+    }
+
+    // Cuts the network into `groups`; the nodes in none of them form one
+    // more group.
+    pub fn partition(&mut self, groups: &[Vec<NodeId>]) {
+        self.network.partition(groups);
+    }
+
+    // Reconnects all nodes, which announce their tips to each other.
+    pub fn heal(&mut self) {
+        self.network.heal();
+        for id in 0..self.nodes.len() {
+            let outgoing = self.nodes[id].announce();
+            self.dispatch(id, None, vec![outgoing]);
+        }
+    }
+
+    // Handles every event due in the next `duration` milliseconds.
+    pub fn run_for(&mut self, duration: u64) {
+        let end = self.now() + duration;
+        while let Some((time, event)) = self.network.next_event(end) {
+            self.clock.set_millis(time);
+            self.handle(event);
+        }
+        self.clock.set_millis(end);
+    }
+
+    // Mines a block on `node` now, whether or not mining is on.
+    pub fn mine_block(&mut self, node: NodeId) -> BlockHash {
+        let block = self.nodes[node].mine();
+        let hash = block.get_hash(self.nodes[node].get_chain().get_hash_algorithm());
+        self.mined.push((self.now(), node, hash));
+        self.dispatch(node, None, vec![Outgoing::Relay(Message::Block(block))]);
+        hash
+    }
+
+    pub fn submit_transaction(&mut self, node: NodeId, tx: Transaction) -> Result<TxId, RejectReason> {
+        let txid = self.nodes[node].submit(tx.clone())?;
+        self.dispatch(node, None, vec![Outgoing::Relay(Message::Transaction(tx))]);
+        Ok(txid)
+    }
+
+    // Sends `message` from `from` to `to` over the network, whatever the
+    // state of `from`, as a misbehaving node would.
+    pub fn send_message(&mut self, from: NodeId, to: NodeId, message: Message) {
+        self.dispatch(from, None, vec![Outgoing::To(to, message)]);
+    }
+
+    pub fn get_tips(&self) -> Vec<BlockHash> {
+        self.nodes.iter().map(|node| node.get_tip()).collect()
+    }
+
+    pub fn is_converged(&self) -> bool {
+        let tips = self.get_tips();
+        tips.iter().all(|tip| *tip == tips[0])
+    }
+
+    // Panics, listing each node's height and tip, unless all nodes have the
+    // same tip, which is returned.
+    pub fn assert_converged(&self) -> BlockHash {
+        if !self.is_converged() {
+            let nodes: Vec<String> = self.nodes.iter()
+                .map(|node| format!("  node {}: height {} tip {}", node.get_id(), node.get_chain().len() - 1, node.get_tip()))
+                .collect();
+            panic!("nodes did not converge at {} ms:\n{}", self.now(), nodes.join("\n"));
+        }
+        self.get_tips()[0]
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Deliver { from, to, message } => {
+                let outgoing = self.nodes[to].handle(from, message);
+                self.dispatch(to, Some(from), outgoing);
+            },
+            Event::Mine(id) => {
+                if self.mining {
+                    self.mine_block(id);
+                }
+                let time = self.next_mining_time();
+                self.network.schedule(time, Event::Mine(id));
+            },
+            Event::Announce(id) => {
+                let outgoing = self.nodes[id].announce();
+                self.dispatch(id, None, vec![outgoing]);
+                let time = self.now() + self.config.announce_interval;
+                self.network.schedule(time, Event::Announce(id));
+            },
+        }
+    }
+
+    // Nodes send nothing to the peers they have banned.
+    fn dispatch(&mut self, from: NodeId, source: Option<NodeId>, outgoing: Vec<Outgoing>) {
+        let now = self.now();
+        for out in outgoing {
+            match out {
+                Outgoing::To(to, message) => {
+                    if !self.nodes[from].is_banned(to) {
+                        self.network.send(&mut self.rng, now, from, to, message);
+                    }
+                },
+                Outgoing::Relay(message) => {
+                    let peers: Vec<NodeId> = (0..self.nodes.len())
+                        .filter(|&to| to != from && Some(to) != source && !self.nodes[from].is_banned(to))
+                        .collect();
+                    for to in peers {
+                        self.network.send(&mut self.rng, now, from, to, message.clone());
+                    }
+                },
+            }
+        }
+    }
+
+    // Each node finds blocks as a Poisson process, so the whole network
+    // finds one every `block_interval` on average.
+    fn next_mining_time(&mut self) -> u64 {
+        let mean = (self.config.block_interval * self.nodes.len() as u64) as f64;
+        let wait = -(1.0 - self.rng.next_f64()).ln() * mean;
+        self.now() + wait as u64 + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::OutPoint;
+    use peers::Misbehavior;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    fn run(seed: u64) -> Simulation {
+        let mut config = SimConfig::new(5, seed);
+        config.set_loss(0.05);
+        let mut sim = Simulation::new(config);
+        sim.run_for(120_000);
+        sim.set_mining(false);
+        sim.run_for(60_000);
+        sim
+    }
+
+    // Mining stopped, a last block breaks any tie between equal tips.
+    fn settle(sim: &mut Simulation) -> BlockHash {
+        sim.set_mining(false);
+        sim.run_for(60_000);
+        sim.mine_block(0);
+        sim.run_for(60_000);
+        sim.assert_converged()
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let (a, b) = (run(7), run(7));
+        assert!(a.get_mined().len() > 5);
+        assert_eq!(a.get_mined(), b.get_mined());
+        assert_eq!(a.get_tips(), b.get_tips());
+        assert_eq!(a.get_messages_dropped(), b.get_messages_dropped());
+        assert!(a.get_messages_dropped() > 0);
+        assert_ne!(a.get_mined(), run(8).get_mined());
+    }
+
+    #[test]
+    fn converges_after_partition_heals() {
+        let mut config = SimConfig::new(6, 42);
+        config.set_latency(20, 500);
+        config.set_loss(0.1);
+        let mut sim = Simulation::new(config);
+
+        // A transaction spending the genesis coinbase reaches every node.
+        let genesis = sim.get_node(0).get_chain().get_block(0).unwrap().clone();
+        let mut tx = Transaction::new();
+        tx.add_input(OutPoint::new(genesis.get_transactions()[0].get_hash(ALGORITHM), 0));
+        tx.add_output(COINBASE_AMOUNT - 10, b"payee");
+        let txid = sim.submit_transaction(0, tx).unwrap();
+        sim.run_for(60_000);
+
+        sim.partition(&[vec![0, 1, 2], vec![3, 4, 5]]);
+        sim.run_for(300_000);
+        assert!(!sim.is_converged());
+        let sides = (sim.get_node(0).get_tip(), sim.get_node(3).get_tip());
+        assert_ne!(sides.0, sides.1);
+
+        sim.heal();
+        sim.run_for(120_000);
+        let tip = settle(&mut sim);
+        assert!(sim.get_nodes().iter().any(|node| node.get_reorgs() > 0));
+        for node in sim.get_nodes() {
+            assert_eq!(node.get_tip(), tip);
+            assert!(node.get_chain().find_transaction(&txid).is_some());
+            assert!(node.get_mempool().is_empty());
+        }
+    }
+
+    #[test]
+    fn bans_misbehaving_nodes() {
+        let mut sim = Simulation::new(SimConfig::new(4, 3));
+        sim.set_mining(false);
+        let genesis = sim.get_node(0).get_chain().get_block(0).unwrap().clone();
+
+        // Node 3 sends node 0 blocks that do not link up, then a block with
+        // a wrong Merkle root, which gets it banned.
+        let mut stray = Block::new();
+        stray.set_previous(BlockHash::from_bytes([9; 32]));
+        stray.update_merkle_root(ALGORITHM);
+        sim.send_message(3, 0, Message::Blocks(vec![stray.clone()]));
+        sim.run_for(1_000);
+        assert_eq!(sim.get_node(0).get_peers().get_host(&node_address(3).ip()).unwrap().get_score(), Misbehavior::BadPrevious.get_score());
+        assert!(!sim.get_node(0).is_banned(3));
+
+        let mut invalid = Block::new();
+        invalid.set_previous(genesis.get_hash(ALGORITHM));
+        invalid.add_transaction(Transaction::new());
+        sim.send_message(3, 0, Message::Block(invalid));
+        sim.run_for(1_000);
+        assert!(sim.get_node(0).is_banned(3));
+        assert!(!sim.get_node(1).is_banned(3));
+
+        // Node 0 neither hears from node 3 nor relays to it, but still
+        // follows its blocks through the other nodes.
+        let mined = sim.mine_block(3);
+        sim.run_for(5_000);
+        assert_eq!(sim.assert_converged(), mined);
+        sim.mine_block(0);
+        sim.run_for(5_000);
+        sim.assert_converged();
+    }
+}
@@ -0,0 +1,169 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+
+use blockchain::{Block, BlockHash, Encodable, Transaction};
+use sim::Rng;
+use util::hash::HASH256_BYTES;
+
+
+pub type NodeId = usize;
+
+// The address other nodes know a node by, distinct for the first 65536.
+pub fn node_address(id: NodeId) -> SocketAddr {
+    SocketAddr::from(([10, 0, (id >> 8) as u8, id as u8], 8333))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // A block the sender has just connected to its tip.
+    Block(Block),
+    // Hashes of the sender's chain from the tip back, ever further apart;
+    // the receiver answers with its blocks after the highest one it has.
+    GetBlocks(Vec<BlockHash>),
+    Blocks(Vec<Block>),
+    Transaction(Transaction),
+}
+
+impl Message {
+    // The size of the message's contents in their binary encoding.
+    pub fn get_size(&self) -> usize {
+        match *self {
+            Message::Block(ref block) => block.to_bytes().len(),
+            Message::GetBlocks(ref locator) => locator.len() * HASH256_BYTES,
+            Message::Blocks(ref blocks) => blocks.iter().map(|b| b.to_bytes().len()).sum(),
+            Message::Transaction(ref tx) => tx.to_bytes().len(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Deliver { from: NodeId, to: NodeId, message: Message },
+    Mine(NodeId),
+    // A node announces its tip to its peers again, which lets the network
+    // recover from lost messages and healed partitions.
+    Announce(NodeId),
+}
+
+// Events due at the same time run in the order they were scheduled, so a
+// run depends on nothing but the seed.
+struct Scheduled {
+    time: u64,
+    seq: u64,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Scheduled) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Scheduled) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed, as `BinaryHeap` pops the greatest.
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Scheduled) -> Ordering {
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+// Links every pair of nodes, with a latency drawn uniformly from
+// [`min_latency`, `max_latency`] milliseconds per message and a `loss`
+// probability of dropping it. A partition splits the nodes into groups that
+// cannot reach each other; messages in flight across it when it starts are
+// lost too.
+pub struct Network {
+    queue: BinaryHeap<Scheduled>,
+    seq: u64,
+    min_latency: u64,
+    max_latency: u64,
+    loss: f64,
+    groups: Vec<usize>,
+    sent: u64,
+    dropped: u64,
+}
+
+impl Network {
+    pub fn new(nodes: usize, min_latency: u64, max_latency: u64, loss: f64) -> Network {
+        Network {
+            queue: BinaryHeap::new(),
+            seq: 0,
+            min_latency,
+            max_latency: max_latency.max(min_latency),
+            loss,
+            groups: vec![0; nodes],
+            sent: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn schedule(&mut self, time: u64, event: Event) {
+        self.seq += 1;
+        self.queue.push(Scheduled { time, seq: self.seq, event });
+    }
+
+    pub fn send(&mut self, rng: &mut Rng, now: u64, from: NodeId, to: NodeId, message: Message) {
+        self.sent += 1;
+        if rng.next_f64() < self.loss {
+            self.dropped += 1;
+            return;
+        }
+        let latency = self.min_latency + rng.next_below(self.max_latency - self.min_latency + 1);
+        self.schedule(now + latency, Event::Deliver { from, to, message });
+    }
+
+    // The next event due no later than `end`, with its time.
+    pub fn next_event(&mut self, end: u64) -> Option<(u64, Event)> {
+        loop {
+            if self.queue.peek()?.time > end {
+                return None;
+            }
+            let Scheduled { time, event, .. } = self.queue.pop().unwrap();
+            if let Event::Deliver { from, to, .. } = event {
+                if !self.is_reachable(from, to) {
+                    self.dropped += 1;
+                    continue;
+                }
+            }
+            return Some((time, event));
+        }
+    }
+
+    // Nodes not in any of `groups` form one more group.
+    pub fn partition(&mut self, groups: &[Vec<NodeId>]) {
+        for group in self.groups.iter_mut() {
+            *group = groups.len();
+        }
+        for (i, nodes) in groups.iter().enumerate() {
+            for &node in nodes {
+                self.groups[node] = i;
+            }
+        }
+    }
+
+    pub fn heal(&mut self) {
+        for group in self.groups.iter_mut() {
+            *group = 0;
+        }
+    }
+
+    pub fn is_reachable(&self, from: NodeId, to: NodeId) -> bool {
+        self.groups[from] == self.groups[to]
+    }
+
+    pub fn get_sent(&self) -> u64 {
+        self.sent
+    }
+
+    pub fn get_dropped(&self) -> u64 {
+        self.dropped
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use blockchain::{Block, BlockChain, BlockHash, ChainError, Mempool, RejectReason, Transaction, TxId};
+use peers::{Misbehavior, PeerConfig, PeerManager};
+use sim::network::{node_address, Message, NodeId};
+use util::time::{Clock, VirtualClock};
+
+
+pub const COINBASE_AMOUNT: u64 = 50;
+
+pub enum Outgoing {
+    To(NodeId, Message),
+    // To every peer but the one the message being handled came from.
+    Relay(Message),
+}
+
+// A full node of the simulation: a chain that follows the longest branch it
+// hears of, a mempool, and a peer manager that bans the nodes sending it
+// invalid or unlinked blocks, all on the simulation's clock.
+pub struct SimNode {
+    id: NodeId,
+    chain: BlockChain,
+    mempool: Mempool,
+    peers: PeerManager,
+    clock: VirtualClock,
+    reorgs: usize,
+}
+
+impl SimNode {
+    pub fn new(id: NodeId, nodes: usize, genesis: Block, clock: VirtualClock, peer_config: PeerConfig) -> SimNode {
+        let mut chain = BlockChain::new();
+        chain.reorganize(vec![genesis]).unwrap();
+        let mut mempool = Mempool::new();
+        mempool.set_clock(Arc::new(clock.clone()));
+        let mut peers = PeerManager::with_clock(peer_config, Arc::new(clock.clone()));
+        for other in (0..nodes).filter(|&other| other != id) {
+            peers.connected(node_address(other)).unwrap();
+        }
+        SimNode {
+            id,
+            chain,
+            mempool,
+            peers,
+            clock,
+            reorgs: 0,
+        }
+    }
+
+    pub fn get_id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn get_chain(&self) -> &BlockChain {
+        &self.chain
+    }
+
+    pub fn get_mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    pub fn get_peers(&self) -> &PeerManager {
+        &self.peers
+    }
+
+    pub fn is_banned(&self, node: NodeId) -> bool {
+        self.peers.is_banned(&node_address(node))
+    }
+
+    pub fn get_tip(&self) -> BlockHash {
+        self.chain.get_best_hash().unwrap()
+    }
+
+    // Times the node switched branches, disconnecting blocks.
+    pub fn get_reorgs(&self) -> usize {
+        self.reorgs
+    }
+
+    // A block on the tip paying a coinbase to this node, with the mempool's
+    // transactions in txid order. It is connected before it is returned.
+    pub fn mine(&mut self) -> Block {
+        let algorithm = self.chain.get_hash_algorithm();
+        let height = self.chain.len();
+        let tip = self.chain.get_header(height - 1).unwrap();
+        let time = self.clock.now().max(tip.get_timestamp());
+
+        let mut coinbase = Transaction::new();
+        coinbase.set_timestamp(time);
+        coinbase.add_output(COINBASE_AMOUNT, format!("node{}/{}", self.id, height).as_bytes());
+        let mut transactions: Vec<_> = self.mempool.iter().map(|e| e.get_transaction().clone()).collect();
+        transactions.sort_by_key(|tx| tx.get_hash(algorithm));
+
+        let mut block = Block::new();
+        block.set_previous(tip.get_hash(algorithm));
+        block.set_timestamp(time);
+        block.add_transaction(coinbase);
+        for tx in transactions {
+            block.add_transaction(tx);
+        }
+        block.update_merkle_root(algorithm);
+        if let Err(e) = self.connect(vec![block.clone()]) {
+            panic!("node {}: mined block rejected: {}", self.id, e);
+        }
+        block
+    }
+
+    pub fn submit(&mut self, tx: Transaction) -> Result<TxId, RejectReason> {
+        self.mempool.accept(tx, &self.chain)
+    }
+
+    pub fn announce(&self) -> Outgoing {
+        let tip = self.chain.get_block(self.chain.len() - 1).unwrap();
+        Outgoing::Relay(Message::Block(tip.clone()))
+    }
+
+    pub fn handle(&mut self, from: NodeId, message: Message) -> Vec<Outgoing> {
+        let algorithm = self.chain.get_hash_algorithm();
+        let peer = node_address(from);
+        if self.peers.check_message(&peer, message.get_size()).is_err() {
+            return Vec::new();
+        }
+        match message {
+            Message::Block(block) => {
+                if self.chain.get_height(&block.get_hash(algorithm)).is_some() {
+                    return Vec::new();
+                }
+                if Some(block.get_previous()) != self.chain.get_best_hash() {
+                    return vec![Outgoing::To(from, Message::GetBlocks(self.locator()))];
+                }
+                match self.connect(vec![block.clone()]) {
+                    Ok(()) => vec![Outgoing::Relay(Message::Block(block))],
+                    Err(e) => self.punish(from, &e),
+                }
+            },
+            Message::GetBlocks(locator) => {
+                let start = locator.iter().filter_map(|hash| self.chain.get_height(hash)).next().map_or(0, |h| h + 1);
+                let blocks: Vec<Block> = (start..self.chain.len())
+                    .map(|height| self.chain.get_block(height).unwrap().clone())
+                    .collect();
+                if blocks.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Outgoing::To(from, Message::Blocks(blocks))]
+                }
+            },
+            Message::Blocks(blocks) => {
+                // Skip the blocks this node already has, up to the fork. The
+                // rest must link to each other and to the active chain.
+                let known = blocks.iter().take_while(|b| self.chain.get_height(&b.get_hash(algorithm)).is_some()).count();
+                let branch = blocks[known..].to_vec();
+                let linked = branch.windows(2).all(|pair| pair[1].get_previous() == pair[0].get_hash(algorithm));
+                let tip = match branch.last() {
+                    Some(tip) => tip.clone(),
+                    None => return Vec::new(),
+                };
+                if !linked || self.chain.get_height(&branch[0].get_previous()).is_none() {
+                    self.peers.misbehaving(&peer, Misbehavior::BadPrevious);
+                    return Vec::new();
+                }
+                match self.connect(branch) {
+                    Ok(()) => vec![Outgoing::Relay(Message::Block(tip))],
+                    Err(e) => self.punish(from, &e),
+                }
+            },
+            Message::Transaction(tx) => match self.submit(tx.clone()) {
+                Ok(_) => vec![Outgoing::Relay(Message::Transaction(tx))],
+                Err(_) => Vec::new(),
+            },
+        }
+    }
+
+    // A branch that is no longer than the active chain is no fault of the
+    // peer that sent it; a block breaking the rules is.
+    fn punish(&mut self, from: NodeId, e: &ChainError) -> Vec<Outgoing> {
+        match *e {
+            ChainError::BadBranch(_) => {},
+            _ => {
+                self.peers.misbehaving(&node_address(from), Misbehavior::InvalidBlock);
+            },
+        }
+        Vec::new()
+    }
+
+    // Hashes at the tip and the nine heights below it, then twice as far
+    // back each time, ending with the genesis block.
+    fn locator(&self) -> Vec<BlockHash> {
+        let mut hashes = Vec::new();
+        let mut height = self.chain.len() - 1;
+        let mut step = 1;
+        loop {
+            hashes.push(self.chain.get_header(height).unwrap().get_hash(self.chain.get_hash_algorithm()));
+            if height == 0 {
+                return hashes;
+            }
+            if hashes.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+    }
+
+    // Switches to `blocks` if they extend or outgrow the active chain, and
+    // brings the mempool up to date.
+    fn connect(&mut self, blocks: Vec<Block>) -> Result<(), ChainError> {
+        let disconnected = self.chain.reorganize(blocks.clone())?;
+        if !disconnected.is_empty() {
+            self.reorgs += 1;
+            self.mempool.disconnect_block(&self.chain);
+        }
+        for block in &blocks {
+            self.mempool.connect_block(self.chain.get_hash_algorithm(), block);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use blockchain::{filter_header, BlockFilter, BlockHeader, StateProof, TxId};
+use spv::{FullNode, HeaderChain, MerkleProof};
+use util::hash::{HashAlgorithm, HASH256_BYTES};
+use util::hex::ToHex;
+
+
+const HEADERS_PER_REQUEST: usize = 2000;
+
+// Follows the header chain of a full node and tracks confirmations of watched
+// transactions by checking the Merkle branches the node serves against the
+// headers. Blocks and their transactions are never downloaded.
+pub struct LightClient<N: FullNode> {
+    node: N,
+    headers: HeaderChain,
+    // Watched transaction to the height it was proven at, if any.
+    watched: HashMap<TxId, Option<usize>>,
+}
+
+impl<N: FullNode> LightClient<N> {
+    // `algorithm` is the hash function of the node's chain.
+    pub fn new(node: N, algorithm: HashAlgorithm) -> LightClient<N> {
+        LightClient {
+            node,
+            headers: HeaderChain::new(algorithm),
+            watched: HashMap::new(),
+        }
+    }
+
+    pub fn get_headers(&self) -> &HeaderChain {
+        &self.headers
+    }
+
+    pub fn watch(&mut self, txid: &TxId) {
+        self.watched.entry(*txid).or_insert(None);
+    }
+
+    // `None` for transactions that are not watched, zero while unconfirmed.
+    pub fn get_confirmations(&self, txid: &TxId) -> Option<usize> {
+        self.watched.get(txid).map(|proven| match *proven {
+            Some(height) => self.headers.len() - height,
+            None => 0,
+        })
+    }
+
+    // Catches up with the node's header chain, following it across reorgs,
+    // and then asks for proofs of the watched transactions not yet proven.
+    // The local headers are only replaced once the node's branch links up,
+    // so a failed sync leaves them as they were.
+    pub fn sync(&mut self) -> Result<(), String> {
+        let fork_height = self.find_fork()?;
+        let branch = self.fetch_branch(fork_height)?;
+        if fork_height < self.headers.len() {
+            self.headers.truncate(fork_height);
+            for proven in self.watched.values_mut() {
+                if proven.is_some_and(|height| height >= fork_height) {
+                    *proven = None;
+                }
+            }
+        }
+        for header in branch {
+            self.headers.append(header)?;
+        }
+
+        let pending: Vec<TxId> = self.watched.iter()
+            .filter(|&(_, proven)| proven.is_none())
+            .map(|(txid, _)| *txid)
+            .collect();
+        for txid in pending {
+            if let Some(proof) = self.node.get_merkle_proof(&txid)? {
+                let height = self.verify_proof(&txid, &proof)?;
+                self.watched.insert(txid, Some(height));
+            }
+        }
+        Ok(())
+    }
+
+    // The height of the first local header the node does not share, found
+    // walking down a batch of headers at a time.
+    fn find_fork(&self) -> Result<usize, String> {
+        let mut height = self.headers.len();
+        while height > 0 {
+            let start = height.saturating_sub(HEADERS_PER_REQUEST);
+            let remote = self.node.get_headers(start, height - start)?;
+            let shared = (start..height).rev().find(|&h| {
+                remote.get(h - start)
+                    .is_some_and(|header| Some(&header.get_hash(self.headers.get_algorithm())) == self.headers.get_hash(h))
+            });
+            if let Some(shared) = shared {
+                return Ok(shared + 1);
+            }
+            height = start;
+        }
+        Ok(0)
+    }
+
+    // The node's headers from `fork_height` to its tip, checked to link to
+    // each other and to the local header below `fork_height`.
+    fn fetch_branch(&self, fork_height: usize) -> Result<Vec<BlockHeader>, String> {
+        let algorithm = self.headers.get_algorithm();
+        let mut previous = fork_height.checked_sub(1)
+            .map(|height| *self.headers.get_hash(height).unwrap())
+            .unwrap_or_default();
+        let mut branch = Vec::new();
+        loop {
+            let batch = self.node.get_headers(fork_height + branch.len(), HEADERS_PER_REQUEST)?;
+            if batch.is_empty() {
+                return Ok(branch);
+            }
+            for header in batch {
+                if header.get_previous() != previous {
+                    return Err(format!("header {} expected previous '{}'; actual '{}'",
+                                       fork_height + branch.len(), previous, header.get_previous()));
+                }
+                previous = header.get_hash(algorithm);
+                branch.push(header);
+            }
+        }
+    }
+
+    // Returns the height of the block the proof places `txid` in.
+    pub fn verify_proof(&self, txid: &TxId, proof: &MerkleProof) -> Result<usize, String> {
+        let height = self.headers.get_height(&proof.block_hash)
+            .ok_or_else(|| format!("proof for tx {} references unknown block {}",
+                                   txid, proof.block_hash))?;
+
+        let header = self.headers.get(height).unwrap();
+        if !proof.branch.verify(self.headers.get_algorithm(), txid.as_bytes(), header.get_merkle_root()) {
+            return Err(format!("invalid merkle branch for tx {} in block {}",
+                               txid, proof.block_hash));
+        }
+        Ok(height)
+    }
+
+    // The heights from `start` up to the synced tip of the blocks whose
+    // filters match any of `items` (output scripts or encoded outpoints).
+    // Each filter header served must commit to its filter and the one
+    // before it; the first is checked against the header below `start`.
+    pub fn scan_filters(&self, items: &[Vec<u8>], start: usize) -> Result<Vec<usize>, String> {
+        let mut previous = match start.checked_sub(1) {
+            Some(height) => self.get_block_filter(height)?.1,
+            None => [0u8; HASH256_BYTES],
+        };
+
+        let mut matched = Vec::new();
+        for height in start..self.headers.len() {
+            let (filter, header) = self.get_block_filter(height)?;
+            if filter_header(self.headers.get_algorithm(), &filter, &previous) != header {
+                return Err(format!("filter header of block {} does not commit to its filter",
+                                   self.headers.get_hash(height).unwrap()));
+            }
+            if filter.match_any(self.headers.get_hash(height).unwrap(), items) {
+                matched.push(height);
+            }
+            previous = header;
+        }
+        Ok(matched)
+    }
+
+    fn get_block_filter(&self, height: usize) -> Result<(BlockFilter, [u8; HASH256_BYTES]), String> {
+        let hash = self.headers.get_hash(height)
+            .ok_or_else(|| format!("height {} is past the synced tip", height))?;
+        self.node.get_block_filter(hash)?
+            .ok_or_else(|| format!("node has no filter for block {}", hash))
+    }
+
+    // Checks a state proof against the synced header at its height.
+    pub fn verify_state_proof(&self, proof: &StateProof) -> Result<(), String> {
+        let header = self.headers.get(proof.height)
+            .ok_or_else(|| format!("state proof references height {} past the tip", proof.height))?;
+        if !proof.verify(self.headers.get_algorithm(), header) {
+            return Err(format!("invalid state proof for key {} at height {}", proof.key.to_hex(), proof.height));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    use blockchain::{BlockHash, MerkleBranch};
+    use blockchain::sample;
+    use spv::MerkleProof;
+
+    const ALGORITHM: HashAlgorithm = HashAlgorithm::Blake2s;
+
+    #[test]
+    fn follows_a_full_node() {
+        let mut chain = sample::chain();
+        chain.enable_filter_index();
+
+        // The last block has two transactions, so its branches are one hash
+        // long; the others prove a lone coinbase with an empty branch.
+        let first = chain.get_block(0).unwrap().get_tx_hashes(ALGORITHM)[0];
+        let coinbase = chain.get_block(2).unwrap().get_tx_hashes(ALGORITHM)[0];
+        let spend = chain.get_block(2).unwrap().get_tx_hashes(ALGORITHM)[1];
+        let block_hash = chain.get_block(2).unwrap().get_hash(ALGORITHM);
+
+        let mut client = LightClient::new(chain, ALGORITHM);
+        for txid in &[first, coinbase, spend] {
+            client.watch(txid);
+        }
+        client.sync().unwrap();
+        assert_eq!(client.get_headers().len(), 3);
+        assert_eq!(client.get_confirmations(&first), Some(3));
+        assert_eq!(client.get_confirmations(&coinbase), Some(1));
+        assert_eq!(client.get_confirmations(&spend), Some(1));
+
+        let proof = |index, hashes| MerkleProof { block_hash, branch: MerkleBranch::new(index, hashes) };
+        let branch = client.node.get_merkle_proof(&spend).unwrap().1;
+        assert!(client.verify_proof(&spend, &proof(1, branch.get_hashes().to_vec())).is_ok());
+        assert!(client.verify_proof(&spend, &proof(0, branch.get_hashes().to_vec())).is_err());
+        assert!(client.verify_proof(&coinbase, &proof(1, branch.get_hashes().to_vec())).is_err());
+        // A lone leaf is not the root of a one-leaf tree.
+        assert!(client.verify_proof(&coinbase, &proof(0, Vec::new())).is_err());
+
+        let mut unknown = proof(1, branch.get_hashes().to_vec());
+        unknown.block_hash = "00".repeat(32).parse().unwrap();
+        assert!(client.verify_proof(&spend, &unknown).is_err());
+
+        let alice = vec![0xa1, 0x1c, 0xe0];
+        let bob = vec![0xb0, 0xb0, 0x00];
+        assert_eq!(client.scan_filters(&[vec![0xa1, 0x1c, 0xe0]], 0), Ok(vec![0, 2]));
+        assert_eq!(client.scan_filters(&[vec![0xb0, 0xb0, 0x00]], 0), Ok(vec![1, 2]));
+        assert_eq!(client.scan_filters(&[alice, bob], 2), Ok(vec![2]));
+        assert_eq!(client.scan_filters(&[b"carol".to_vec()], 0), Ok(vec![]));
+        assert!(client.scan_filters(&[], 4).is_err());
+    }
+
+    #[test]
+    fn needs_filters_to_scan() {
+        let mut client = LightClient::new(sample::chain(), ALGORITHM);
+        client.sync().unwrap();
+        assert!(client.scan_filters(&[vec![0xa1, 0x1c, 0xe0]], 0).is_err());
+    }
+
+    // Serves only headers, counting the requests for them.
+    struct Headers {
+        headers: Vec<BlockHeader>,
+        requests: Cell<usize>,
+    }
+
+    impl FullNode for Headers {
+        fn get_headers(&self, start: usize, count: usize) -> Result<Vec<BlockHeader>, String> {
+            self.requests.set(self.requests.get() + 1);
+            Ok(self.headers.iter().skip(start).take(count).cloned().collect())
+        }
+
+        fn get_merkle_proof(&self, _txid: &TxId) -> Result<Option<MerkleProof>, String> {
+            Ok(None)
+        }
+
+        fn get_block_filter(&self, _block_hash: &BlockHash) -> Result<Option<(BlockFilter, [u8; HASH256_BYTES])>, String> {
+            Err("no filters".to_string())
+        }
+    }
+
+    // `count` headers on top of `previous`, stamped with `seed` so that
+    // branches differ.
+    fn branch(previous: BlockHash, count: usize, seed: u64) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for i in 0..count {
+            let previous = headers.last().map_or(previous, |h| h.get_hash(ALGORITHM));
+            headers.push(BlockHeader::new(1, seed * 1_000_000 + i as u64, previous, [0; 32], [0; 32]));
+        }
+        headers
+    }
+
+    #[test]
+    fn deep_reorgs_take_few_requests() {
+        let node = Headers { headers: branch(BlockHash::default(), 5000, 1), requests: Cell::new(0) };
+        let mut client = LightClient::new(node, ALGORITHM);
+        client.sync().unwrap();
+        assert_eq!(client.get_headers().len(), 5000);
+
+        // Three requests walk down to the fork, four fetch the new branch.
+        let fork = *client.get_headers().get_hash(9).unwrap();
+        client.node.headers.truncate(10);
+        client.node.headers.extend(branch(fork, 4991, 2));
+        client.node.requests.set(0);
+        client.sync().unwrap();
+        assert_eq!(client.get_headers().len(), 5001);
+        assert_eq!(client.get_headers().get(5000), client.node.headers.last());
+        assert_eq!(client.node.requests.get(), 7);
+
+        // A branch that breaks off partway is not taken.
+        let tip = *client.get_headers().get_hash(5000).unwrap();
+        client.node.headers.truncate(10);
+        client.node.headers.extend(branch(fork, 5000, 3));
+        client.node.headers[4000] = branch(BlockHash::default(), 1, 4)[0];
+        assert!(client.sync().unwrap_err().contains("expected previous"));
+        assert_eq!(client.get_headers().len(), 5001);
+        assert_eq!(client.get_headers().get_hash(5000), Some(&tip));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use blockchain::{BlockHash, BlockHeader};
+use util::hash::HashAlgorithm;
+
+
+// The header-only counterpart of `BlockChain`, for a chain hashing with
+// `algorithm`.
+pub struct HeaderChain {
+    algorithm: HashAlgorithm,
+    headers: Vec<BlockHeader>,
+    hashes: Vec<BlockHash>,
+    heights: HashMap<BlockHash, usize>,
+}
+
+impl HeaderChain {
+    pub fn new(algorithm: HashAlgorithm) -> HeaderChain {
+        HeaderChain {
+            algorithm,
+            headers: Vec::new(),
+            hashes: Vec::new(),
+            heights: HashMap::new(),
+        }
+    }
+
+    pub fn get_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, height: usize) -> Option<&BlockHeader> {
+        self.headers.get(height)
+    }
+
+    pub fn get_hash(&self, height: usize) -> Option<&BlockHash> {
+        self.hashes.get(height)
+    }
+
+    pub fn get_height(&self, hash: &BlockHash) -> Option<usize> {
+        self.heights.get(hash).cloned()
+    }
+
+    pub fn append(&mut self, header: BlockHeader) -> Result<(), String> {
+        let expected = self.hashes.last().cloned().unwrap_or_default();
+        if header.get_previous() != expected {
+            return Err(format!("append expected previous '{}'; actual '{}'",
+                               expected, header.get_previous()));
+        }
+
+        let hash = header.get_hash(self.algorithm);
+        self.heights.insert(hash, self.headers.len());
+        self.hashes.push(hash);
+        self.headers.push(header);
+        Ok(())
+    }
+
+    // Drops the headers at `height` and above.
+    pub fn truncate(&mut self, height: usize) {
+        for hash in self.hashes.drain(height..) {
+            self.heights.remove(&hash);
+        }
+        self.headers.truncate(height);
+    }
+}
@@ -0,0 +1,8 @@
+
+mod client;
+mod headers;
+mod node;
+
+pub use self::client::LightClient;
+pub use self::headers::HeaderChain;
+pub use self::node::{FullNode, MerkleProof};
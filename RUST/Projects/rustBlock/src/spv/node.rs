@@ -0,0 +1,112 @@
+extern crate serde_json;
+
+use self::serde_json::Value;
+
+use blockchain::{BlockChain, BlockFilter, BlockHash, BlockHeader, Decodable, MerkleBranch, TxId};
+use rpc::RpcClient;
+use rpc::error::INVALID_ADDRESS_OR_KEY;
+use util::hash::HASH256_BYTES;
+use util::hex::FromHex;
+
+
+pub struct MerkleProof {
+    pub block_hash: BlockHash,
+    pub branch: MerkleBranch,
+}
+
+// What a light client needs from a full node.
+pub trait FullNode {
+    // Up to `count` headers starting at `start`; fewer past the tip.
+    fn get_headers(&self, start: usize, count: usize) -> Result<Vec<BlockHeader>, String>;
+
+    // `None` while the transaction is not in the node's chain.
+    fn get_merkle_proof(&self, txid: &TxId) -> Result<Option<MerkleProof>, String>;
+
+    // The filter of a block in the node's chain and its filter header;
+    // `None` if the block is not in the chain. An error if the node keeps no
+    // filters.
+    fn get_block_filter(&self, block_hash: &BlockHash) -> Result<Option<(BlockFilter, [u8; HASH256_BYTES])>, String>;
+}
+
+impl FullNode for BlockChain {
+    fn get_headers(&self, start: usize, count: usize) -> Result<Vec<BlockHeader>, String> {
+        Ok((start..start.saturating_add(count))
+            .map_while(|height| self.get_header(height))
+            .collect())
+    }
+
+    fn get_merkle_proof(&self, txid: &TxId) -> Result<Option<MerkleProof>, String> {
+        Ok(BlockChain::get_merkle_proof(self, txid).map(|(height, branch)| MerkleProof {
+            block_hash: self.get_block(height).unwrap().get_hash(self.get_hash_algorithm()),
+            branch,
+        }))
+    }
+
+    fn get_block_filter(&self, block_hash: &BlockHash) -> Result<Option<(BlockFilter, [u8; HASH256_BYTES])>, String> {
+        let index = self.get_filter_index().ok_or("block filter index is not enabled")?;
+        Ok(self.get_height(block_hash)
+            .and_then(|height| index.get(height))
+            .map(|(filter, header)| (filter.clone(), *header)))
+    }
+}
+
+// Branch hashes are inner nodes of the tree, and filter headers are not block
+// hashes either, so both are read as plain hashes.
+fn parse_node_hash(value: &Value) -> Result<[u8; HASH256_BYTES], String> {
+    let v = value.as_str().ok_or("expected a hex hash")?
+        .from_hex().map_err(|e| e.to_string())?;
+    if v.len() != HASH256_BYTES {
+        return Err(format!("hash must be {} bytes", HASH256_BYTES));
+    }
+
+    let mut hash = [0u8; HASH256_BYTES];
+    hash.copy_from_slice(&v);
+    Ok(hash)
+}
+
+impl FullNode for RpcClient {
+    fn get_headers(&self, start: usize, count: usize) -> Result<Vec<BlockHeader>, String> {
+        let result = self.call("getheaders", json!([start, count])).map_err(|e| e.to_string())?;
+        let headers = result.as_array().ok_or("getheaders: expected an array")?;
+
+        headers.iter().map(|h| {
+            let raw = h.as_str().ok_or("getheaders: expected hex headers")?
+                .from_hex().map_err(|e| format!("getheaders: {}", e))?;
+            BlockHeader::from_bytes(&raw).map_err(|e| format!("getheaders: {}", e))
+        }).collect()
+    }
+
+    fn get_merkle_proof(&self, txid: &TxId) -> Result<Option<MerkleProof>, String> {
+        let result = match self.call("getmerkleproof", json!([txid.to_string()])) {
+            Ok(result) => result,
+            Err(ref e) if e.code == INVALID_ADDRESS_OR_KEY => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let block_hash: BlockHash = result["block_hash"].as_str().ok_or("getmerkleproof: expected a block hash")?
+            .parse().map_err(|e| format!("getmerkleproof: {}", e))?;
+        let index = result["index"].as_u64().ok_or("getmerkleproof: expected an index")?;
+        let hashes = result["branch"].as_array().ok_or("getmerkleproof: expected a branch")?
+            .iter().map(parse_node_hash).collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("getmerkleproof: {}", e))?;
+
+        Ok(Some(MerkleProof {
+            block_hash,
+            branch: MerkleBranch::new(index as u32, hashes),
+        }))
+    }
+
+    fn get_block_filter(&self, block_hash: &BlockHash) -> Result<Option<(BlockFilter, [u8; HASH256_BYTES])>, String> {
+        let result = match self.call("getblockfilter", json!([block_hash.to_string()])) {
+            Ok(result) => result,
+            Err(ref e) if e.code == INVALID_ADDRESS_OR_KEY => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let raw = result["filter"].as_str().ok_or("getblockfilter: expected a filter")?
+            .from_hex().map_err(|e| format!("getblockfilter: {}", e))?;
+        let filter = BlockFilter::from_bytes(&raw).map_err(|e| format!("getblockfilter: {}", e))?;
+        let header = parse_node_hash(&result["header"]).map_err(|e| format!("getblockfilter: {}", e))?;
+        Ok(Some((filter, header)))
+    }
+}
@@ -1,35 +1,146 @@
 extern crate blake2;
+extern crate blake3;
+extern crate sha2;
 
+use std::fmt;
 use std::io;
-use self::blake2::{Blake2s, Digest};
+use std::str::FromStr;
+
+use self::blake2::digest::consts::U32;
+use self::blake2::{Blake2b, Blake2s256, Digest};
+use self::sha2::Sha256;
 
 
 pub const HASH256_BYTES: usize = 32;
 
+// An incremental hash function with a 256 bit output.
+pub trait Hasher: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>, buf: &mut [u8]);
+}
+
+struct Blake2sHasher(Blake2s256);
+
+impl Hasher for Blake2sHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.finalize());
+    }
+}
+
+struct Blake2bHasher(Blake2b<U32>);
+
+impl Hasher for Blake2bHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0.finalize());
+    }
+}
+
+// SHA-256 applied twice, as in bitcoin.
+struct Sha256dHasher(Sha256);
+
+impl Hasher for Sha256dHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>, buf: &mut [u8]) {
+        buf.copy_from_slice(&Sha256::digest(self.0.finalize()));
+    }
+}
+
+struct Blake3Hasher(blake3::Hasher);
+
+impl Hasher for Blake3Hasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self: Box<Self>, buf: &mut [u8]) {
+        buf.copy_from_slice(self.0.finalize().as_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake2s,
+    Blake2b,
+    Sha256d,
+    Blake3,
+}
+
+const ALGORITHMS: [HashAlgorithm; 4] = [
+    HashAlgorithm::Blake2s,
+    HashAlgorithm::Blake2b,
+    HashAlgorithm::Sha256d,
+    HashAlgorithm::Blake3,
+];
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            HashAlgorithm::Blake2s => "blake2s",
+            HashAlgorithm::Blake2b => "blake2b",
+            HashAlgorithm::Sha256d => "sha256d",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn new_hasher(&self) -> Box<dyn Hasher> {
+        match *self {
+            HashAlgorithm::Blake2s => Box::new(Blake2sHasher(Blake2s256::new())),
+            HashAlgorithm::Blake2b => Box::new(Blake2bHasher(Blake2b::new())),
+            HashAlgorithm::Sha256d => Box::new(Sha256dHasher(Sha256::new())),
+            HashAlgorithm::Blake3 => Box::new(Blake3Hasher(blake3::Hasher::new())),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HashAlgorithm, String> {
+        ALGORITHMS.iter()
+            .find(|a| a.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("unknown hash algorithm '{}'", s))
+    }
+}
+
 pub struct Hash256 {
-    hasher: Blake2s,
+    hasher: Box<dyn Hasher>,
 }
 
 impl Hash256 {
-    pub fn new() -> Hash256 {
+    pub fn new(algorithm: HashAlgorithm) -> Hash256 {
         Hash256 {
-            hasher: Blake2s::default(),
+            hasher: algorithm.new_hasher(),
         }
     }
 
-    pub fn finalize(&mut self, buf: &mut [u8]) {
-        let result = self.hasher.result();
-        buf.copy_from_slice(&result)
-    }
-
-    pub fn reset(&mut self) {
-        self.hasher = Blake2s::default();
+    pub fn finalize(self, buf: &mut [u8]) {
+        self.hasher.finalize(buf)
     }
 }
 
 impl io::Write for Hash256 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.hasher.input(buf);
+        self.hasher.update(buf);
         Ok(buf.len())
     }
 
@@ -37,3 +148,72 @@ impl io::Write for Hash256 {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use util::hex::ToHex;
+
+    fn digest(algorithm: HashAlgorithm, data: &[u8]) -> String {
+        let mut hash = Hash256::new(algorithm);
+        hash.write_all(data).unwrap();
+        let mut buf = [0u8; HASH256_BYTES];
+        hash.finalize(&mut buf);
+        buf.to_hex()
+    }
+
+    fn check(algorithm: HashAlgorithm, empty: &str, abc: &str) {
+        assert_eq!(digest(algorithm, b""), empty);
+        assert_eq!(digest(algorithm, b"abc"), abc);
+    }
+
+    #[test]
+    fn blake2s_vectors() {
+        check(HashAlgorithm::Blake2s,
+              "69217a3079908094e11121d042354a7c1f55b6482ca1a51e1b250dfd1ed0eef9",
+              "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982");
+    }
+
+    #[test]
+    fn blake2b_vectors() {
+        check(HashAlgorithm::Blake2b,
+              "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8",
+              "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319");
+    }
+
+    #[test]
+    fn sha256d_vectors() {
+        check(HashAlgorithm::Sha256d,
+              "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456",
+              "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358");
+    }
+
+    #[test]
+    fn blake3_vectors() {
+        check(HashAlgorithm::Blake3,
+              "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+              "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+    }
+
+    #[test]
+    fn incremental_writes_match_one_shot() {
+        for &algorithm in &ALGORITHMS {
+            let mut hash = Hash256::new(algorithm);
+            hash.write_all(b"a").unwrap();
+            hash.write_all(b"bc").unwrap();
+            let mut buf = [0u8; HASH256_BYTES];
+            hash.finalize(&mut buf);
+            assert_eq!(buf.to_hex(), digest(algorithm, b"abc"));
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for &algorithm in &ALGORITHMS {
+            assert_eq!(algorithm.name().parse::<HashAlgorithm>(), Ok(algorithm));
+        }
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+}
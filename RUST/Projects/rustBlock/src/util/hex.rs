@@ -9,7 +9,7 @@ pub trait ToHex {
     fn to_hex(&self) -> String;
 }
 
-static CHARS: &'static[u8] = b"0123456789abcdef";
+static CHARS: &[u8] = b"0123456789abcdef";
 
 impl ToHex for [u8] {
     fn to_hex(&self) -> String {
@@ -26,6 +26,7 @@ impl ToHex for [u8] {
 }
 
 pub trait FromHex {
+    #[allow(clippy::wrong_self_convention)]
     fn from_hex(&self) -> Result<Vec<u8>, FromHexError>;
 }
 
@@ -56,7 +57,7 @@ impl error::Error for FromHexError {
 
 impl fmt::Display for FromHexError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(&self, f)
+        fmt::Debug::fmt(self, f)
     }
 }
 
@@ -71,9 +72,9 @@ impl FromHex for str {
             buf <<= 4;
 
             match byte {
-                b'A'...b'F' => buf |= byte - b'A' + 10,
-                b'a'...b'f' => buf |= byte - b'a' + 10,
-                b'0'...b'9' => buf |= byte - b'0',
+                b'A'..=b'F' => buf |= byte - b'A' + 10,
+                b'a'..=b'f' => buf |= byte - b'a' + 10,
+                b'0'..=b'9' => buf |= byte - b'0',
                 b' '|b'\r'|b'\n'|b'\t' => {
                     buf >>= 4;
                     continue
@@ -97,3 +98,52 @@ impl FromHex for str {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    #[test]
+    fn to_hex_is_lowercase() {
+        assert_eq!(b"".to_hex(), "");
+        assert_eq!([0x00u8, 0x1f, 0xa0, 0xff].to_hex(), "001fa0ff");
+    }
+
+    #[test]
+    fn from_hex_accepts_either_case_and_whitespace() {
+        assert_eq!("001FA0ff".from_hex().unwrap(), vec![0x00, 0x1f, 0xa0, 0xff]);
+        assert_eq!("00 1f\n a0\tff".from_hex().unwrap(), vec![0x00, 0x1f, 0xa0, 0xff]);
+    }
+
+    #[test]
+    fn from_hex_rejects_bad_input() {
+        match "0g".from_hex() {
+            Err(InvalidHexCharacter('g', 1)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match "abc".from_hex() {
+            Err(InvalidHexLength) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        // Multi-byte characters are reported whole.
+        match "0é".from_hex() {
+            Err(InvalidHexCharacter('é', 1)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn round_trips(bytes in vec(any::<u8>(), 0..64)) {
+            prop_assert_eq!(bytes.to_hex().from_hex().unwrap(), bytes.clone());
+            prop_assert_eq!(bytes.to_hex().to_uppercase().from_hex().unwrap(), bytes);
+        }
+
+        #[test]
+        fn from_hex_never_panics(s in ".*") {
+            let _ = s.from_hex();
+        }
+    }
+}
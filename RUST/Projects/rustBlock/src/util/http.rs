@@ -0,0 +1,319 @@
+// Minimal blocking HTTP/1.1 server: one thread per connection, up to
+// MAX_CONNECTIONS at once, one request per connection.
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+
+const MAX_LINE_BYTES: usize = 8 * 1024;
+const MAX_HEADER_LINES: usize = 100;
+const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+// Streamed responses hold their connection until the client leaves, so this
+// also bounds the event subscribers.
+const MAX_CONNECTIONS: usize = 128;
+// How long a client may keep a read or write waiting.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // The path without its query string.
+    pub fn route(&self) -> &str {
+        match self.path.find('?') {
+            Some(i) => &self.path[..i],
+            None => &self.path,
+        }
+    }
+
+    // The percent-decoded value of query parameter `name`.
+    pub fn query(&self, name: &str) -> Option<String> {
+        let query = match self.path.find('?') {
+            Some(i) => &self.path[i + 1..],
+            None => return None,
+        };
+        query.split('&')
+            .map(|pair| match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, ""),
+            })
+            .find(|&(k, _)| k == name)
+            .map(|(_, v)| percent_decode(v))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    // Written chunk by chunk after `body`, until it ends or the client goes
+    // away. The response then has no Content-Length.
+    pub stream: Option<Box<dyn Iterator<Item = Vec<u8>> + Send>>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Response {
+        Response {
+            status,
+            content_type,
+            body,
+            stream: None,
+        }
+    }
+
+    pub fn stream<I>(content_type: &'static str, chunks: I) -> Response
+        where I: Iterator<Item = Vec<u8>> + Send + 'static
+    {
+        Response {
+            stream: Some(Box::new(chunks)),
+            ..Response::new(200, content_type, Vec::new())
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Response {
+        Response::new(status, "text/plain; charset=utf-8", body.as_bytes().to_vec())
+    }
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            hex_value(bytes[i + 1]).and_then(|h| hex_value(bytes[i + 2]).map(|l| h << 4 | l))
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            },
+            None => {
+                out.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn bad_request(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Reads a line of at most MAX_LINE_BYTES into `line`, returning its length.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE_BYTES as u64).read_line(line)?;
+    if read == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err(bad_request("line too long"));
+    }
+    Ok(read)
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let mut line = String::new();
+    read_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(|| bad_request("missing method"))?.to_string();
+    let path = parts.next().ok_or_else(|| bad_request("missing path"))?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if read_line(reader, &mut line)? == 0 {
+            return Err(bad_request("unexpected end of headers"));
+        }
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADER_LINES {
+            return Err(bad_request("too many headers"));
+        }
+        if let Some(colon) = trimmed.find(':') {
+            headers.push((trimmed[..colon].trim().to_string(), trimmed[colon + 1..].trim().to_string()));
+        }
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+
+    let length = match request.header("content-length") {
+        Some(v) => v.parse::<usize>().map_err(|_| bad_request("invalid content-length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_BYTES {
+        return Err(bad_request("body too large"));
+    }
+    reader.take(length as u64).read_to_end(&mut request.body)?;
+    Ok(request)
+}
+
+pub fn write_response<W: Write>(w: &mut W, response: Response) -> io::Result<()> {
+    write!(w, "HTTP/1.1 {} {}\r\n", response.status, reason(response.status))?;
+    write!(w, "Content-Type: {}\r\n", response.content_type)?;
+    match response.stream {
+        Some(_) => write!(w, "Cache-Control: no-cache\r\n")?,
+        None => write!(w, "Content-Length: {}\r\n", response.body.len())?,
+    }
+    write!(w, "Connection: close\r\n\r\n")?;
+    w.write_all(&response.body)?;
+    w.flush()?;
+
+    for chunk in response.stream.into_iter().flatten() {
+        w.write_all(&chunk)?;
+        w.flush()?;
+    }
+    Ok(())
+}
+
+fn handle_connection<F>(stream: TcpStream, handler: &F) -> io::Result<()>
+    where F: Fn(&Request) -> Response
+{
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok(request) => handler(&request),
+        Err(e) => Response::text(400, &e.to_string()),
+    };
+    let mut stream = stream;
+    write_response(&mut stream, response)
+}
+
+pub fn serve<A, F>(addr: A, handler: F) -> io::Result<()>
+    where A: ToSocketAddrs,
+          F: Fn(&Request) -> Response + Send + Sync + 'static
+{
+    let listener = TcpListener::bind(addr)?;
+    let handler = Arc::new(handler);
+    let open = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            open.fetch_sub(1, Ordering::SeqCst);
+            let _ = stream.set_write_timeout(Some(TIMEOUT))
+                .and_then(|_| write_response(&mut stream, Response::text(503, "too many connections")));
+            continue;
+        }
+        let handler = handler.clone();
+        let open = open.clone();
+        thread::spawn(move || {
+            let _ = handle_connection(stream, &*handler);
+            open.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+// POSTs `body` to `path` on `addr` and returns the response status and body.
+pub fn post<A: ToSocketAddrs>(addr: A, path: &str, content_type: &str, body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(stream, "POST {} HTTP/1.1\r\n", path)?;
+    write!(stream, "Host: localhost\r\n")?;
+    write!(stream, "Content-Type: {}\r\n", content_type)?;
+    write!(stream, "Content-Length: {}\r\n", body.len())?;
+    write!(stream, "Connection: close\r\n\r\n")?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    let status = line.split_whitespace().nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| bad_request("invalid status line"))?;
+
+    let mut length = None;
+    loop {
+        line.clear();
+        if read_line(&mut reader, &mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some(colon) = line.find(':') {
+            if line[..colon].trim().eq_ignore_ascii_case("content-length") {
+                length = line[colon + 1..].trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut response = Vec::new();
+    match length {
+        Some(length) if length <= MAX_BODY_BYTES => {
+            reader.take(length as u64).read_to_end(&mut response)?;
+        },
+        Some(_) => return Err(bad_request("body too large")),
+        None => {
+            reader.take(MAX_BODY_BYTES as u64).read_to_end(&mut response)?;
+        },
+    }
+    Ok((status, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn requests_are_bounded() {
+        let request = b"POST /a?b=c%20d HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi";
+        let request = read_request(&mut Cursor::new(&request[..])).unwrap();
+        assert_eq!(request.route(), "/a");
+        assert_eq!(request.query("b").unwrap(), "c d");
+        assert_eq!(request.body, b"hi");
+
+        // A line without an end is cut off rather than read into memory.
+        let endless = vec![b'a'; MAX_LINE_BYTES * 2];
+        let e = read_request(&mut Cursor::new(endless)).err().unwrap();
+        assert_eq!(e.to_string(), "line too long");
+    }
+}
@@ -1,3 +1,5 @@
 pub mod hash;
 pub mod hex;
+pub mod http;
+pub mod siphash;
 pub mod time;
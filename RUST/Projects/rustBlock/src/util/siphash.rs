@@ -0,0 +1,94 @@
+// SipHash-2-4, as used by BIP158 to map filter items into a range.
+
+pub struct SipHasher {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+}
+
+impl SipHasher {
+    pub fn new(k0: u64, k1: u64) -> SipHasher {
+        SipHasher {
+            v0: k0 ^ 0x736f6d6570736575,
+            v1: k1 ^ 0x646f72616e646f6d,
+            v2: k0 ^ 0x6c7967656e657261,
+            v3: k1 ^ 0x7465646279746573,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13);
+        self.v1 ^= self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16);
+        self.v3 ^= self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21);
+        self.v3 ^= self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17);
+        self.v1 ^= self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, m: u64) {
+        self.v3 ^= m;
+        self.round();
+        self.round();
+        self.v0 ^= m;
+    }
+
+    pub fn hash(mut self, data: &[u8]) -> u64 {
+        let mut chunks = data.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            self.compress(u64::from_le_bytes(word));
+        }
+
+        let mut last = [0u8; 8];
+        let tail = chunks.remainder();
+        last[..tail.len()].copy_from_slice(tail);
+        last[7] = data.len() as u8;
+        self.compress(u64::from_le_bytes(last));
+
+        self.v2 ^= 0xff;
+        for _ in 0..4 {
+            self.round();
+        }
+        self.v0 ^ self.v1 ^ self.v2 ^ self.v3
+    }
+}
+
+pub fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    SipHasher::new(k0, k1).hash(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The reference vectors of the SipHash paper: key 00 01 .. 0f and
+    // messages 00 01 .. of each length.
+    #[test]
+    fn reference_vectors() {
+        let vectors = [
+            (0, 0x726fdb47dd0e0e31),
+            (1, 0x74f839c593dc67fd),
+            (2, 0x0d6c8009d9a94f5a),
+            (3, 0x85676696d7fb7e2d),
+            (7, 0xab0200f58b01d137),
+            (8, 0x93f5f5799a932462),
+            (15, 0xa129ca6149be45e5),
+            (63, 0x958a324ceb064572),
+        ];
+        let message: Vec<u8> = (0..64).collect();
+        for &(len, expected) in &vectors {
+            let hash = siphash24(0x0706050403020100, 0x0f0e0d0c0b0a0908, &message[..len]);
+            assert_eq!(hash, expected, "length {}", len);
+        }
+    }
+}
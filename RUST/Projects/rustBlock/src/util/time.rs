@@ -1,3 +1,5 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn now() -> u64 {
@@ -5,3 +7,49 @@ pub fn now() -> u64 {
     let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
     since_epoch.as_secs()
 }
+
+// Where the current time, in seconds since the epoch, comes from.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        now()
+    }
+}
+
+// A clock that only moves when told to. It counts milliseconds so that
+// network latencies can be simulated; clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+    millis: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    pub fn new(millis: u64) -> VirtualClock {
+        VirtualClock {
+            millis: Arc::new(AtomicU64::new(millis)),
+        }
+    }
+
+    pub fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+
+    pub fn set_millis(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+
+    pub fn advance_millis(&self, millis: u64) {
+        self.millis.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u64 {
+        self.now_millis() / 1000
+    }
+}